@@ -0,0 +1,20 @@
+use std::{env::var, sync::LazyLock};
+
+/// Other services which hold content of users, as (name, export URL) pairs from
+/// `DATA_EXPORT_SOURCES=posts=http://post_service:8001/internal/export,...`.
+/// Each one is called with `?user_id=` and must answer with JSON
+pub static DATA_EXPORT_SOURCES: LazyLock<Vec<(String, String)>> = LazyLock::new(|| {
+    var("DATA_EXPORT_SOURCES")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|source| !source.is_empty())
+        .map(|source| {
+            let (name, url) = source
+                .split_once('=')
+                .unwrap_or_else(|| panic!("DATA_EXPORT_SOURCES entry {} must be name=url", source));
+
+            (name.trim().to_owned(), url.trim().to_owned())
+        })
+        .collect()
+});
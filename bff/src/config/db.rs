@@ -1,3 +1,4 @@
+use crate::utils::db_util::ensure_indexes;
 use mongodb::error::Error;
 use mongodb::{
     Client, Database,
@@ -5,7 +6,6 @@ use mongodb::{
     options::{ClientOptions, ServerApi, ServerApiVersion},
 };
 use std::env;
-use crate::utils::db_util::ensure_indexes;
 
 pub async fn connect_db() -> Result<Database, Error> {
     let uri = env::var("MONGO_URI").expect("MONGO_URI is not set in env");
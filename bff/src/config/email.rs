@@ -0,0 +1,50 @@
+use std::{env::var, sync::LazyLock};
+
+/// Values every email needs, read once at startup so a missing one stops
+/// the server instead of the first request sending an email
+pub struct EmailConfig {
+    pub app_name: String,
+    /// Public URL of this API, for links handled by the BFF
+    pub domain: String,
+    /// URL of the web app, for links handled by the frontend
+    pub frontend_url: String,
+    pub support_email: String,
+    pub company_address: String,
+    pub sender_name: String,
+    pub sender_email: String,
+    pub brevo_api_key: String,
+}
+
+pub static EMAIL_CONFIG: LazyLock<EmailConfig> = LazyLock::new(|| EmailConfig {
+    app_name: required("APP_NAME"),
+    domain: required("DOMAIN"),
+    frontend_url: required("FRONTEND_URL"),
+    support_email: required("SUPPORT_EMAIL"),
+    company_address: required("COMPANY_ADDRESS"),
+    sender_name: required("BREVO_SENDER_NAME"),
+    sender_email: required("BREVO_SENDER_EMAIL"),
+    brevo_api_key: required("BREVO_API_KEY"),
+});
+
+/// Lifetime of a link as shown in emails. A lifetime which isn't a whole
+/// number of minutes fails to compile rather than be shown rounded down
+pub const fn whole_minutes(secs: u64) -> u64 {
+    assert!(
+        secs.is_multiple_of(60),
+        "link lifetime must be whole minutes"
+    );
+    secs / 60
+}
+
+/// Like `whole_minutes`, for lifetimes shown in hours
+pub const fn whole_hours(secs: u64) -> u64 {
+    assert!(
+        secs.is_multiple_of(3600),
+        "link lifetime must be whole hours"
+    );
+    secs / 3600
+}
+
+fn required(name: &str) -> String {
+    var(name).unwrap_or_else(|_| panic!("{} missing", name))
+}
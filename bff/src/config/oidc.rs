@@ -0,0 +1,79 @@
+use jsonwebtoken::Algorithm;
+use std::{collections::HashMap, env::var, str::FromStr, sync::LazyLock};
+
+/// An OpenID Connect provider users can log in with
+#[derive(Debug, Clone)]
+pub struct OidcProvider {
+    pub discovery_url: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub scopes: String,
+    /// e.g. `form_post`, which Apple requires when asking for the email
+    pub response_mode: Option<String>,
+    /// Algorithms ID tokens may be signed with, when the JWK doesn't name one
+    pub algorithms: Vec<Algorithm>,
+}
+
+fn default_discovery_url(name: &str) -> Option<&'static str> {
+    match name {
+        "google" => Some("https://accounts.google.com/.well-known/openid-configuration"),
+        "apple" => Some("https://appleid.apple.com/.well-known/openid-configuration"),
+        _ => None,
+    }
+}
+
+/// Asymmetric algorithms only, a symmetric one would let anyone knowing the
+/// client secret forge ID tokens
+fn parse_algorithms(prefix: &str, value: &str) -> Vec<Algorithm> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|alg| !alg.is_empty())
+        .map(|alg| match Algorithm::from_str(alg) {
+            Ok(Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) | Err(_) => {
+                panic!(
+                    "{}_ALGORITHMS entry {} is not an asymmetric JWS algorithm",
+                    prefix, alg
+                )
+            }
+            Ok(alg) => alg,
+        })
+        .collect()
+}
+
+/// Providers listed in `OIDC_PROVIDERS=google,apple,...`, each configured with
+/// `OIDC_<NAME>_CLIENT_ID`, `OIDC_<NAME>_CLIENT_SECRET`, `OIDC_<NAME>_DISCOVERY_URL`
+/// (optional for Google and Apple), `OIDC_<NAME>_SCOPES`, `OIDC_<NAME>_RESPONSE_MODE`
+/// and `OIDC_<NAME>_ALGORITHMS=RS256,...` (RS256 by default)
+pub static OIDC_PROVIDERS: LazyLock<HashMap<String, OidcProvider>> = LazyLock::new(|| {
+    var("OIDC_PROVIDERS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let prefix = format!("OIDC_{}", name.to_uppercase());
+
+            let discovery_url = var(format!("{}_DISCOVERY_URL", prefix))
+                .ok()
+                .or_else(|| default_discovery_url(&name).map(str::to_owned))
+                .unwrap_or_else(|| panic!("{}_DISCOVERY_URL missing", prefix));
+
+            let provider = OidcProvider {
+                discovery_url,
+                client_id: var(format!("{}_CLIENT_ID", prefix))
+                    .unwrap_or_else(|_| panic!("{}_CLIENT_ID missing", prefix)),
+                client_secret: var(format!("{}_CLIENT_SECRET", prefix)).ok(),
+                scopes: var(format!("{}_SCOPES", prefix))
+                    .unwrap_or_else(|_| "openid email".to_owned()),
+                response_mode: var(format!("{}_RESPONSE_MODE", prefix)).ok(),
+                algorithms: parse_algorithms(
+                    &prefix,
+                    &var(format!("{}_ALGORITHMS", prefix)).unwrap_or_else(|_| "RS256".to_owned()),
+                ),
+            };
+
+            (name, provider)
+        })
+        .collect()
+});
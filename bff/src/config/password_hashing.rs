@@ -0,0 +1,18 @@
+use crate::utils::env::parse_var;
+use argon2::Params;
+use std::sync::LazyLock;
+
+/// Argon2id cost parameters for new password hashes. Stored hashes created
+/// with weaker parameters are upgraded on the next successful login.
+///
+/// Configured with `ARGON2_MEMORY_KIB`, `ARGON2_ITERATIONS` and
+/// `ARGON2_PARALLELISM`, defaulting to the argon2 crate defaults
+pub static ARGON2_PARAMS: LazyLock<Params> = LazyLock::new(|| {
+    Params::new(
+        parse_var("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST),
+        parse_var("ARGON2_ITERATIONS", Params::DEFAULT_T_COST),
+        parse_var("ARGON2_PARALLELISM", Params::DEFAULT_P_COST),
+        None,
+    )
+    .unwrap_or_else(|e| panic!("Invalid Argon2 parameters: {}", e))
+});
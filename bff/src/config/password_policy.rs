@@ -0,0 +1,40 @@
+use crate::utils::env::parse_var;
+use std::{env::var, sync::LazyLock};
+
+/// Rules new passwords are checked against
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// Minimum estimated strength, in bits of entropy
+    pub min_entropy_bits: f64,
+    /// Lowercase words a password must not contain, on top of the username
+    /// and the local part of the email
+    pub banned_words: Vec<String>,
+    /// Directory of Pwned Passwords range files, see `BreachedRanges`
+    pub breached_ranges_dir: Option<String>,
+}
+
+/// Configured with `PASSWORD_MIN_LENGTH`, `PASSWORD_MAX_LENGTH`,
+/// `PASSWORD_MIN_ENTROPY_BITS`, `PASSWORD_BANNED_WORDS=word,...` and
+/// `PASSWORD_BREACHED_RANGES_DIR`
+pub static PASSWORD_POLICY: LazyLock<PasswordPolicy> = LazyLock::new(|| {
+    let mut banned_words: Vec<String> = var("PASSWORD_BANNED_WORDS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|word| word.trim().to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect();
+
+    if let Ok(app_name) = var("APP_NAME") {
+        banned_words.push(app_name.trim().to_lowercase());
+    }
+
+    PasswordPolicy {
+        min_length: parse_var("PASSWORD_MIN_LENGTH", 8),
+        max_length: parse_var("PASSWORD_MAX_LENGTH", 128),
+        min_entropy_bits: parse_var("PASSWORD_MIN_ENTROPY_BITS", 36.0),
+        banned_words,
+        breached_ranges_dir: var("PASSWORD_BREACHED_RANGES_DIR").ok(),
+    }
+});
@@ -0,0 +1,126 @@
+use std::{collections::HashMap, env::var, sync::LazyLock};
+
+/// Limits of one route, counted in fixed windows
+#[derive(Debug, Clone, Copy)]
+pub struct RouteLimit {
+    /// Max requests per client IP
+    pub per_ip: u64,
+    /// Max requests per target identifier (email in the body), if limited
+    pub per_identifier: Option<u64>,
+    pub window_secs: u64,
+}
+
+impl RouteLimit {
+    const fn new(per_ip: u64, per_identifier: Option<u64>, window_secs: u64) -> Self {
+        Self {
+            per_ip,
+            per_identifier,
+            window_secs,
+        }
+    }
+
+    /// Parse `<per_ip>,<per_identifier>,<window_secs>`, 0 per identifier means unlimited
+    fn parse(value: &str) -> Option<Self> {
+        let parts: Vec<u64> = value
+            .split(',')
+            .map(|v| v.trim().parse().ok())
+            .collect::<Option<_>>()?;
+
+        match parts.as_slice() {
+            [per_ip, per_identifier, window_secs] if *window_secs > 0 => Some(Self::new(
+                *per_ip,
+                (*per_identifier > 0).then_some(*per_identifier),
+                *window_secs,
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Used for `/auth` routes without their own entry
+pub const DEFAULT_ROUTE_LIMIT: RouteLimit = RouteLimit::new(60, None, 60);
+
+const ROUTE_LIMIT_DEFAULTS: [(&str, RouteLimit); 15] = [
+    ("/auth/login", RouteLimit::new(20, Some(10), 300)),
+    ("/auth/register", RouteLimit::new(10, Some(3), 3600)),
+    (
+        "/auth/send_reset_pass_link",
+        RouteLimit::new(10, Some(3), 3600),
+    ),
+    ("/auth/reset_password", RouteLimit::new(10, None, 900)),
+    ("/auth/magic_link", RouteLimit::new(10, Some(3), 3600)),
+    ("/auth/magic_link/login", RouteLimit::new(10, None, 900)),
+    ("/auth/refresh", RouteLimit::new(60, None, 60)),
+    ("/auth/resend_verification", RouteLimit::new(10, None, 3600)),
+    ("/auth/verify_email", RouteLimit::new(30, None, 300)),
+    ("/auth/confirm_email_change", RouteLimit::new(30, None, 300)),
+    ("/auth/2fa/verify", RouteLimit::new(20, None, 300)),
+    ("/auth/passkey/login/finish", RouteLimit::new(20, None, 300)),
+    ("/auth/oidc/callback", RouteLimit::new(20, None, 300)),
+    ("/auth/username_available", RouteLimit::new(30, None, 60)),
+    ("/auth/change_username", RouteLimit::new(5, None, 3600)),
+];
+
+/// Per route limits, each overridable with an env var named after the route,
+/// e.g. `RATE_LIMIT_AUTH_LOGIN=20,10,300`
+pub static ROUTE_LIMITS: LazyLock<HashMap<&'static str, RouteLimit>> = LazyLock::new(|| {
+    ROUTE_LIMIT_DEFAULTS
+        .iter()
+        .map(|(route, default)| {
+            let name = format!(
+                "RATE_LIMIT_{}",
+                route
+                    .trim_start_matches('/')
+                    .replace('/', "_")
+                    .to_uppercase()
+            );
+
+            let limit = match var(&name) {
+                Ok(value) => RouteLimit::parse(&value).unwrap_or_else(|| {
+                    panic!("{} must be <per_ip>,<per_identifier>,<window_secs>", name)
+                }),
+                Err(_) => *default,
+            };
+
+            (*route, limit)
+        })
+        .collect()
+});
+
+pub enum RateLimitBackend {
+    /// Counters live in the process, only correct for a single instance
+    Memory,
+    /// Counters are shared through MongoDB between every BFF replica
+    Mongo,
+}
+
+pub fn rate_limit_backend() -> RateLimitBackend {
+    match var("RATE_LIMIT_BACKEND").as_deref() {
+        Ok("mongo") => RateLimitBackend::Mongo,
+        Ok("memory") | Err(_) => RateLimitBackend::Memory,
+        Ok(other) => panic!("RATE_LIMIT_BACKEND must be memory or mongo, got {}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_limit_overrides() {
+        let limit = RouteLimit::parse("20, 10, 300").unwrap();
+
+        assert_eq!(
+            (limit.per_ip, limit.per_identifier, limit.window_secs),
+            (20, Some(10), 300)
+        );
+        assert_eq!(RouteLimit::parse("20,0,300").unwrap().per_identifier, None);
+    }
+
+    #[test]
+    fn malformed_route_limits_are_rejected() {
+        for value in ["", "20,10", "20,10,300,1", "20,10,0", "20,-1,300", "a,b,c"] {
+            assert!(RouteLimit::parse(value).is_none(), "{}", value);
+        }
+    }
+}
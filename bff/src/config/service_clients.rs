@@ -0,0 +1,20 @@
+use std::{collections::HashMap, env::var, sync::LazyLock};
+
+/// Internal services allowed to call the `/internal` routes, from
+/// `SERVICE_CLIENTS=post_service:<hex sha256 of the secret>,...`.
+/// Only hashes are configured here, the services keep the secrets
+pub static SERVICE_CLIENTS: LazyLock<HashMap<String, String>> = LazyLock::new(|| {
+    var("SERVICE_CLIENTS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|client| !client.is_empty())
+        .map(|client| {
+            let (id, secret_hash) = client
+                .split_once(':')
+                .unwrap_or_else(|| panic!("SERVICE_CLIENTS entry {} must be id:sha256", client));
+
+            (id.trim().to_owned(), secret_hash.trim().to_lowercase())
+        })
+        .collect()
+});
@@ -0,0 +1,20 @@
+use ipnet::IpNet;
+use std::{env::var, net::IpAddr, sync::LazyLock};
+
+/// Reverse proxies whose `X-Forwarded-For` and `X-Real-IP` headers are trusted,
+/// from `TRUSTED_PROXIES=10.0.0.0/8,192.168.1.10,...`.
+/// Without any, the client IP is the address of the TCP peer
+pub static TRUSTED_PROXIES: LazyLock<Vec<IpNet>> = LazyLock::new(|| {
+    var("TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|proxy| !proxy.is_empty())
+        .map(|proxy| {
+            proxy
+                .parse::<IpNet>()
+                .or_else(|_| proxy.parse::<IpAddr>().map(IpNet::from))
+                .unwrap_or_else(|_| panic!("TRUSTED_PROXIES entry {} must be an IP or CIDR", proxy))
+        })
+        .collect()
+});
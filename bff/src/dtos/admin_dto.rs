@@ -0,0 +1,76 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dtos::session_dto::SessionResDto,
+    models::user::User,
+    types::role::{Permission, Role},
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserRolesDto {
+    pub roles: Vec<Role>,
+    /// Permissions granted on top of the ones of the roles
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserSearchQuery {
+    /// Part of the email or username to look for
+    pub q: Option<String>,
+    pub page: Option<u64>,
+    pub limit: Option<u64>,
+    /// Also list accounts which were deleted and wait to be purged
+    #[serde(default)]
+    pub include_deleted: bool,
+}
+
+/// A user as seen by operators, without any secret
+#[derive(Debug, Serialize)]
+pub struct AdminUserResDto {
+    pub id: String,
+    pub username: String,
+    pub email: String,
+    pub is_email_verified: bool,
+    pub is_totp_enabled: bool,
+    pub is_disabled: bool,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub roles: Vec<Role>,
+    pub permissions: Vec<Permission>,
+    pub last_login_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<User> for AdminUserResDto {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id.to_hex(),
+            username: user.username,
+            email: user.email,
+            is_email_verified: user.isEmailVerified,
+            is_totp_enabled: user.isTotpEnabled,
+            is_disabled: user.isDisabled,
+            deleted_at: user.deletedAt,
+            roles: user.roles,
+            permissions: user.permissions,
+            last_login_at: user.lastLoginAt,
+            created_at: user.createdAt,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserPageResDto {
+    pub users: Vec<AdminUserResDto>,
+    pub page: u64,
+    pub limit: u64,
+    pub total: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AdminUserDetailResDto {
+    #[serde(flatten)]
+    pub user: AdminUserResDto,
+    pub sessions: Vec<SessionResDto>,
+}
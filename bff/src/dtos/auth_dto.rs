@@ -17,7 +17,12 @@ pub struct LoginReqDto {
 pub struct AuthResDto {
     pub access_token: String,
     pub token_type: String,
-    pub refresh_token: String,
+    /// Left out when the refresh token is sent as an HttpOnly cookie
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    /// Token to echo in the `X-CSRF-Token` header when the refresh token is a cookie
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub csrf_token: Option<String>,
 }
 
 impl AuthResDto {
@@ -25,7 +30,31 @@ impl AuthResDto {
         Self {
             access_token,
             token_type: "Bearer".to_string(),
-            refresh_token,
+            refresh_token: Some(refresh_token),
+            csrf_token: None,
+        }
+    }
+}
+
+/// Response of login, either the tokens or a pending second factor
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum LoginResDto {
+    Tokens(AuthResDto),
+    MfaPending(MfaPendingResDto),
+}
+
+#[derive(Debug, Serialize)]
+pub struct MfaPendingResDto {
+    pub mfa_required: bool,
+    pub mfa_token: String,
+}
+
+impl MfaPendingResDto {
+    pub fn new(mfa_token: String) -> Self {
+        Self {
+            mfa_required: true,
+            mfa_token,
         }
     }
 }
@@ -45,3 +74,39 @@ pub struct VerifyEmailDto {
 pub struct ReqResetPassLinkDto {
     pub email: String,
 }
+
+#[derive(Debug, Deserialize)]
+pub struct ReqMagicLinkDto {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MagicLinkLoginDto {
+    pub token: String,
+    pub user_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPassDto {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePassDto {
+    pub current_password: String,
+    pub new_password: String,
+    #[serde(default)]
+    pub revoke_other_sessions: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccountDto {
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangeEmailDto {
+    pub new_email: String,
+    pub password: String,
+}
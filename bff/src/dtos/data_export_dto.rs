@@ -0,0 +1,153 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{
+    models::{
+        email_verif_token::{EmailTokenPurpose, EmailVerifToken},
+        linked_identity::LinkedIdentity,
+        refresh_token::RefreshToken,
+        security_event::{SecurityEvent, SecurityEventKind},
+        user::User,
+        webauthn_credential::WebauthnCredential,
+    },
+    types::role::{Permission, Role},
+};
+
+/// The account as stored, without the password hash and 2FA secrets
+#[derive(Debug, Serialize)]
+pub struct ExportUserDto {
+    pub id: String,
+    pub username: String,
+    pub email: String,
+    pub is_email_verified: bool,
+    pub is_totp_enabled: bool,
+    pub is_disabled: bool,
+    pub roles: Vec<Role>,
+    pub permissions: Vec<Permission>,
+    pub last_login_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<User> for ExportUserDto {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id.to_hex(),
+            username: user.username,
+            email: user.email,
+            is_email_verified: user.isEmailVerified,
+            is_totp_enabled: user.isTotpEnabled,
+            is_disabled: user.isDisabled,
+            roles: user.roles,
+            permissions: user.permissions,
+            last_login_at: user.lastLoginAt,
+            created_at: user.createdAt,
+            updated_at: user.updatedAt,
+        }
+    }
+}
+
+/// A refresh token without the token id itself
+#[derive(Debug, Serialize)]
+pub struct ExportSessionDto {
+    pub session_id: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub is_revoked: bool,
+    pub session_started_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+impl From<RefreshToken> for ExportSessionDto {
+    fn from(token: RefreshToken) -> Self {
+        Self {
+            session_id: token.familyId,
+            user_agent: token.userAgent,
+            ip: token.ip,
+            is_revoked: token.isRevoked,
+            session_started_at: token.sessionStartedAt,
+            created_at: token.createdAt,
+            expires_at: token.expiresAt,
+            used_at: token.usedAt,
+        }
+    }
+}
+
+/// An emailed link (verification, email change, login) without the token hash
+#[derive(Debug, Serialize)]
+pub struct ExportEmailTokenDto {
+    pub purpose: EmailTokenPurpose,
+    pub new_email: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+impl From<EmailVerifToken> for ExportEmailTokenDto {
+    fn from(token: EmailVerifToken) -> Self {
+        Self {
+            purpose: token.purpose,
+            new_email: token.newEmail,
+            created_at: token.createdAt,
+            expires_at: token.expiresAt,
+            used_at: token.usedAt,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportSecurityEventDto {
+    pub kind: SecurityEventKind,
+    pub detail: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<SecurityEvent> for ExportSecurityEventDto {
+    fn from(event: SecurityEvent) -> Self {
+        Self {
+            kind: event.kind,
+            detail: event.detail,
+            created_at: event.createdAt,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportLinkedIdentityDto {
+    pub provider: String,
+    pub subject: String,
+    pub email: String,
+    pub created_at: DateTime<Utc>,
+    pub last_login_at: DateTime<Utc>,
+}
+
+impl From<LinkedIdentity> for ExportLinkedIdentityDto {
+    fn from(identity: LinkedIdentity) -> Self {
+        Self {
+            provider: identity.provider,
+            subject: identity.subject,
+            email: identity.email,
+            created_at: identity.createdAt,
+            last_login_at: identity.lastLoginAt,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportPasskeyDto {
+    pub credential_id: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl From<WebauthnCredential> for ExportPasskeyDto {
+    fn from(credential: WebauthnCredential) -> Self {
+        Self {
+            credential_id: credential.credentialId,
+            created_at: credential.createdAt,
+            last_used_at: credential.lastUsedAt,
+        }
+    }
+}
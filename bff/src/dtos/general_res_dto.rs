@@ -2,5 +2,5 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, Deserialize, Serialize)]
 pub struct GeneralResDto {
     pub status_code: u16,
-    pub message: String
-}
\ No newline at end of file
+    pub message: String,
+}
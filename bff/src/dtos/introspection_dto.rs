@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+use crate::types::role::{Permission, Role};
+
+/// RFC 7662 introspection request, sent form encoded
+#[derive(Debug, Deserialize)]
+pub struct IntrospectReqDto {
+    pub token: String,
+    /// `access_token` or `refresh_token`, only decides which type is tried first.
+    /// Refresh tokens are tried first without a hint
+    pub token_type_hint: Option<String>,
+}
+
+/// RFC 7662 introspection response. Inactive tokens only report `active: false`
+#[derive(Debug, Default, Serialize)]
+pub struct IntrospectResDto {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    /// Session (refresh token family) of the token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roles: Option<Vec<Role>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub perms: Option<Vec<Permission>>,
+}
+
+impl IntrospectResDto {
+    pub fn inactive() -> Self {
+        Self::default()
+    }
+}
@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize)]
+pub struct TotpEnrollResDto {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpCodeDto {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResDto {
+    pub recovery_codes: Vec<String>,
+}
+
+/// Second step of login, `code` is either a TOTP code or a recovery code
+#[derive(Debug, Deserialize)]
+pub struct TotpLoginDto {
+    pub mfa_token: String,
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DisableTotpDto {
+    pub password: String,
+    pub code: String,
+}
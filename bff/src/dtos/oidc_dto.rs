@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize)]
+pub struct OidcStartResDto {
+    pub authorization_url: String,
+}
+
+/// Parameters the provider sent back to the redirect URL
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackDto {
+    pub code: String,
+    pub state: String,
+}
@@ -0,0 +1,78 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    models::user::User,
+    types::role::{Permission, Role},
+};
+
+/// The logged in user as returned by `/me`, without the password and 2FA secrets
+#[derive(Debug, Serialize)]
+pub struct MeResDto {
+    pub id: String,
+    pub username: String,
+    pub email: String,
+    pub is_email_verified: bool,
+    pub is_totp_enabled: bool,
+    pub roles: Vec<Role>,
+    pub permissions: Vec<Permission>,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar_key: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<User> for MeResDto {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id.to_hex(),
+            username: user.username,
+            email: user.email,
+            is_email_verified: user.isEmailVerified,
+            is_totp_enabled: user.isTotpEnabled,
+            roles: user.roles,
+            permissions: user.permissions,
+            display_name: user.displayName,
+            bio: user.bio,
+            avatar_key: user.avatarKey,
+            locale: user.locale,
+            timezone: user.timezone,
+            created_at: user.createdAt,
+            updated_at: user.updatedAt,
+        }
+    }
+}
+
+/// Profile fields to change. Missing fields are left as they are,
+/// an empty string clears the field
+#[derive(Debug, Deserialize)]
+pub struct UpdateProfileDto {
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar_key: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+}
+
+impl UpdateProfileDto {
+    /// (field in the users collection, new value) of every field in the request
+    pub fn changes(&self) -> Vec<(&'static str, Option<&str>)> {
+        [
+            ("displayName", &self.display_name),
+            ("bio", &self.bio),
+            ("avatarKey", &self.avatar_key),
+            ("locale", &self.locale),
+            ("timezone", &self.timezone),
+        ]
+        .into_iter()
+        .filter_map(|(field, value)| {
+            value
+                .as_deref()
+                .map(|v| (field, Some(v).filter(|v| !v.is_empty())))
+        })
+        .collect()
+    }
+}
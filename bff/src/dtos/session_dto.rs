@@ -0,0 +1,29 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::models::refresh_token::RefreshToken;
+
+#[derive(Debug, Serialize)]
+pub struct SessionResDto {
+    pub id: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub is_current: bool,
+}
+
+impl SessionResDto {
+    pub fn new(token: RefreshToken, current_family_id: &str) -> Self {
+        Self {
+            id: token.id.to_hex(),
+            is_current: !token.familyId.is_empty() && token.familyId == current_family_id,
+            user_agent: token.userAgent,
+            ip: token.ip,
+            created_at: token.sessionStartedAt.unwrap_or(token.createdAt),
+            last_used_at: token.createdAt,
+            expires_at: token.expiresAt,
+        }
+    }
+}
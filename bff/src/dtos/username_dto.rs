@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct UsernameQuery {
+    pub username: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsernameAvailabilityResDto {
+    /// The username as it would be stored
+    pub username: String,
+    pub available: bool,
+    /// Why the username can't be used, if it's not available
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangeUsernameDto {
+    pub username: String,
+}
@@ -0,0 +1,637 @@
+use crate::{
+    AppState,
+    dtos::{
+        admin_dto::{
+            AdminUserDetailResDto, AdminUserResDto, UserPageResDto, UserRolesDto, UserSearchQuery,
+        },
+        general_res_dto::GeneralResDto,
+    },
+    handlers::session_handler::sessions_of,
+    models::{
+        security_event::{NewSecurityEvent, SecurityEventKind},
+        user::User,
+    },
+    types::{
+        authorization::{Admin, ManageUsers, ReadUsers, RequirePermission, RequireRole},
+        claims::Claims,
+        error::CustomError,
+        role::highest_rank,
+        validation::FieldError,
+    },
+};
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use chrono::Utc;
+use std::sync::Arc;
+
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const MAX_PAGE_SIZE: u64 = 100;
+
+/// Search users by part of their email or username, paginated newest first
+pub async fn search_users(
+    RequirePermission { claims, .. }: RequirePermission<ReadUsers>,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<UserSearchQuery>,
+) -> Result<Json<UserPageResDto>, CustomError> {
+    let page = query.page.unwrap_or(1).max(1);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let skip = page_offset(page, limit)?;
+
+    tracing::debug!("User {} is searching users", claims.sub);
+
+    let (users, total) = state
+        .user_service
+        .search_users(
+            query.q.as_deref(),
+            query.include_deleted,
+            skip,
+            limit as i64,
+        )
+        .await?;
+
+    Ok(Json(UserPageResDto {
+        users: users.into_iter().map(AdminUserResDto::from).collect(),
+        page,
+        limit,
+        total,
+    }))
+}
+
+/// View a user with their verification state and active sessions
+pub async fn get_user(
+    RequirePermission { claims, .. }: RequirePermission<ReadUsers>,
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<String>,
+) -> Result<Json<AdminUserDetailResDto>, CustomError> {
+    tracing::debug!("User {} is reading user {}", claims.sub, user_id);
+
+    let user = state.user_service.get_user_by_id(&user_id).await?;
+
+    let tokens = state
+        .refresh_token_service
+        .get_active_tokens_by_user(&user_id)
+        .await?;
+
+    Ok(Json(AdminUserDetailResDto {
+        user: AdminUserResDto::from(user),
+        sessions: sessions_of(tokens, ""),
+    }))
+}
+
+/// Disable an account and log it out everywhere
+pub async fn disable_user(
+    RequirePermission { claims, .. }: RequirePermission<ManageUsers>,
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<String>,
+) -> Result<Json<GeneralResDto>, CustomError> {
+    if user_id == claims.sub {
+        return Err(CustomError::Forbidden);
+    }
+
+    set_user_disabled(&state, &claims, &user_id, true).await?;
+
+    state
+        .refresh_token_service
+        .revoke_all_user_tokens(&user_id)
+        .await?;
+
+    state
+        .access_denylist_service
+        .revoke_user(&user_id, None)
+        .await?;
+
+    Ok(Json(GeneralResDto {
+        status_code: 200,
+        message: "Ok".to_owned(),
+    }))
+}
+
+pub async fn enable_user(
+    RequirePermission { claims, .. }: RequirePermission<ManageUsers>,
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<String>,
+) -> Result<Json<GeneralResDto>, CustomError> {
+    set_user_disabled(&state, &claims, &user_id, false).await?;
+
+    Ok(Json(GeneralResDto {
+        status_code: 200,
+        message: "Ok".to_owned(),
+    }))
+}
+
+/// Mark the email of a user as verified without the link
+pub async fn force_verify_email(
+    RequirePermission { claims, .. }: RequirePermission<ManageUsers>,
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<String>,
+) -> Result<Json<GeneralResDto>, CustomError> {
+    user_below_operator(&state, &claims, &user_id).await?;
+
+    state.user_service.update_email_verified(&user_id).await?;
+
+    tracing::info!("User {} has verified the email of {}", claims.sub, user_id);
+
+    Ok(Json(GeneralResDto {
+        status_code: 200,
+        message: "Ok".to_owned(),
+    }))
+}
+
+/// Revoke every refresh and access token of a user
+pub async fn force_logout(
+    RequirePermission { claims, .. }: RequirePermission<ManageUsers>,
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<String>,
+) -> Result<Json<GeneralResDto>, CustomError> {
+    // also makes sure the user exists, revoking would silently do nothing otherwise
+    user_below_operator(&state, &claims, &user_id).await?;
+
+    let revoked = state
+        .refresh_token_service
+        .revoke_all_user_tokens(&user_id)
+        .await?;
+
+    state
+        .access_denylist_service
+        .revoke_user(&user_id, None)
+        .await?;
+
+    tracing::info!(
+        "User {} has logged {} out of {} sessions",
+        claims.sub,
+        user_id,
+        revoked
+    );
+
+    Ok(Json(GeneralResDto {
+        status_code: 200,
+        message: "Ok".to_owned(),
+    }))
+}
+
+/// Get the roles and directly granted permissions of a user
+pub async fn get_user_roles(
+    RequirePermission { claims, .. }: RequirePermission<ReadUsers>,
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<String>,
+) -> Result<Json<UserRolesDto>, CustomError> {
+    tracing::debug!("User {} is reading the roles of {}", claims.sub, user_id);
+
+    let user = state.user_service.get_user_by_id(&user_id).await?;
+
+    Ok(Json(UserRolesDto {
+        roles: user.roles,
+        permissions: user.permissions,
+    }))
+}
+
+/// Replace the roles and permissions of a user, only admins may do so and only with roles
+/// ranking below their own and permissions they hold themselves. The access tokens of the user are revoked so the next refresh
+/// picks up the new roles
+pub async fn update_user_roles(
+    RequireRole { claims, .. }: RequireRole<Admin>,
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<String>,
+    Json(payload): Json<UserRolesDto>,
+) -> Result<Json<GeneralResDto>, CustomError> {
+    user_below_operator(&state, &claims, &user_id).await?;
+
+    if highest_rank(&payload.roles) >= highest_rank(&claims.roles) {
+        tracing::warn!(
+            "User {} tried to grant {:?} which isn't below them",
+            claims.sub,
+            payload.roles
+        );
+        return Err(CustomError::Forbidden);
+    }
+
+    if let Some(permission) = payload
+        .permissions
+        .iter()
+        .find(|permission| !claims.perms.contains(permission))
+    {
+        tracing::warn!(
+            "User {} tried to grant {:?} which they don't hold",
+            claims.sub,
+            permission
+        );
+        return Err(CustomError::Forbidden);
+    }
+
+    state
+        .user_service
+        .update_roles(&user_id, &payload.roles, &payload.permissions)
+        .await?;
+
+    tracing::info!(
+        "User {} has set the roles of {} to {:?}",
+        claims.sub,
+        user_id,
+        payload.roles
+    );
+
+    // access tokens carry the roles, the ones already issued must not outlive the change
+    state
+        .access_denylist_service
+        .revoke_user(&user_id, None)
+        .await?;
+
+    Ok(Json(GeneralResDto {
+        status_code: 200,
+        message: "Ok".to_owned(),
+    }))
+}
+
+/// How many users come before `page`, rejecting pages too far out to count
+fn page_offset(page: u64, limit: u64) -> Result<u64, CustomError> {
+    (page - 1)
+        .checked_mul(limit)
+        .filter(|skip| i64::try_from(*skip).is_ok())
+        .ok_or_else(|| {
+            CustomError::ValidationError(vec![FieldError::new("page", "is out of range")])
+        })
+}
+
+/// Get the user an operator is about to change, only users ranking below the
+/// operator can be changed
+async fn user_below_operator(
+    state: &AppState,
+    operator: &Claims,
+    user_id: &str,
+) -> Result<User, CustomError> {
+    let user = state.user_service.get_user_by_id(user_id).await?;
+
+    if highest_rank(&user.roles) >= highest_rank(&operator.roles) {
+        tracing::warn!(
+            "User {} tried to change the account of {} who isn't below them",
+            operator.sub,
+            user_id
+        );
+        return Err(CustomError::Forbidden);
+    }
+
+    Ok(user)
+}
+
+/// Flip the disabled flag and keep a trace of who did it
+async fn set_user_disabled(
+    state: &AppState,
+    operator: &Claims,
+    user_id: &str,
+    disabled: bool,
+) -> Result<(), CustomError> {
+    let user = user_below_operator(state, operator, user_id).await?;
+
+    state.user_service.set_disabled(user_id, disabled).await?;
+
+    let (kind, action) = if disabled {
+        (SecurityEventKind::AccountDisabled, "disabled")
+    } else {
+        (SecurityEventKind::AccountEnabled, "enabled")
+    };
+
+    state
+        .security_event_service
+        .record_event(&NewSecurityEvent {
+            userId: user.id,
+            kind,
+            detail: format!("Account {} by {}", action, operator.sub),
+            createdAt: Utc::now(),
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_support::{claims_of, insert_unverified_user, insert_user, start_session, test_state},
+        types::role::{Permission, Role, effective_permissions},
+    };
+
+    async fn insert_user_with_role(state: &AppState, username: &str, role: Role) -> User {
+        let user = insert_user(state, username, "Correct-Horse-9").await;
+
+        state
+            .user_service
+            .update_roles(&user.id.to_hex(), &[role], &[])
+            .await
+            .unwrap();
+
+        user
+    }
+
+    fn operator(user: &User, role: Role) -> Claims {
+        Claims {
+            roles: vec![role],
+            perms: effective_permissions(&[role], &[]),
+            ..claims_of(&user.id.to_hex(), "family-a")
+        }
+    }
+
+    /// Moderator who was also granted `ManageUsers` directly
+    fn managing_moderator(user: &User) -> Claims {
+        Claims {
+            perms: effective_permissions(&[Role::Moderator], &[Permission::ManageUsers]),
+            ..operator(user, Role::Moderator)
+        }
+    }
+
+    #[test]
+    fn page_offset_skips_the_previous_pages() {
+        assert_eq!(page_offset(1, 20).unwrap(), 0);
+        assert_eq!(page_offset(3, 20).unwrap(), 40);
+    }
+
+    #[test]
+    fn page_offset_rejects_overflowing_pages() {
+        assert!(matches!(
+            page_offset(u64::MAX, MAX_PAGE_SIZE),
+            Err(CustomError::ValidationError(_))
+        ));
+        assert!(matches!(
+            page_offset(u64::MAX / 2, 2),
+            Err(CustomError::ValidationError(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn only_users_below_the_operator_can_be_disabled() {
+        let (state, _r2, _db) = test_state().await;
+        let admin = insert_user_with_role(&state, "root", Role::Admin).await;
+        let moderator = insert_user_with_role(&state, "moderator", Role::Moderator).await;
+        let other_moderator = insert_user_with_role(&state, "other", Role::Moderator).await;
+        let user = insert_user(&state, "alice", "Correct-Horse-9").await;
+        let by_moderator = operator(&moderator, Role::Moderator);
+
+        for target in [&admin, &other_moderator] {
+            for disabled in [true, false] {
+                let result =
+                    set_user_disabled(&state, &by_moderator, &target.id.to_hex(), disabled).await;
+                assert!(matches!(result, Err(CustomError::Forbidden)));
+            }
+        }
+
+        let result = set_user_disabled(&state, &by_moderator, &user.id.to_hex(), true).await;
+        assert!(result.is_ok());
+
+        let by_admin = operator(&admin, Role::Admin);
+        let result = set_user_disabled(&state, &by_admin, &moderator.id.to_hex(), true).await;
+        assert!(result.is_ok());
+        assert!(
+            state
+                .user_service
+                .get_user_by_id(&moderator.id.to_hex())
+                .await
+                .unwrap()
+                .isDisabled
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn disable_and_enable_handlers_only_reach_users_below_the_operator() {
+        let (state, _r2, _db) = test_state().await;
+        let admin = insert_user_with_role(&state, "root", Role::Admin).await;
+        let moderator = insert_user_with_role(&state, "moderator", Role::Moderator).await;
+        let user = insert_user(&state, "alice", "Correct-Horse-9").await;
+        let by_moderator = managing_moderator(&moderator);
+
+        let result = disable_user(
+            RequirePermission::checked(by_moderator.clone()).unwrap(),
+            State(state.clone()),
+            Path(admin.id.to_hex()),
+        )
+        .await;
+        assert!(matches!(result, Err(CustomError::Forbidden)));
+
+        let result = enable_user(
+            RequirePermission::checked(by_moderator.clone()).unwrap(),
+            State(state.clone()),
+            Path(admin.id.to_hex()),
+        )
+        .await;
+        assert!(matches!(result, Err(CustomError::Forbidden)));
+
+        let result = disable_user(
+            RequirePermission::checked(by_moderator.clone()).unwrap(),
+            State(state.clone()),
+            Path(user.id.to_hex()),
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let result = enable_user(
+            RequirePermission::checked(by_moderator).unwrap(),
+            State(state.clone()),
+            Path(user.id.to_hex()),
+        )
+        .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn only_users_below_the_operator_can_be_force_verified() {
+        let (state, _r2, _db) = test_state().await;
+        let admin = insert_user_with_role(&state, "root", Role::Admin).await;
+        let moderator = insert_user_with_role(&state, "moderator", Role::Moderator).await;
+        let user = insert_unverified_user(&state, "alice", "Correct-Horse-9").await;
+        let by_moderator = managing_moderator(&moderator);
+
+        let result = force_verify_email(
+            RequirePermission::checked(by_moderator.clone()).unwrap(),
+            State(state.clone()),
+            Path(admin.id.to_hex()),
+        )
+        .await;
+        assert!(matches!(result, Err(CustomError::Forbidden)));
+
+        let result = force_verify_email(
+            RequirePermission::checked(by_moderator).unwrap(),
+            State(state.clone()),
+            Path(user.id.to_hex()),
+        )
+        .await;
+        assert!(result.is_ok());
+        assert!(
+            state
+                .user_service
+                .get_user_by_id(&user.id.to_hex())
+                .await
+                .unwrap()
+                .isEmailVerified
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn only_users_below_the_operator_can_be_force_logged_out() {
+        let (state, _r2, _db) = test_state().await;
+        let admin = insert_user_with_role(&state, "root", Role::Admin).await;
+        let moderator = insert_user_with_role(&state, "moderator", Role::Moderator).await;
+        let user = insert_user(&state, "alice", "Correct-Horse-9").await;
+        start_session(&state, &admin, "family-admin").await;
+        start_session(&state, &user, "family-user").await;
+        let by_moderator = managing_moderator(&moderator);
+
+        let result = force_logout(
+            RequirePermission::checked(by_moderator.clone()).unwrap(),
+            State(state.clone()),
+            Path(admin.id.to_hex()),
+        )
+        .await;
+        assert!(matches!(result, Err(CustomError::Forbidden)));
+        assert!(
+            state
+                .refresh_token_service
+                .is_family_active("family-admin")
+                .await
+                .unwrap()
+        );
+
+        let result = force_logout(
+            RequirePermission::checked(by_moderator).unwrap(),
+            State(state.clone()),
+            Path(user.id.to_hex()),
+        )
+        .await;
+        assert!(result.is_ok());
+        assert!(
+            !state
+                .refresh_token_service
+                .is_family_active("family-user")
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn admins_only_change_and_grant_roles_below_their_own() {
+        let (state, _r2, _db) = test_state().await;
+        let admin = insert_user_with_role(&state, "root", Role::Admin).await;
+        let other_admin = insert_user_with_role(&state, "other", Role::Admin).await;
+        let user = insert_user(&state, "alice", "Correct-Horse-9").await;
+        let by_admin = operator(&admin, Role::Admin);
+
+        let set_roles = |target: &User, roles: Vec<Role>| {
+            update_user_roles(
+                RequireRole::checked(by_admin.clone()).unwrap(),
+                State(state.clone()),
+                Path(target.id.to_hex()),
+                Json(UserRolesDto {
+                    roles,
+                    permissions: vec![],
+                }),
+            )
+        };
+
+        // neither demoting another admin nor changing their own roles
+        let result = set_roles(&other_admin, vec![]).await;
+        assert!(matches!(result, Err(CustomError::Forbidden)));
+        let result = set_roles(&admin, vec![Role::Admin]).await;
+        assert!(matches!(result, Err(CustomError::Forbidden)));
+
+        let result = set_roles(&user, vec![Role::Admin]).await;
+        assert!(matches!(result, Err(CustomError::Forbidden)));
+
+        let result = set_roles(&user, vec![Role::Moderator]).await;
+        assert!(result.is_ok());
+        let stored = state
+            .user_service
+            .get_user_by_id(&user.id.to_hex())
+            .await
+            .unwrap();
+        assert_eq!(stored.roles, vec![Role::Moderator]);
+        let stored = state
+            .user_service
+            .get_user_by_id(&other_admin.id.to_hex())
+            .await
+            .unwrap();
+        assert_eq!(stored.roles, vec![Role::Admin]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn admins_only_grant_permissions_they_hold() {
+        let (state, _r2, _db) = test_state().await;
+        let admin = insert_user_with_role(&state, "root", Role::Admin).await;
+        let user = insert_user(&state, "alice", "Correct-Horse-9").await;
+        // e.g. a token issued while the admin role granted less
+        let by_admin = Claims {
+            perms: vec![Permission::ReadUsers],
+            ..operator(&admin, Role::Admin)
+        };
+
+        let grant = |permissions: Vec<Permission>| {
+            update_user_roles(
+                RequireRole::checked(by_admin.clone()).unwrap(),
+                State(state.clone()),
+                Path(user.id.to_hex()),
+                Json(UserRolesDto {
+                    roles: vec![],
+                    permissions,
+                }),
+            )
+        };
+
+        let result = grant(vec![Permission::ManageUsers]).await;
+        assert!(matches!(result, Err(CustomError::Forbidden)));
+
+        assert!(grant(vec![Permission::ReadUsers]).await.is_ok());
+        let stored = state
+            .user_service
+            .get_user_by_id(&user.id.to_hex())
+            .await
+            .unwrap();
+        assert_eq!(stored.permissions, vec![Permission::ReadUsers]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn deleted_users_are_only_listed_on_request() {
+        let (state, _r2, _db) = test_state().await;
+        let admin = insert_user_with_role(&state, "root", Role::Admin).await;
+        let user = insert_user(&state, "alice", "Correct-Horse-9").await;
+        state
+            .user_service
+            .soft_delete(&user.id.to_hex(), Utc::now())
+            .await
+            .unwrap();
+
+        let search = |include_deleted: bool| {
+            search_users(
+                RequirePermission::checked(operator(&admin, Role::Admin)).unwrap(),
+                State(state.clone()),
+                Query(UserSearchQuery {
+                    q: None,
+                    page: None,
+                    limit: None,
+                    include_deleted,
+                }),
+            )
+        };
+
+        let Json(page) = search(false).await.unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.users[0].id, admin.id.to_hex());
+
+        let Json(page) = search(true).await.unwrap();
+        assert_eq!(page.total, 2);
+        assert!(
+            page.users
+                .iter()
+                .any(|u| u.id == user.id.to_hex() && u.deleted_at.is_some())
+        );
+    }
+}
@@ -1,37 +1,79 @@
-use crate::dtos::auth_dto::{AuthResDto, ReqResetPassLinkDto, VerifyEmailDto};
-use crate::models::email_verif_token::NewEmailVerifToken;
-use crate::models::refresh_token::NewRefreshToken;
-use crate::services::auth_service::EMAIL_VERIFICATION_EXP_MINUTES;
-use crate::services::email_service::EmailTemplateValues;
+use crate::dtos::auth_dto::{
+    AuthResDto, ChangeEmailDto, ChangePassDto, DeleteAccountDto, LoginResDto, MagicLinkLoginDto,
+    MfaPendingResDto, ReqMagicLinkDto, ReqResetPassLinkDto, ResetPassDto, VerifyEmailDto,
+};
+use crate::models::account_purge::NewAccountPurge;
+use crate::models::email_verif_token::{EmailTokenPurpose, NewEmailVerifToken};
+use crate::models::refresh_token::{NewRefreshToken, RefreshToken};
+use crate::models::reset_pass_token::NewResetPassToken;
+use crate::models::security_event::{NewSecurityEvent, SecurityEventKind};
+use crate::services::auth_service::{
+    ACCOUNT_PURGE_AFTER_DAYS, EMAIL_VERIFICATION_EXP_SECS, MAGIC_LINK_EXP_SECS,
+    RESET_PASS_EXP_SECS, VERIF_EMAIL_DAILY_CAP, VERIF_EMAIL_RESEND_COOLDOWN_SECS,
+};
+use crate::services::login_attempt_service::LoginAttemptService;
+use crate::services::refresh_token_service::RefreshTokenService;
+use crate::types::change_email::{ChangeEmail, EmailChangeNotice};
 use crate::types::claims::Claims;
+use crate::types::client_info::ClientInfo;
 use crate::types::email::Email;
 use crate::types::error::CustomError;
+use crate::types::magic_link::MagicLink;
+use crate::types::reset_password::ResetPassword;
+use crate::types::role::effective_permissions;
+use crate::types::token_transport::TokenTransport;
+use crate::types::validation::FieldError;
 use crate::types::verify_email::VerifyEmail;
 use crate::utils::datetime::now_epoch;
+use crate::utils::email::normalize_email;
+use crate::utils::username::normalize_username;
 use crate::{
     AppState,
     dtos::{auth_dto, general_res_dto::GeneralResDto},
-    models::user::NewUser,
+    models::user::{NewUser, User},
 };
 use axum::extract::Query;
 use axum::{Json, debug_handler, extract::State, http::StatusCode};
+use axum_extra::extract::CookieJar;
+use bson::oid::ObjectId;
 use chrono::offset::LocalResult;
-use chrono::{TimeZone, Utc};
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use std::sync::Arc;
 
 #[debug_handler]
 pub async fn register(
     State(state): State<Arc<AppState>>,
+    client: ClientInfo,
+    transport: TokenTransport,
     Json(payload): Json<auth_dto::RegisterReqDto>,
-) -> Result<Json<AuthResDto>, CustomError> {
-    if payload.email.is_empty()
-        || !payload.email.contains("@")
-        || payload.username.len() < 5
-        || payload.password.len() < 8
-    {
+) -> Result<(CookieJar, Json<AuthResDto>), CustomError> {
+    if payload.email.is_empty() || !payload.email.contains("@") || payload.password.is_empty() {
         return Err(CustomError::MissingCredentials);
     }
 
+    let username = normalize_username(&payload.username).map_err(|reason| {
+        CustomError::ValidationError(vec![FieldError::new("username", &reason)])
+    })?;
+
+    if let Some(reason) = state
+        .username_history_service
+        .username_taken_reason(&state.user_service, &username, None)
+        .await?
+    {
+        return Err(CustomError::ValidationError(vec![FieldError::new(
+            "username", &reason,
+        )]));
+    }
+
+    state
+        .password_policy_service
+        .check(
+            "password",
+            &payload.password,
+            &[&username, email_local_part(&payload.email)],
+        )
+        .await?;
+
     let password_hash = match state.auth_service.hash_password(payload.password) {
         Ok(value) => value,
         Err(_) => return Err(CustomError::HashError),
@@ -42,8 +84,8 @@ pub async fn register(
 
     // Create user in DB
     let user = NewUser {
-        email: payload.email.to_lowercase(),
-        username: payload.username,
+        email: normalize_email(&payload.email),
+        username,
         password: password_hash,
         isEmailVerified: false,
         lastLoginAt: Utc::now(),
@@ -53,180 +95,164 @@ pub async fn register(
 
     let user_id = state.user_service.create_user(&user).await?;
 
+    let created_user = state.user_service.get_user_by_id(&user_id.to_hex()).await?;
+
     // Send email to verify email address
     tokio::spawn({
         let state = state.clone();
         let username = user.username.clone();
         let email = user.email.clone();
-        let user_id = user_id.clone();
 
         async move {
-            if let Err(err) = async {
-                let (object_bytes, ext) = state
-                    .storage_service
-                    .get_object("halalho/email-templates/verify-email.html")
-                    .await
-                    .map_err(|_| CustomError::R2Error)?;
-
-                let object_extension = ext.ok_or(CustomError::R2Error)?;
-
-                let (raw_token, token_hash) =
-                    state.auth_service.generate_email_verification_token()?;
-
-                let new_verif_email_token = NewEmailVerifToken {
-                    userId: user_id,
-                    tokenHash: token_hash,
-                    expiresAt: Utc
-                        .timestamp_opt(
-                            (now_epoch() + EMAIL_VERIFICATION_EXP_MINUTES as usize) as i64,
-                            0,
-                        )
-                        .single()
-                        .ok_or_else(|| {
-                            tracing::error!(
-                                "Error converting timestamp for verif email token expiration"
-                            );
-                            CustomError::TokenCreation
-                        })?,
-                    createdAt: Utc::now(),
-                    usedAt: None,
-                };
-
-                state
-                    .verif_email_token_service
-                    .create_token(&new_verif_email_token)
-                    .await?;
-
-                let values = EmailTemplateValues::VerifyEmailValues(VerifyEmail::new(
-                    &username,
-                    &user_id.to_hex(),
-                    &raw_token,
-                ));
-
-                let email_html = state.email_service.prepare_template(
-                    &object_bytes,
-                    &object_extension,
-                    values,
-                )?;
-
-                let email: Email = Email::new(
-                    vec![(&username, &email)],
-                    email_html,
-                    "Please verify your email-address",
-                );
-
-                state.email_service.send_transactional_email(email).await?;
-
-                Ok::<(), CustomError>(())
-            }
-            .await
-            {
+            if let Err(err) = send_verification_email(&state, user_id, &username, &email).await {
                 tracing::error!("Failed to send verification email for {}: {:?}", email, err)
             }
         }
     });
 
     // Generate tokens for authentication
-    let (tokens, jti, exp) = state
-        .auth_service
-        .generate_tokens(&user_id.to_hex())
-        .map_err(|_| CustomError::TokenCreation)?;
-
-    let expires_at = match Utc.timestamp_opt(exp as i64, 0) {
-        LocalResult::Single(dt) => dt,
-        _ => {
-            tracing::error!("Error converting timestamp");
-            return Err(CustomError::TokenCreation);
-        }
-    };
-
-    // let expiresAt = Utc.timestamp_opt(exp as i64, 0).single().ok_or_else(|| {
-    //     tracing::error!("Error converting timestamp");
-    //     CustomError::TokenCreation
-    // })?;
-
-    let new_refresh_token = NewRefreshToken {
-        userId: user_id,
-        token: jti,
-        isRevoked: false,
-        createdAt: Utc::now(),
-        expiresAt: expires_at,
-        usedAt: None,
-    };
+    let family_id = state.auth_service.new_token_family();
 
-    state
-        .refresh_token_service
-        .create_token(&new_refresh_token)
-        .await?;
+    let tokens = issue_tokens(&state, &created_user, family_id, Utc::now(), &client).await?;
 
     tracing::info!("User {} has logged in after registration", user_id.to_hex());
 
-    Ok(Json(tokens))
+    let (jar, tokens) = transport.deliver(tokens)?;
+
+    Ok((jar, Json(tokens)))
 }
 
 pub async fn login(
     State(state): State<Arc<AppState>>,
+    client: ClientInfo,
+    transport: TokenTransport,
     Json(payload): Json<auth_dto::LoginReqDto>,
-) -> Result<Json<AuthResDto>, CustomError> {
-    if payload.email.is_empty() || !payload.email.contains("@") || payload.password.len() < 8 {
+) -> Result<(CookieJar, Json<LoginResDto>), CustomError> {
+    // Password rules only apply to new passwords, older or imported ones can be shorter
+    if payload.email.is_empty() || !payload.email.contains("@") || payload.password.is_empty() {
         return Err(CustomError::MissingCredentials);
     }
 
-    let user = state.user_service.get_user_by_email(&payload.email).await?;
+    let user = match state.user_service.get_user_by_email(&payload.email).await {
+        Ok(user) => user,
+        Err(CustomError::NotFoundError(email)) => {
+            // Track nonexistent accounts by email so they behave like real ones
+            let key = LoginAttemptService::email_key(&email);
 
-    match state
+            state.login_attempt_service.ensure_can_attempt(&key).await?;
+
+            // Spend as long as checking a real password, so timing doesn't tell either
+            state.auth_service.verify_dummy_password(payload.password);
+
+            state.login_attempt_service.record_failure(&key).await?;
+
+            return Err(CustomError::WrongCredentials);
+        }
+        Err(err) => return Err(err),
+    };
+
+    let attempt_key = LoginAttemptService::user_key(&user.id.to_hex());
+
+    state
+        .login_attempt_service
+        .ensure_can_attempt(&attempt_key)
+        .await?;
+
+    let verified = state
         .auth_service
-        .verify_password(payload.password, user.password)
-    {
+        .verify_password(payload.password.clone(), user.password.clone());
+
+    if verified.is_ok() && !user.isDisabled {
+        upgrade_password_hash(&state, &user, payload.password).await;
+    }
+
+    match verified {
+        Ok(_) if user.isDisabled => Err(CustomError::AccountDisabled),
+        Ok(_) if user.isTotpEnabled => {
+            // Tokens are only issued once the second factor is verified
+            let mfa_token = state.auth_service.generate_mfa_token(&user.id.to_hex())?;
+
+            Ok((
+                CookieJar::new(),
+                Json(LoginResDto::MfaPending(MfaPendingResDto::new(mfa_token))),
+            ))
+        }
         Ok(_) => {
             tracing::info!("User {} has logged in", user.email);
 
-            let (tokens, jti, exp) = state
-                .auth_service
-                .generate_tokens(&user.id.to_string())
-                .map_err(|_| CustomError::TokenCreation)?;
-
-            let expires_at = match Utc.timestamp_opt(exp as i64, 0) {
-                LocalResult::Single(dt) => dt,
-                _ => {
-                    tracing::error!("Error converting timestamp");
-                    return Err(CustomError::TokenCreation);
-                }
-            };
+            state.login_attempt_service.reset(&attempt_key).await?;
 
-            let new_refresh_token = NewRefreshToken {
-                userId: user.id,
-                token: jti,
-                isRevoked: false,
-                createdAt: Utc::now(),
-                expiresAt: expires_at,
-                usedAt: None,
-            };
+            let family_id = state.auth_service.new_token_family();
 
-            state
-                .refresh_token_service
-                .create_token(&new_refresh_token)
-                .await?;
+            let tokens = issue_tokens(&state, &user, family_id, Utc::now(), &client).await?;
+
+            let (jar, tokens) = transport.deliver(tokens)?;
+
+            Ok((jar, Json(LoginResDto::Tokens(tokens))))
+        }
+        Err(_) => {
+            if let Some(locked_until) = state
+                .login_attempt_service
+                .record_failure(&attempt_key)
+                .await?
+            {
+                state
+                    .security_event_service
+                    .record_event(&NewSecurityEvent {
+                        userId: user.id,
+                        kind: SecurityEventKind::AccountLocked,
+                        detail: format!("Too many failed logins, locked until {}", locked_until),
+                        createdAt: Utc::now(),
+                    })
+                    .await?;
+            }
+
+            Err(CustomError::WrongCredentials)
+        }
+    }
+}
+
+/// Re-hash a verified password whose stored hash is a legacy algorithm or
+/// uses weaker Argon2 parameters than configured. Failures are logged only,
+/// the old hash keeps working.
+async fn upgrade_password_hash(state: &AppState, user: &User, password: String) {
+    if !state.auth_service.needs_rehash(&user.password) {
+        return;
+    }
 
-            Ok(Json(tokens))
+    let password_hash = match state.auth_service.hash_password(password) {
+        Ok(password_hash) => password_hash,
+        Err(err) => {
+            tracing::error!("Error rehashing password for {}: {:?}", user.email, err);
+            return;
         }
-        Err(_) => Err(CustomError::WrongCredentials),
+    };
+
+    match state
+        .user_service
+        .update_password(&user.id.to_hex(), &password_hash)
+        .await
+    {
+        Ok(_) => tracing::info!("Upgraded password hash for {}", user.email),
+        Err(err) => tracing::error!(
+            "Error storing upgraded password hash for {}: {:?}",
+            user.email,
+            err
+        ),
     }
 }
 
 pub async fn logout(
     claims: Claims,
     State(state): State<Arc<AppState>>,
-    Json(payload): Json<auth_dto::LogoutDto>,
-) -> Result<Json<GeneralResDto>, CustomError> {
-    if payload.refresh_token.is_empty() {
-        tracing::debug!("No refresh token provided");
-        return Err(CustomError::MissingCredentials);
-    }
+    transport: TokenTransport,
+    payload: Option<Json<auth_dto::LogoutDto>>,
+) -> Result<(CookieJar, Json<GeneralResDto>), CustomError> {
+    let refresh_token = transport
+        .refresh_token(payload.as_ref().map(|p| p.refresh_token.as_str()))
+        .inspect_err(|_| tracing::debug!("No refresh token provided"))?;
 
-    let refresh_claims = state
-        .auth_service
-        .decode_refresh_token(&payload.refresh_token)?;
+    let refresh_claims = state.auth_service.decode_refresh_token(&refresh_token)?;
 
     if refresh_claims.jti.is_empty() {
         tracing::debug!("Missing jti");
@@ -242,24 +268,34 @@ pub async fn logout(
         .revoke_token(&refresh_claims.jti)
         .await?;
 
-    Ok(Json(GeneralResDto {
-        message: "Ok".to_string(),
-        status_code: StatusCode::OK.as_u16(),
-    }))
+    // the session ends with its refresh token, and so must its other access tokens
+    state
+        .access_denylist_service
+        .revoke_session(&refresh_claims.fam)
+        .await?;
+
+    state.access_denylist_service.revoke_token(&claims).await?;
+
+    Ok((
+        transport.clear(),
+        Json(GeneralResDto {
+            message: "Ok".to_string(),
+            status_code: StatusCode::OK.as_u16(),
+        }),
+    ))
 }
 
 pub async fn refresh(
     State(state): State<Arc<AppState>>,
-    Json(payload): Json<auth_dto::LogoutDto>,
-) -> Result<Json<AuthResDto>, CustomError> {
-    if payload.refresh_token.is_empty() {
-        tracing::debug!("Missing refresh token");
-        return Err(CustomError::MissingCredentials);
-    }
+    client: ClientInfo,
+    transport: TokenTransport,
+    payload: Option<Json<auth_dto::LogoutDto>>,
+) -> Result<(CookieJar, Json<AuthResDto>), CustomError> {
+    let refresh_token = transport
+        .refresh_token(payload.as_ref().map(|p| p.refresh_token.as_str()))
+        .inspect_err(|_| tracing::debug!("Missing refresh token"))?;
 
-    let current_refresh_claims = state
-        .auth_service
-        .decode_refresh_token(&payload.refresh_token)?;
+    let current_refresh_claims = state.auth_service.decode_refresh_token(&refresh_token)?;
 
     if current_refresh_claims.jti.is_empty() {
         tracing::debug!("Missing jti");
@@ -275,62 +311,103 @@ pub async fn refresh(
         return Err(CustomError::TokenExpired);
     }
 
-    let mut should_revoke = true;
-
-    if current_token.isRevoked {
-        match current_token.usedAt {
-            Some(used_at) if (Utc::now() - used_at).num_seconds() > 90 => {
-                return Err(CustomError::TokenExpired);
-            }
-            Some(used_at) if (Utc::now() - used_at).num_seconds() < 90 => {
-                // don't revoke again
-                should_revoke = false;
-            }
-            None => return Err(CustomError::InvalidToken),
-            _ => return Err(CustomError::InvalidToken),
-        }
-    }
+    let rotated = !current_token.isRevoked
+        && state
+            .refresh_token_service
+            .rotate_token(&current_refresh_claims.jti)
+            .await?;
 
-    if should_revoke {
+    let current_token = if rotated || current_token.isRevoked {
+        current_token
+    } else {
+        // Another request rotated the token since it was read, e.g. a second tab
+        // refreshing at the same time, so its rotation decides what this one is
         state
             .refresh_token_service
-            .revoke_token(&current_refresh_claims.jti)
-            .await?;
+            .get_token_by_jti(&current_refresh_claims.jti)
+            .await?
+    };
+
+    if !rotated {
+        let family_active = !current_token.familyId.is_empty()
+            && state
+                .refresh_token_service
+                .is_family_active(&current_token.familyId)
+                .await?;
+
+        // A rotated token may be redeemed once more inside the grace window, any
+        // other presentation replays it and the whole chain has to be considered
+        // compromised
+        if !RefreshTokenService::within_rotation_grace(&current_token, family_active, Utc::now())
+            || !state
+                .refresh_token_service
+                .redeem_in_grace(&current_refresh_claims.jti)
+                .await?
+        {
+            revoke_reused_chain(&state, &current_token).await?;
+
+            return Err(CustomError::TokenExpired);
+        }
     }
 
+    // tokens issued before family tracking start a new chain
+    let family_id = if current_token.familyId.is_empty() {
+        state.auth_service.new_token_family()
+    } else {
+        current_token.familyId
+    };
+
     let user = state
         .user_service
         .get_user_by_id(&current_refresh_claims.sub)
         .await?;
 
-    let (tokens, jti, exp) = state
-        .auth_service
-        .generate_tokens(&user.id.to_hex())
-        .map_err(|_| CustomError::TokenCreation)?;
+    let session_started_at = current_token
+        .sessionStartedAt
+        .unwrap_or(current_token.createdAt);
 
-    let expires_at = match Utc.timestamp_opt(exp as i64, 0) {
-        LocalResult::Single(dt) => dt,
-        _ => {
-            tracing::error!("Error converting timestamp");
-            return Err(CustomError::TokenCreation);
-        }
-    };
+    let tokens = issue_tokens(&state, &user, family_id, session_started_at, &client).await?;
 
-    let new_refresh_token = NewRefreshToken {
-        userId: user.id,
-        token: jti,
-        isRevoked: false,
-        createdAt: Utc::now(),
-        expiresAt: expires_at,
-        usedAt: None,
-    };
+    let (jar, tokens) = transport.deliver(tokens)?;
+
+    Ok((jar, Json(tokens)))
+}
+
+/// Revoke the chain a reused refresh token belongs to and record the reuse
+async fn revoke_reused_chain(state: &AppState, token: &RefreshToken) -> Result<(), CustomError> {
+    if token.familyId.is_empty() {
+        let user_id = token.userId.to_hex();
+
+        state
+            .refresh_token_service
+            .revoke_all_user_tokens(&user_id)
+            .await?;
+        state
+            .access_denylist_service
+            .revoke_user(&user_id, None)
+            .await?;
+    } else {
+        state
+            .refresh_token_service
+            .revoke_family(&token.familyId)
+            .await?;
+        state
+            .access_denylist_service
+            .revoke_session(&token.familyId)
+            .await?;
+    }
 
     state
-        .refresh_token_service
-        .create_token(&new_refresh_token)
+        .security_event_service
+        .record_event(&NewSecurityEvent {
+            userId: token.userId,
+            kind: SecurityEventKind::RefreshTokenReuse,
+            detail: format!("A refresh token of family {} was reused", token.familyId),
+            createdAt: Utc::now(),
+        })
         .await?;
 
-    Ok(Json(tokens))
+    Ok(())
 }
 
 /// Verify email address based on the link from email which was sent to user
@@ -344,7 +421,7 @@ pub async fn verify_email(
 
     let user = state.user_service.get_user_by_id(&payload.user_id).await?;
 
-    if user.isEmailVerified == true {
+    if user.isEmailVerified {
         return Ok(Json(GeneralResDto {
             status_code: 200,
             message: "Email is already verified".to_owned(),
@@ -355,7 +432,11 @@ pub async fn verify_email(
 
     state
         .verif_email_token_service
-        .find_valid_token_then_update(&hashed_token, &payload.user_id)
+        .find_valid_token_then_update(
+            &hashed_token,
+            &payload.user_id,
+            EmailTokenPurpose::VerifyEmail,
+        )
         .await?;
 
     state
@@ -370,16 +451,1229 @@ pub async fn verify_email(
 }
 
 /// Request to get reset password link
+///
+/// Always answers 200 regardless of whether the email belongs to an account,
+/// so this endpoint can't be used to enumerate users
 pub async fn send_reset_pass_link(
     State(state): State<Arc<AppState>>,
     Json(payload): Json<ReqResetPassLinkDto>,
 ) -> Result<Json<GeneralResDto>, CustomError> {
-    if payload.email.is_empty() {
+    if payload.email.is_empty() || !payload.email.contains("@") {
+        return Err(CustomError::MissingCredentials);
+    }
+
+    let user = match state.user_service.get_user_by_email(&payload.email).await {
+        Ok(user) => Some(user),
+        Err(CustomError::NotFoundError(_)) => {
+            tracing::info!("Reset password link requested for unknown email");
+            None
+        }
+        Err(err) => return Err(err),
+    };
+
+    // Send the email in the background so the response time doesn't reveal
+    // whether the account exists
+    if let Some(user) = user {
+        tokio::spawn({
+            let state = state.clone();
+
+            async move {
+                if let Err(err) = async {
+                    let (object_bytes, ext) = state
+                        .storage_service
+                        .get_object("halalho/email-templates/reset-password.html")
+                        .await
+                        .map_err(|_| CustomError::R2Error)?;
+
+                    let object_extension = ext.ok_or(CustomError::R2Error)?;
+
+                    let (raw_token, token_hash) = state.auth_service.generate_reset_pass_token()?;
+
+                    // Only the latest link should be usable
+                    state
+                        .reset_pass_token_service
+                        .invalidate_user_tokens(&user.id)
+                        .await?;
+
+                    let new_reset_pass_token = NewResetPassToken {
+                        userId: user.id,
+                        tokenHash: token_hash,
+                        expiresAt: Utc
+                            .timestamp_opt((now_epoch() + RESET_PASS_EXP_SECS as usize) as i64, 0)
+                            .single()
+                            .ok_or_else(|| {
+                                tracing::error!(
+                                    "Error converting timestamp for reset pass token expiration"
+                                );
+                                CustomError::TokenCreation
+                            })?,
+                        createdAt: Utc::now(),
+                        usedAt: None,
+                    };
+
+                    state
+                        .reset_pass_token_service
+                        .create_token(&new_reset_pass_token)
+                        .await?;
+
+                    let values = ResetPassword::new(&user.username, &raw_token);
+
+                    let email_html = state.email_service.prepare_template(
+                        &object_bytes,
+                        &object_extension,
+                        &values.as_array(),
+                    )?;
+
+                    let email: Email = Email::new(
+                        vec![(&user.username, &user.email)],
+                        email_html,
+                        "Reset your password",
+                    );
+
+                    state.email_service.send_transactional_email(email).await?;
+
+                    Ok::<(), CustomError>(())
+                }
+                .await
+                {
+                    tracing::error!(
+                        "Failed to send reset password email for {}: {:?}",
+                        user.email,
+                        err
+                    )
+                }
+            }
+        });
+    }
+
+    Ok(Json(GeneralResDto {
+        status_code: 200,
+        message: "If the email is registered, a reset link has been sent".to_owned(),
+    }))
+}
+
+/// Set a new password using the token from the reset password link.
+///
+/// Every existing session of the user is revoked afterwards
+pub async fn reset_password(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<ResetPassDto>,
+) -> Result<Json<GeneralResDto>, CustomError> {
+    if payload.token.is_empty() || payload.new_password.is_empty() {
+        return Err(CustomError::MissingCredentials);
+    }
+
+    let hashed_token = state.auth_service.hash_raw_token(&payload.token);
+
+    // The link is only consumed once the new password is accepted, so a rejected
+    // password can be retried with the same link
+    let reset_token = state
+        .reset_pass_token_service
+        .find_valid_token(&hashed_token)
+        .await?;
+
+    let user_id = reset_token.userId.to_hex();
+
+    let user = state.user_service.get_user_by_id(&user_id).await?;
+
+    state
+        .password_policy_service
+        .check(
+            "new_password",
+            &payload.new_password,
+            &[&user.username, email_local_part(&user.email)],
+        )
+        .await?;
+
+    state
+        .reset_pass_token_service
+        .find_valid_token_then_update(&hashed_token)
+        .await?;
+
+    let password_hash = state
+        .auth_service
+        .hash_password(payload.new_password)
+        .map_err(|_| CustomError::HashError)?;
+
+    state
+        .user_service
+        .update_password(&user_id, &password_hash)
+        .await?;
+
+    state
+        .refresh_token_service
+        .revoke_all_user_tokens(&user_id)
+        .await?;
+
+    state
+        .access_denylist_service
+        .revoke_user(&user_id, None)
+        .await?;
+
+    // A successful reset proves ownership, so a lockout shouldn't keep the user out
+    state
+        .login_attempt_service
+        .reset(&LoginAttemptService::user_key(&user_id))
+        .await?;
+
+    tracing::info!("User {} has reset the password", user_id);
+
+    Ok(Json(GeneralResDto {
+        status_code: 200,
+        message: "Ok".to_owned(),
+    }))
+}
+
+/// Change the password of the logged in user, optionally logging out of
+/// every other session while keeping the current one
+pub async fn change_password(
+    claims: Claims,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<ChangePassDto>,
+) -> Result<Json<GeneralResDto>, CustomError> {
+    if payload.current_password.is_empty() || payload.new_password.is_empty() {
         return Err(CustomError::MissingCredentials);
     }
 
+    let user = state.user_service.get_user_by_id(&claims.sub).await?;
+
+    let password_hash = hash_changed_password(
+        &state,
+        &user,
+        payload.current_password,
+        payload.new_password,
+    )
+    .await?;
+
+    state
+        .user_service
+        .update_password(&claims.sub, &password_hash)
+        .await?;
+
+    if payload.revoke_other_sessions {
+        state
+            .refresh_token_service
+            .revoke_user_tokens_except_family(&claims.sub, &claims.sid)
+            .await?;
+
+        // Other sessions lose their refresh tokens, their access tokens go too
+        state
+            .access_denylist_service
+            .revoke_user(&claims.sub, Some(&claims.sid))
+            .await?;
+    }
+
+    tracing::info!("User {} has changed the password", claims.sub);
+
     Ok(Json(GeneralResDto {
         status_code: 200,
-        message: "OK".to_owned(),
+        message: "Ok".to_owned(),
     }))
 }
+
+/// Hash the new password of `user`, once the current one is verified and the new
+/// one passes the policy
+async fn hash_changed_password(
+    state: &AppState,
+    user: &User,
+    current_password: String,
+    new_password: String,
+) -> Result<String, CustomError> {
+    state
+        .auth_service
+        .verify_password(current_password, user.password.clone())
+        .map_err(|_| CustomError::WrongCredentials)?;
+
+    state
+        .password_policy_service
+        .check(
+            "new_password",
+            &new_password,
+            &[&user.username, email_local_part(&user.email)],
+        )
+        .await?;
+
+    state
+        .auth_service
+        .hash_password(new_password)
+        .map_err(|_| CustomError::HashError)
+}
+
+/// Request to change the email address of the logged in user.
+///
+/// A confirmation link is sent to the new address and a notice to the old one,
+/// the address is only switched once the link is opened
+pub async fn request_email_change(
+    claims: Claims,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<ChangeEmailDto>,
+) -> Result<Json<GeneralResDto>, CustomError> {
+    if payload.new_email.is_empty()
+        || !payload.new_email.contains("@")
+        || payload.password.is_empty()
+    {
+        return Err(CustomError::MissingCredentials);
+    }
+
+    let new_email = normalize_email(&payload.new_email);
+
+    let user = state.user_service.get_user_by_id(&claims.sub).await?;
+
+    state
+        .auth_service
+        .verify_password(payload.password, user.password)
+        .map_err(|_| CustomError::WrongCredentials)?;
+
+    match state.user_service.get_user_by_email(&new_email).await {
+        Ok(_) => return Err(CustomError::DuplicateKey(new_email)),
+        Err(CustomError::NotFoundError(_)) => {}
+        Err(err) => return Err(err),
+    }
+
+    // Only the latest requested address can be confirmed
+    state
+        .verif_email_token_service
+        .invalidate_user_tokens(&user.id, EmailTokenPurpose::ChangeEmail)
+        .await?;
+
+    let raw_token = create_email_token(
+        &state,
+        user.id,
+        EmailTokenPurpose::ChangeEmail,
+        Some(new_email.clone()),
+    )
+    .await?;
+
+    let values = ChangeEmail::new(&user.username, &user.id.to_hex(), &new_email, &raw_token);
+
+    state
+        .email_service
+        .send_templated_email(
+            &state.storage_service,
+            "halalho/email-templates/change-email.html",
+            &values.as_array(),
+            (&user.username, &new_email),
+            "Confirm your new email-address",
+        )
+        .await?;
+
+    // Let the owner of the old address know, in case the request wasn't made by them
+    tokio::spawn({
+        let state = state.clone();
+
+        async move {
+            let values = EmailChangeNotice::new(&user.username, &new_email);
+
+            if let Err(err) = state
+                .email_service
+                .send_templated_email(
+                    &state.storage_service,
+                    "halalho/email-templates/email-change-notice.html",
+                    &values.as_array(),
+                    (&user.username, &user.email),
+                    "Your email-address is being changed",
+                )
+                .await
+            {
+                tracing::error!(
+                    "Failed to send email change notice for {}: {:?}",
+                    user.email,
+                    err
+                )
+            }
+        }
+    });
+
+    Ok(Json(GeneralResDto {
+        status_code: 200,
+        message: "Ok".to_owned(),
+    }))
+}
+
+/// Switch to the new email address based on the link sent to that address
+pub async fn confirm_email_change(
+    State(state): State<Arc<AppState>>,
+    payload: Query<VerifyEmailDto>,
+) -> Result<Json<GeneralResDto>, CustomError> {
+    if payload.user_id.is_empty() || payload.token.is_empty() {
+        return Err(CustomError::MissingCredentials);
+    }
+
+    let hashed_token = state.auth_service.hash_raw_token(&payload.token);
+
+    let token = state
+        .verif_email_token_service
+        .find_valid_token_then_update(
+            &hashed_token,
+            &payload.user_id,
+            EmailTokenPurpose::ChangeEmail,
+        )
+        .await?;
+
+    let new_email = token.newEmail.ok_or(CustomError::InvalidToken)?;
+
+    // The unique index rejects the switch if the address got taken in the meantime
+    state
+        .user_service
+        .update_email(&payload.user_id, &new_email)
+        .await?;
+
+    tracing::info!("User {} has changed the email address", payload.user_id);
+
+    Ok(Json(GeneralResDto {
+        status_code: 200,
+        message: "Ok".to_owned(),
+    }))
+}
+
+/// Delete the account of the logged in user after re-entering the password.
+///
+/// The user is soft deleted right away, which frees the email and username. Sessions,
+/// email tokens, passkeys and linked identities are removed now, the objects stored in R2
+/// by the purge job and every other document of the user after a grace period
+pub async fn delete_account(
+    claims: Claims,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<DeleteAccountDto>,
+) -> Result<Json<GeneralResDto>, CustomError> {
+    if payload.password.is_empty() {
+        return Err(CustomError::MissingCredentials);
+    }
+
+    let user = state.user_service.get_user_by_id(&claims.sub).await?;
+
+    state
+        .auth_service
+        .verify_password(payload.password, user.password)
+        .map_err(|_| CustomError::WrongCredentials)?;
+
+    let now = Utc::now();
+    let purge_at = now + Duration::days(ACCOUNT_PURGE_AFTER_DAYS);
+
+    // Scheduled first, so whatever fails below is still cleaned up by the purge job,
+    // which removes the R2 objects and retries until they are gone
+    state
+        .account_purge_service
+        .schedule_purge(&NewAccountPurge {
+            userId: user.id,
+            objectsDeleted: false,
+            attempts: 0,
+            purgeAt: purge_at,
+            nextAttemptAt: now,
+        })
+        .await?;
+
+    state
+        .user_service
+        .soft_delete(&claims.sub, purge_at)
+        .await?;
+
+    state
+        .refresh_token_service
+        .revoke_all_user_tokens(&claims.sub)
+        .await?;
+
+    state
+        .access_denylist_service
+        .revoke_user(&claims.sub, None)
+        .await?;
+
+    state
+        .verif_email_token_service
+        .delete_user_tokens(&user.id)
+        .await?;
+
+    state
+        .reset_pass_token_service
+        .invalidate_user_tokens(&user.id)
+        .await?;
+
+    state
+        .webauthn_credential_service
+        .delete_user_credentials(&user.id)
+        .await?;
+
+    state
+        .linked_identity_service
+        .delete_user_identities(&user.id)
+        .await?;
+
+    tracing::info!("User {} has deleted the account", claims.sub);
+
+    Ok(Json(GeneralResDto {
+        status_code: 200,
+        message: "Ok".to_owned(),
+    }))
+}
+
+/// Resend the verification email to the logged in user.
+///
+/// Older links are invalidated and resending is limited by a cooldown and a daily cap
+pub async fn resend_verification(
+    claims: Claims,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<GeneralResDto>, CustomError> {
+    let user = state.user_service.get_user_by_id(&claims.sub).await?;
+
+    if user.isEmailVerified {
+        return Ok(Json(GeneralResDto {
+            status_code: 200,
+            message: "Email is already verified".to_owned(),
+        }));
+    }
+
+    let recent_tokens = state
+        .verif_email_token_service
+        .get_tokens_created_since(
+            &user.id,
+            EmailTokenPurpose::VerifyEmail,
+            Utc::now() - Duration::days(1),
+        )
+        .await?;
+
+    let sent_at: Vec<DateTime<Utc>> = recent_tokens.iter().map(|t| t.createdAt).collect();
+
+    if let Some(wait_secs) = resend_wait_secs(&sent_at, Utc::now()) {
+        return Err(CustomError::TooManyRequests(wait_secs));
+    }
+
+    state
+        .verif_email_token_service
+        .invalidate_user_tokens(&user.id, EmailTokenPurpose::VerifyEmail)
+        .await?;
+
+    send_verification_email(&state, user.id, &user.username, &user.email).await?;
+
+    Ok(Json(GeneralResDto {
+        status_code: 200,
+        message: "Ok".to_owned(),
+    }))
+}
+
+/// Request a one-time login link by email.
+///
+/// Like the reset password link it always answers 200, so accounts can't be enumerated
+pub async fn send_magic_link(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<ReqMagicLinkDto>,
+) -> Result<Json<GeneralResDto>, CustomError> {
+    if payload.email.is_empty() || !payload.email.contains("@") {
+        return Err(CustomError::MissingCredentials);
+    }
+
+    let user = match state.user_service.get_user_by_email(&payload.email).await {
+        Ok(user) => user,
+        Err(CustomError::NotFoundError(_)) => {
+            tracing::info!("Magic link requested for unknown email");
+
+            return Ok(Json(GeneralResDto {
+                status_code: 200,
+                message: "If the email is registered, a login link has been sent".to_owned(),
+            }));
+        }
+        Err(err) => return Err(err),
+    };
+
+    tokio::spawn({
+        let state = state.clone();
+
+        async move {
+            if let Err(err) = async {
+                // Only the latest link should be usable
+                state
+                    .verif_email_token_service
+                    .invalidate_user_tokens(&user.id, EmailTokenPurpose::MagicLink)
+                    .await?;
+
+                let raw_token =
+                    create_email_token(&state, user.id, EmailTokenPurpose::MagicLink, None).await?;
+
+                let values = MagicLink::new(&user.username, &user.id.to_hex(), &raw_token);
+
+                state
+                    .email_service
+                    .send_templated_email(
+                        &state.storage_service,
+                        "halalho/email-templates/magic-link.html",
+                        &values.as_array(),
+                        (&user.username, &user.email),
+                        "Your login link",
+                    )
+                    .await
+            }
+            .await
+            {
+                tracing::error!("Failed to send magic link for {}: {:?}", user.email, err)
+            }
+        }
+    });
+
+    Ok(Json(GeneralResDto {
+        status_code: 200,
+        message: "If the email is registered, a login link has been sent".to_owned(),
+    }))
+}
+
+/// Log in with the token of a magic link, which also proves the email address
+pub async fn magic_link_login(
+    State(state): State<Arc<AppState>>,
+    client: ClientInfo,
+    transport: TokenTransport,
+    Json(payload): Json<MagicLinkLoginDto>,
+) -> Result<(CookieJar, Json<LoginResDto>), CustomError> {
+    if payload.user_id.is_empty() || payload.token.is_empty() {
+        return Err(CustomError::MissingCredentials);
+    }
+
+    let hashed_token = state.auth_service.hash_raw_token(&payload.token);
+
+    state
+        .verif_email_token_service
+        .find_valid_token_then_update(
+            &hashed_token,
+            &payload.user_id,
+            EmailTokenPurpose::MagicLink,
+        )
+        .await?;
+
+    let user = state.user_service.get_user_by_id(&payload.user_id).await?;
+
+    if !user.isEmailVerified {
+        state
+            .user_service
+            .update_email_verified(&payload.user_id)
+            .await?;
+    }
+
+    // The link replaces the password, not the second factor
+    if user.isTotpEnabled {
+        let mfa_token = state.auth_service.generate_mfa_token(&payload.user_id)?;
+
+        return Ok((
+            CookieJar::new(),
+            Json(LoginResDto::MfaPending(MfaPendingResDto::new(mfa_token))),
+        ));
+    }
+
+    tracing::info!("User {} has logged in with a magic link", user.email);
+
+    let family_id = state.auth_service.new_token_family();
+
+    let tokens = issue_tokens(&state, &user, family_id, Utc::now(), &client).await?;
+
+    let (jar, tokens) = transport.deliver(tokens)?;
+
+    Ok((jar, Json(LoginResDto::Tokens(tokens))))
+}
+
+/// Issue a new email verification token and mail the link to the user
+async fn send_verification_email(
+    state: &AppState,
+    user_id: ObjectId,
+    username: &str,
+    email: &str,
+) -> Result<(), CustomError> {
+    let raw_token =
+        create_email_token(state, user_id, EmailTokenPurpose::VerifyEmail, None).await?;
+
+    let values = VerifyEmail::new(username, &user_id.to_hex(), &raw_token);
+
+    state
+        .email_service
+        .send_templated_email(
+            &state.storage_service,
+            "halalho/email-templates/verify-email.html",
+            &values.as_array(),
+            (username, email),
+            "Please verify your email-address",
+        )
+        .await
+}
+
+/// Persist a hashed email token for the user and return the raw token
+async fn create_email_token(
+    state: &AppState,
+    user_id: ObjectId,
+    purpose: EmailTokenPurpose,
+    new_email: Option<String>,
+) -> Result<String, CustomError> {
+    let (raw_token, token_hash) = state.auth_service.generate_email_verification_token()?;
+
+    let exp_secs = match purpose {
+        EmailTokenPurpose::MagicLink => MAGIC_LINK_EXP_SECS,
+        _ => EMAIL_VERIFICATION_EXP_SECS,
+    };
+
+    let new_verif_email_token = NewEmailVerifToken {
+        userId: user_id,
+        tokenHash: token_hash,
+        purpose,
+        newEmail: new_email,
+        expiresAt: Utc
+            .timestamp_opt((now_epoch() + exp_secs as usize) as i64, 0)
+            .single()
+            .ok_or_else(|| {
+                tracing::error!("Error converting timestamp for verif email token expiration");
+                CustomError::TokenCreation
+            })?,
+        createdAt: Utc::now(),
+        usedAt: None,
+    };
+
+    state
+        .verif_email_token_service
+        .create_token(&new_verif_email_token)
+        .await?;
+
+    Ok(raw_token)
+}
+
+/// Seconds until another verification email may be sent, given when the ones of
+/// the last day were sent, newest first. `None` if it may be sent now
+fn resend_wait_secs(sent_at: &[DateTime<Utc>], now: DateTime<Utc>) -> Option<u64> {
+    if let Some(latest) = sent_at.first() {
+        let elapsed = (now - *latest).num_seconds();
+
+        if elapsed < VERIF_EMAIL_RESEND_COOLDOWN_SECS {
+            return Some((VERIF_EMAIL_RESEND_COOLDOWN_SECS - elapsed) as u64);
+        }
+    }
+
+    if sent_at.len() >= VERIF_EMAIL_DAILY_CAP {
+        let oldest = sent_at.last().copied().unwrap_or(now);
+
+        return Some((oldest + Duration::days(1) - now).num_seconds().max(1) as u64);
+    }
+
+    None
+}
+
+fn email_local_part(email: &str) -> &str {
+    email.split('@').next().unwrap_or_default()
+}
+
+/// Generate a new access and refresh token pair for the session of `family_id`
+/// and persist the refresh token. The access token carries the current roles of the user
+///
+/// Every login method and the refresh go through here, so disabled users are rejected here
+pub async fn issue_tokens(
+    state: &AppState,
+    user: &User,
+    family_id: String,
+    session_started_at: DateTime<Utc>,
+    client: &ClientInfo,
+) -> Result<AuthResDto, CustomError> {
+    if user.isDisabled || user.deletedAt.is_some() {
+        tracing::warn!("Disabled user {} tried to get tokens", user.id);
+        return Err(CustomError::AccountDisabled);
+    }
+
+    let permissions = effective_permissions(&user.roles, &user.permissions);
+
+    let (tokens, jti, exp) = state
+        .auth_service
+        .generate_tokens(&user.id.to_hex(), &family_id, &user.roles, &permissions)
+        .map_err(|_| CustomError::TokenCreation)?;
+
+    let expires_at = match Utc.timestamp_opt(exp as i64, 0) {
+        LocalResult::Single(dt) => dt,
+        _ => {
+            tracing::error!("Error converting timestamp");
+            return Err(CustomError::TokenCreation);
+        }
+    };
+
+    let new_refresh_token = NewRefreshToken {
+        userId: user.id,
+        token: jti,
+        familyId: family_id,
+        isRevoked: false,
+        createdAt: Utc::now(),
+        expiresAt: expires_at,
+        usedAt: None,
+        sessionStartedAt: Some(session_started_at),
+        userAgent: client.user_agent.clone(),
+        ip: client.ip.clone(),
+    };
+
+    state
+        .refresh_token_service
+        .create_token(&new_refresh_token)
+        .await?;
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{
+        claims_of, insert_unverified_user, insert_user, start_session, test_state, transport_with,
+        unreachable_state, user_with_password,
+    };
+    use crate::types::role::{Permission, Role};
+    use crate::types::token_transport::{CSRF_HEADER, TRANSPORT_HEADER};
+    use axum::http::header::COOKIE;
+
+    const OLD_PASSWORD: &str = "Old-Pass-41";
+
+    fn user_with_old_password(state: &AppState) -> User {
+        let password_hash = state
+            .auth_service
+            .hash_password(OLD_PASSWORD.to_owned())
+            .unwrap();
+
+        user_with_password(&password_hash)
+    }
+
+    #[test]
+    fn verification_email_can_be_resent_after_the_cooldown() {
+        let now = Utc::now();
+
+        assert_eq!(resend_wait_secs(&[], now), None);
+        assert_eq!(
+            resend_wait_secs(&[now - Duration::seconds(10)], now),
+            Some((VERIF_EMAIL_RESEND_COOLDOWN_SECS - 10) as u64)
+        );
+        assert_eq!(
+            resend_wait_secs(
+                &[now - Duration::seconds(VERIF_EMAIL_RESEND_COOLDOWN_SECS)],
+                now
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn verification_emails_are_capped_per_day() {
+        let now = Utc::now();
+        let sent_at: Vec<DateTime<Utc>> = (1..=VERIF_EMAIL_DAILY_CAP as i64)
+            .map(|hours| now - Duration::hours(hours))
+            .collect();
+
+        assert_eq!(
+            resend_wait_secs(&sent_at, now),
+            Some(3600 * (24 - VERIF_EMAIL_DAILY_CAP as u64))
+        );
+        assert_eq!(resend_wait_secs(&sent_at[1..], now), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn verification_email_is_not_resent_during_the_cooldown() {
+        let (state, _r2, _db) = test_state().await;
+        let user = insert_unverified_user(&state, "alice", OLD_PASSWORD).await;
+        create_email_token(&state, user.id, EmailTokenPurpose::VerifyEmail, None)
+            .await
+            .unwrap();
+
+        let result = resend_verification(
+            claims_of(&user.id.to_hex(), "family-a"),
+            State(state.clone()),
+        )
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(CustomError::TooManyRequests(wait_secs))
+                if wait_secs > 0 && wait_secs <= VERIF_EMAIL_RESEND_COOLDOWN_SECS as u64
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn password_change_can_log_out_the_other_sessions() {
+        let (state, _r2, _db) = test_state().await;
+        let user = insert_user(&state, "alice", OLD_PASSWORD).await;
+        start_session(&state, &user, "family-a").await;
+        start_session(&state, &user, "family-b").await;
+        let user_id = user.id.to_hex();
+
+        let result = change_password(
+            claims_of(&user_id, "family-a"),
+            State(state.clone()),
+            Json(ChangePassDto {
+                current_password: OLD_PASSWORD.to_owned(),
+                new_password: "Tangerine-Rocket-93".to_owned(),
+                revoke_other_sessions: true,
+            }),
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let sessions = state
+            .refresh_token_service
+            .get_active_tokens_by_user(&user_id)
+            .await
+            .unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].familyId, "family-a");
+
+        let stored = state.user_service.get_user_by_id(&user_id).await.unwrap();
+        assert!(
+            state
+                .auth_service
+                .verify_password("Tangerine-Rocket-93".to_owned(), stored.password)
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn changed_password_replaces_the_current_one() {
+        let state = unreachable_state().await;
+        let user = user_with_old_password(&state);
+
+        let password_hash = hash_changed_password(
+            &state,
+            &user,
+            OLD_PASSWORD.to_owned(),
+            "Tangerine-Rocket-93".to_owned(),
+        )
+        .await
+        .unwrap();
+
+        assert!(
+            state
+                .auth_service
+                .verify_password("Tangerine-Rocket-93".to_owned(), password_hash)
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn password_change_needs_the_current_password() {
+        let state = unreachable_state().await;
+        let user = user_with_old_password(&state);
+
+        let result = hash_changed_password(
+            &state,
+            &user,
+            "Wrong-Pass-41".to_owned(),
+            "Tangerine-Rocket-93".to_owned(),
+        )
+        .await;
+
+        assert!(matches!(result, Err(CustomError::WrongCredentials)));
+    }
+
+    #[tokio::test]
+    async fn new_password_must_pass_the_policy() {
+        let state = unreachable_state().await;
+        let user = user_with_old_password(&state);
+
+        let result = hash_changed_password(
+            &state,
+            &user,
+            OLD_PASSWORD.to_owned(),
+            "alice-123".to_owned(),
+        )
+        .await;
+
+        assert!(matches!(result, Err(CustomError::ValidationError(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn email_change_to_a_taken_address_is_refused() {
+        let (state, _r2, _db) = test_state().await;
+        let alice = insert_user(&state, "alice", OLD_PASSWORD).await;
+        insert_user(&state, "bob", OLD_PASSWORD).await;
+
+        let result = request_email_change(
+            claims_of(&alice.id.to_hex(), "family-a"),
+            State(state.clone()),
+            Json(ChangeEmailDto {
+                new_email: "Bob@Example.com".to_owned(),
+                password: OLD_PASSWORD.to_owned(),
+            }),
+        )
+        .await;
+
+        assert!(
+            matches!(result, Err(CustomError::DuplicateKey(email)) if email == "bob@example.com")
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn email_change_is_confirmed_once() {
+        let (state, _r2, _db) = test_state().await;
+        let user = insert_user(&state, "alice", OLD_PASSWORD).await;
+        let user_id = user.id.to_hex();
+        let token = create_email_token(
+            &state,
+            user.id,
+            EmailTokenPurpose::ChangeEmail,
+            Some("alice@new.example".to_owned()),
+        )
+        .await
+        .unwrap();
+
+        let confirm = || {
+            confirm_email_change(
+                State(state.clone()),
+                Query(VerifyEmailDto {
+                    token: token.clone(),
+                    user_id: user_id.clone(),
+                }),
+            )
+        };
+
+        assert!(confirm().await.is_ok());
+        assert!(confirm().await.is_err());
+
+        let stored = state.user_service.get_user_by_id(&user_id).await.unwrap();
+        assert_eq!(stored.email, "alice@new.example");
+    }
+
+    async fn log_in_with_magic_link(
+        state: &Arc<AppState>,
+        user_id: &str,
+        token: &str,
+    ) -> Result<LoginResDto, CustomError> {
+        let (_jar, Json(res)) = magic_link_login(
+            State(state.clone()),
+            ClientInfo {
+                user_agent: None,
+                ip: None,
+            },
+            transport_with(&[]).await,
+            Json(MagicLinkLoginDto {
+                token: token.to_owned(),
+                user_id: user_id.to_owned(),
+            }),
+        )
+        .await?;
+
+        Ok(res)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn magic_link_logs_in_once_and_verifies_the_email() {
+        let (state, _r2, _db) = test_state().await;
+        let user = insert_unverified_user(&state, "alice", OLD_PASSWORD).await;
+        let user_id = user.id.to_hex();
+        let verify_token =
+            create_email_token(&state, user.id, EmailTokenPurpose::VerifyEmail, None)
+                .await
+                .unwrap();
+        let token = create_email_token(&state, user.id, EmailTokenPurpose::MagicLink, None)
+            .await
+            .unwrap();
+
+        // a link of another purpose doesn't log in
+        let result = log_in_with_magic_link(&state, &user_id, &verify_token).await;
+        assert!(result.is_err());
+
+        let first = log_in_with_magic_link(&state, &user_id, &token).await;
+        let second = log_in_with_magic_link(&state, &user_id, &token).await;
+        assert!(matches!(first, Ok(LoginResDto::Tokens(_))));
+        assert!(second.is_err());
+
+        let stored = state.user_service.get_user_by_id(&user_id).await.unwrap();
+        assert!(stored.isEmailVerified);
+    }
+
+    #[tokio::test]
+    async fn cookie_refresh_needs_the_csrf_header() {
+        let transport = transport_with(&[
+            (TRANSPORT_HEADER, "cookie"),
+            (COOKIE, "csrf_token=abc; refresh_token=refresh"),
+            (CSRF_HEADER, "other"),
+        ])
+        .await;
+
+        let result = refresh(
+            State(unreachable_state().await),
+            ClientInfo {
+                user_agent: None,
+                ip: None,
+            },
+            transport,
+            Some(Json(auth_dto::LogoutDto {
+                refresh_token: "body-token".to_owned(),
+            })),
+        )
+        .await;
+
+        assert!(matches!(result, Err(CustomError::Forbidden)));
+    }
+
+    async fn refresh_with(
+        state: &Arc<AppState>,
+        refresh_token: &str,
+    ) -> Result<AuthResDto, CustomError> {
+        let (_jar, Json(res)) = refresh(
+            State(state.clone()),
+            ClientInfo {
+                user_agent: None,
+                ip: None,
+            },
+            transport_with(&[]).await,
+            Some(Json(auth_dto::LogoutDto {
+                refresh_token: refresh_token.to_owned(),
+            })),
+        )
+        .await?;
+
+        Ok(res)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn rotated_refresh_token_is_redeemed_once_in_grace_then_reuse() {
+        let (state, _r2, _db) = test_state().await;
+        let user = insert_user(&state, "alice", OLD_PASSWORD).await;
+        let tokens = start_session(&state, &user, "family-a").await;
+        let refresh_token = tokens.refresh_token.unwrap();
+
+        let jti = state
+            .auth_service
+            .decode_refresh_token(&refresh_token)
+            .unwrap()
+            .jti;
+
+        // a second tab refreshing right after the first still gets a pair
+        assert!(refresh_with(&state, &refresh_token).await.is_ok());
+        assert!(refresh_with(&state, &refresh_token).await.is_ok());
+
+        let third = refresh_with(&state, &refresh_token).await;
+        assert!(matches!(third, Err(CustomError::TokenExpired)));
+        assert!(
+            !state
+                .refresh_token_service
+                .is_family_active("family-a")
+                .await
+                .unwrap()
+        );
+
+        let events = state
+            .security_event_service
+            .get_user_events(&user.id)
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(!events[0].detail.contains(&jti));
+
+        // access tokens of the session stop working along with the chain
+        let claims = claims_of(&user.id.to_hex(), "family-a");
+        assert!(
+            state
+                .access_denylist_service
+                .is_revoked(&claims)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn deleted_account_has_its_purge_scheduled() {
+        let (state, _r2, _db) = test_state().await;
+        let user = insert_user(&state, "alice", OLD_PASSWORD).await;
+        let user_id = user.id.to_hex();
+        state
+            .user_service
+            .update_profile(
+                &user_id,
+                &[("displayName", Some("Alice")), ("bio", Some("Hi"))],
+            )
+            .await
+            .unwrap();
+        state
+            .user_service
+            .update_roles(&user_id, &[Role::Moderator], &[Permission::ManageUsers])
+            .await
+            .unwrap();
+
+        let response = delete_account(
+            claims_of(&user.id.to_hex(), "family-a"),
+            State(state.clone()),
+            Json(DeleteAccountDto {
+                password: OLD_PASSWORD.to_owned(),
+            }),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status_code, 200);
+        assert!(state.user_service.is_user_deleted(&user.id).await.unwrap());
+        let deleted = state.user_service.get_user_by_id(&user_id).await.unwrap();
+        assert!(deleted.displayName.is_none() && deleted.bio.is_none());
+        assert!(deleted.roles.is_empty() && deleted.permissions.is_empty());
+        let purges = state
+            .account_purge_service
+            .get_due_purges(Utc::now() + Duration::days(ACCOUNT_PURGE_AFTER_DAYS + 1))
+            .await
+            .unwrap();
+        assert_eq!(purges.len(), 1);
+        assert_eq!(purges[0].userId, user.id);
+    }
+
+    async fn stored_reset_token(state: &AppState, user_id: ObjectId) -> String {
+        let (raw_token, token_hash) = state.auth_service.generate_reset_pass_token().unwrap();
+
+        state
+            .reset_pass_token_service
+            .create_token(&NewResetPassToken {
+                userId: user_id,
+                tokenHash: token_hash,
+                expiresAt: Utc::now() + Duration::minutes(30),
+                createdAt: Utc::now(),
+                usedAt: None,
+            })
+            .await
+            .unwrap();
+
+        raw_token
+    }
+
+    async fn reset_with(
+        state: &Arc<AppState>,
+        token: &str,
+        new_password: &str,
+    ) -> Result<Json<GeneralResDto>, CustomError> {
+        reset_password(
+            State(state.clone()),
+            Json(ResetPassDto {
+                token: token.to_owned(),
+                new_password: new_password.to_owned(),
+            }),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn reset_link_sets_the_password_once_and_ends_sessions() {
+        let (state, _r2, _db) = test_state().await;
+        let user = insert_user(&state, "alice", OLD_PASSWORD).await;
+        start_session(&state, &user, "family-a").await;
+        let token = stored_reset_token(&state, user.id).await;
+
+        // a rejected password doesn't use up the link
+        let result = reset_with(&state, &token, "alice-123").await;
+        assert!(matches!(result, Err(CustomError::ValidationError(_))));
+
+        let first = reset_with(&state, &token, "Tangerine-Rocket-93").await;
+        let second = reset_with(&state, &token, "Another-Rocket-77").await;
+        assert!(first.is_ok());
+        assert!(second.is_err());
+
+        let user_id = user.id.to_hex();
+        let stored = state.user_service.get_user_by_id(&user_id).await.unwrap();
+        assert!(
+            state
+                .auth_service
+                .verify_password("Tangerine-Rocket-93".to_owned(), stored.password)
+                .is_ok()
+        );
+        let sessions = state
+            .refresh_token_service
+            .get_active_tokens_by_user(&user_id)
+            .await
+            .unwrap();
+        assert!(sessions.is_empty());
+    }
+}
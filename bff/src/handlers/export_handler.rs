@@ -0,0 +1,303 @@
+use crate::{
+    AppState,
+    dtos::{
+        data_export_dto::{
+            ExportEmailTokenDto, ExportLinkedIdentityDto, ExportPasskeyDto, ExportSecurityEventDto,
+            ExportSessionDto, ExportUserDto,
+        },
+        general_res_dto::GeneralResDto,
+    },
+    models::{
+        data_export::{DataExportStatus, NewDataExport},
+        user::User,
+    },
+    services::data_export_service::{DATA_EXPORT_COOLDOWN_HOURS, DATA_EXPORT_LINK_EXP_SECS},
+    types::{
+        claims::Claims,
+        data_export::{DataExportFailed, DataExportReady},
+        error::CustomError,
+    },
+};
+use axum::{Json, extract::State};
+use bson::oid::ObjectId;
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use std::{io::Write, sync::Arc, time::Duration as StdDuration};
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+/// Start an export of everything stored about the logged in user.
+///
+/// The archive is assembled in the background, uploaded to R2 and a signed
+/// download link is emailed to the user once it's ready
+pub async fn request_data_export(
+    claims: Claims,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<GeneralResDto>, CustomError> {
+    let user = state.user_service.get_user_by_id(&claims.sub).await?;
+
+    let since = Utc::now() - Duration::hours(DATA_EXPORT_COOLDOWN_HOURS);
+
+    let recent_exports = state
+        .data_export_service
+        .get_exports_created_since(&user.id, since)
+        .await?;
+
+    if let Some(latest) = recent_exports.first() {
+        return Err(CustomError::TooManyRequests(
+            (latest.createdAt + Duration::hours(DATA_EXPORT_COOLDOWN_HOURS) - Utc::now())
+                .num_seconds()
+                .max(1) as u64,
+        ));
+    }
+
+    let export_id = state
+        .data_export_service
+        .create_export(&NewDataExport {
+            userId: user.id,
+            status: DataExportStatus::Pending,
+            createdAt: Utc::now(),
+        })
+        .await?;
+
+    tokio::spawn({
+        let state = state.clone();
+
+        async move {
+            let result = run_export(&state, &user, &export_id).await;
+
+            let result = match result {
+                Ok(()) => state.data_export_service.complete_export(&export_id).await,
+                Err(err) => {
+                    tracing::error!("Data export {} of {} failed: {:?}", export_id, user.id, err);
+                    notify_export_failed(&state, &user).await;
+                    state.data_export_service.fail_export(&export_id).await
+                }
+            };
+
+            if let Err(err) = result {
+                tracing::error!("Failed to update data export {}: {:?}", export_id, err);
+            }
+        }
+    });
+
+    tracing::info!("User {} has requested a data export", claims.sub);
+
+    Ok(Json(GeneralResDto {
+        status_code: 200,
+        message: "Ok".to_owned(),
+    }))
+}
+
+/// Collect the data of the user, upload it as a zip archive and email the
+/// download link
+async fn run_export(
+    state: &AppState,
+    user: &User,
+    export_id: &ObjectId,
+) -> Result<(), CustomError> {
+    let user_id = user.id.to_hex();
+
+    let sessions: Vec<ExportSessionDto> = state
+        .refresh_token_service
+        .get_tokens_by_user(&user.id)
+        .await?
+        .into_iter()
+        .map(ExportSessionDto::from)
+        .collect();
+
+    let email_tokens: Vec<ExportEmailTokenDto> = state
+        .verif_email_token_service
+        .get_user_tokens(&user.id)
+        .await?
+        .into_iter()
+        .map(ExportEmailTokenDto::from)
+        .collect();
+
+    let security_events: Vec<ExportSecurityEventDto> = state
+        .security_event_service
+        .get_user_events(&user.id)
+        .await?
+        .into_iter()
+        .map(ExportSecurityEventDto::from)
+        .collect();
+
+    let linked_identities: Vec<ExportLinkedIdentityDto> = state
+        .linked_identity_service
+        .get_user_identities(&user.id)
+        .await?
+        .into_iter()
+        .map(ExportLinkedIdentityDto::from)
+        .collect();
+
+    let passkeys: Vec<ExportPasskeyDto> = state
+        .webauthn_credential_service
+        .get_credentials_by_user(&user.id)
+        .await?
+        .into_iter()
+        .map(ExportPasskeyDto::from)
+        .collect();
+
+    let mut files = vec![
+        (
+            "account.json".to_owned(),
+            to_json(&ExportUserDto::from(user.clone()))?,
+        ),
+        ("sessions.json".to_owned(), to_json(&sessions)?),
+        (
+            "email_verifications.json".to_owned(),
+            to_json(&email_tokens)?,
+        ),
+        (
+            "security_events.json".to_owned(),
+            to_json(&security_events)?,
+        ),
+        (
+            "linked_identities.json".to_owned(),
+            to_json(&linked_identities)?,
+        ),
+        ("passkeys.json".to_owned(), to_json(&passkeys)?),
+    ];
+
+    for (name, value) in state
+        .data_export_service
+        .fetch_external_data(&user_id)
+        .await?
+    {
+        files.push((format!("services/{}.json", name), to_json(&value)?));
+    }
+
+    let archive = build_archive(files)?;
+
+    let object_key = format!(
+        "{}exports/{}.zip",
+        state.storage_service.user_prefix(&user_id),
+        export_id.to_hex()
+    );
+
+    state
+        .storage_service
+        .put_object(&object_key, archive, "application/zip")
+        .await?;
+
+    state
+        .data_export_service
+        .set_object_key(export_id, &object_key)
+        .await?;
+
+    let download_url = state
+        .storage_service
+        .presigned_get_url(
+            &object_key,
+            StdDuration::from_secs(DATA_EXPORT_LINK_EXP_SECS),
+        )
+        .await?;
+
+    let values = DataExportReady::new(&user.username, &download_url);
+
+    state
+        .email_service
+        .send_templated_email(
+            &state.storage_service,
+            "halalho/email-templates/data-export.html",
+            &values.as_array(),
+            (&user.username, &user.email),
+            "Your data export is ready",
+        )
+        .await?;
+
+    tracing::info!("Data export {} of {} is ready", export_id, user.id);
+
+    Ok(())
+}
+
+/// Let the user know the export won't arrive, so they can ask again
+async fn notify_export_failed(state: &AppState, user: &User) {
+    let values = DataExportFailed::new(&user.username);
+
+    if let Err(err) = state
+        .email_service
+        .send_templated_email(
+            &state.storage_service,
+            "halalho/email-templates/data-export-failed.html",
+            &values.as_array(),
+            (&user.username, &user.email),
+            "Your data export failed",
+        )
+        .await
+    {
+        tracing::error!(
+            "Failed to send export failure email to {}: {:?}",
+            user.id,
+            err
+        );
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<Vec<u8>, CustomError> {
+    serde_json::to_vec_pretty(value).map_err(|err| {
+        tracing::error!("Error serializing export file: {:?}", err);
+        CustomError::ArchiveError
+    })
+}
+
+fn build_archive(files: Vec<(String, Vec<u8>)>) -> Result<Vec<u8>, CustomError> {
+    let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    for (name, content) in files {
+        zip.start_file(name, options)
+            .and_then(|_| zip.write_all(&content).map_err(Into::into))
+            .map_err(|err| {
+                tracing::error!("Error writing export archive: {:?}", err);
+                CustomError::ArchiveError
+            })?;
+    }
+
+    let cursor = zip.finish().map_err(|err| {
+        tracing::error!("Error finishing export archive: {:?}", err);
+        CustomError::ArchiveError
+    })?;
+
+    Ok(cursor.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{insert_user, test_state};
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn archive_is_recorded_even_if_the_email_fails() {
+        // the mock R2 holds no email templates, so the export fails after the upload
+        let (state, r2, _db) = test_state().await;
+        let user = insert_user(&state, "alice", "Correct-Horse-9").await;
+        let export_id = state
+            .data_export_service
+            .create_export(&NewDataExport {
+                userId: user.id,
+                status: DataExportStatus::Pending,
+                createdAt: Utc::now(),
+            })
+            .await
+            .unwrap();
+
+        assert!(run_export(&state, &user, &export_id).await.is_err());
+        state
+            .data_export_service
+            .fail_export(&export_id)
+            .await
+            .unwrap();
+
+        let stale = state
+            .data_export_service
+            .get_stale_exports(Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(stale.len(), 1);
+        assert_eq!(
+            stale[0].objectKey.as_deref(),
+            r2.keys().first().map(String::as_str)
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use crate::{
+    AppState,
+    dtos::introspection_dto::{IntrospectReqDto, IntrospectResDto},
+    types::{error::CustomError, service_client::ServiceClient},
+};
+use axum::{Form, Json, extract::State};
+use chrono::Utc;
+use std::sync::Arc;
+
+/// RFC 7662 token introspection for internal services.
+///
+/// Reports whether an access or refresh token is active, which besides the
+/// signature and expiry requires the session to be alive and the user to be
+/// enabled. Unknown and invalid tokens are simply inactive
+pub async fn introspect(
+    service: ServiceClient,
+    State(state): State<Arc<AppState>>,
+    Form(payload): Form<IntrospectReqDto>,
+) -> Result<Json<IntrospectResDto>, CustomError> {
+    tracing::debug!(
+        "Service {} introspects a token, hint {:?}",
+        service.client_id,
+        payload.token_type_hint
+    );
+
+    if payload.token.is_empty() {
+        return Ok(Json(IntrospectResDto::inactive()));
+    }
+
+    // Access and refresh tokens have distinct audiences, so at most one of the
+    // two decodes whatever the hint says. It only spares decoding the other first
+    let res = if payload.token_type_hint.as_deref() == Some("access_token") {
+        match introspect_access_token(&state, &payload.token).await? {
+            Some(res) => Some(res),
+            None => introspect_refresh_token(&state, &payload.token).await?,
+        }
+    } else {
+        match introspect_refresh_token(&state, &payload.token).await? {
+            Some(res) => Some(res),
+            None => introspect_access_token(&state, &payload.token).await?,
+        }
+    };
+
+    Ok(Json(res.unwrap_or_else(IntrospectResDto::inactive)))
+}
+
+/// `None` if `token` isn't one of our refresh tokens
+async fn introspect_refresh_token(
+    state: &AppState,
+    token: &str,
+) -> Result<Option<IntrospectResDto>, CustomError> {
+    let Ok(claims) = state.auth_service.decode_refresh_token(token) else {
+        return Ok(None);
+    };
+
+    let stored = match state
+        .refresh_token_service
+        .get_token_by_jti(&claims.jti)
+        .await
+    {
+        Ok(stored) => stored,
+        Err(CustomError::NotFoundError(_)) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    if stored.isRevoked
+        || stored.expiresAt < Utc::now()
+        || !is_user_active(state, &claims.sub).await?
+    {
+        return Ok(Some(IntrospectResDto::inactive()));
+    }
+
+    Ok(Some(IntrospectResDto {
+        active: true,
+        token_type: Some("refresh_token".to_owned()),
+        sub: Some(claims.sub),
+        exp: Some(claims.exp),
+        iss: Some(claims.iss),
+        aud: Some(claims.aud),
+        sid: Some(claims.fam).filter(|fam| !fam.is_empty()),
+        ..Default::default()
+    }))
+}
+
+/// `None` if `token` isn't a valid access token
+async fn introspect_access_token(
+    state: &AppState,
+    token: &str,
+) -> Result<Option<IntrospectResDto>, CustomError> {
+    let Ok(claims) = state.auth_service.decode_access_token(token) else {
+        return Ok(None);
+    };
+
+    // the denylist check of the `Claims` extractor, which is all a request needs.
+    // Introspection is rare enough to also look the user and session up, in case
+    // a revocation entry went missing
+    match claims.ensure_not_revoked(state).await {
+        Ok(()) => {}
+        Err(err @ CustomError::MongoError(_)) => return Err(err),
+        Err(_) => return Ok(Some(IntrospectResDto::inactive())),
+    }
+
+    if !is_user_active(state, &claims.sub).await?
+        || !state
+            .refresh_token_service
+            .is_family_active(&claims.sid)
+            .await?
+    {
+        return Ok(Some(IntrospectResDto::inactive()));
+    }
+
+    Ok(Some(IntrospectResDto {
+        active: true,
+        token_type: Some("access_token".to_owned()),
+        sub: Some(claims.sub),
+        exp: Some(claims.exp),
+        iss: Some(claims.iss),
+        aud: Some(claims.aud),
+        sid: Some(claims.sid),
+        roles: Some(claims.roles),
+        perms: Some(claims.perms),
+    }))
+}
+
+async fn is_user_active(state: &AppState, user_id: &str) -> Result<bool, CustomError> {
+    match state.user_service.is_user_disabled(user_id).await {
+        Ok(disabled) => Ok(!disabled),
+        Err(CustomError::NotFoundError(_) | CustomError::InvalidIDError(_)) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{insert_user, start_session, test_state, unreachable_state};
+
+    async fn introspect_token(state: Arc<AppState>, token: String) -> IntrospectResDto {
+        introspect_with_hint(state, token, None).await
+    }
+
+    async fn introspect_with_hint(
+        state: Arc<AppState>,
+        token: String,
+        hint: Option<&str>,
+    ) -> IntrospectResDto {
+        let Json(res) = introspect(
+            ServiceClient {
+                client_id: "post_service".to_owned(),
+            },
+            State(state),
+            Form(IntrospectReqDto {
+                token,
+                token_type_hint: hint.map(str::to_owned),
+            }),
+        )
+        .await
+        .unwrap();
+
+        res
+    }
+
+    #[tokio::test]
+    async fn tokens_we_did_not_issue_are_inactive() {
+        let state = unreachable_state().await;
+
+        for token in ["".to_owned(), "not-a-jwt".to_owned()] {
+            let res = introspect_token(state.clone(), token).await;
+
+            assert!(!res.active);
+            assert!(res.sub.is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn mfa_tokens_are_inactive() {
+        let state = unreachable_state().await;
+        let mfa_token = state.auth_service.generate_mfa_token("user-1").unwrap();
+
+        let res = introspect_token(state, mfa_token).await;
+
+        assert!(!res.active);
+    }
+
+    #[tokio::test]
+    async fn hinted_tokens_we_did_not_issue_are_inactive() {
+        let state = unreachable_state().await;
+        let mfa_token = state.auth_service.generate_mfa_token("user-1").unwrap();
+
+        for hint in ["access_token", "refresh_token", "other"] {
+            let res = introspect_with_hint(state.clone(), mfa_token.clone(), Some(hint)).await;
+
+            assert!(!res.active);
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn access_token_is_active_exactly_while_the_bff_accepts_it() {
+        let (state, _r2, _db) = test_state().await;
+        let user = insert_user(&state, "alice", "Correct-Horse-9").await;
+        let tokens = start_session(&state, &user, "family-a").await;
+        let claims = state
+            .auth_service
+            .decode_access_token(&tokens.access_token)
+            .unwrap();
+
+        for hint in [None, Some("access_token"), Some("refresh_token")] {
+            let res = introspect_with_hint(state.clone(), tokens.access_token.clone(), hint).await;
+            assert!(res.active);
+            assert_eq!(res.token_type.as_deref(), Some("access_token"));
+        }
+        assert!(claims.ensure_not_revoked(&state).await.is_ok());
+
+        // ending the session adds a denylist entry, which the extractor relies on
+        state
+            .refresh_token_service
+            .revoke_family("family-a")
+            .await
+            .unwrap();
+        state
+            .access_denylist_service
+            .revoke_session("family-a")
+            .await
+            .unwrap();
+
+        let res = introspect_token(state.clone(), tokens.access_token.clone()).await;
+        assert!(!res.active);
+        assert!(matches!(
+            claims.ensure_not_revoked(&state).await,
+            Err(CustomError::InvalidToken)
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn access_token_of_ended_session_is_inactive_without_a_denylist_entry() {
+        let (state, _r2, _db) = test_state().await;
+        let user = insert_user(&state, "alice", "Correct-Horse-9").await;
+        let tokens = start_session(&state, &user, "family-a").await;
+
+        state
+            .refresh_token_service
+            .revoke_family("family-a")
+            .await
+            .unwrap();
+
+        let res = introspect_token(state.clone(), tokens.access_token.clone()).await;
+        assert!(!res.active);
+    }
+}
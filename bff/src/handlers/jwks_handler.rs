@@ -0,0 +1,37 @@
+use crate::types::keys::KEYS;
+use axum::{Json, http::header::CACHE_CONTROL, response::IntoResponse};
+
+/// Public keys which verify our tokens, so other services can check them
+/// without the private key. Retired keys stay listed until their tokens expire
+pub async fn jwks() -> impl IntoResponse {
+    (
+        [(CACHE_CONTROL, "public, max-age=300")],
+        Json(KEYS.jwks.clone()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::init_env;
+    use axum::body::to_bytes;
+    use jsonwebtoken::jwk::JwkSet;
+
+    #[tokio::test]
+    async fn jwks_publishes_the_signing_key() {
+        init_env();
+
+        let response = jwks().await.into_response();
+
+        assert_eq!(
+            response.headers()[CACHE_CONTROL].to_str().unwrap(),
+            "public, max-age=300"
+        );
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let jwks: JwkSet = serde_json::from_slice(&body).unwrap();
+        let kid = KEYS.header().kid.unwrap();
+
+        assert!(jwks.find(&kid).is_some());
+    }
+}
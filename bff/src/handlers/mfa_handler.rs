@@ -0,0 +1,433 @@
+use crate::{
+    AppState,
+    dtos::{
+        auth_dto::AuthResDto,
+        general_res_dto::GeneralResDto,
+        mfa_dto::{
+            DisableTotpDto, RecoveryCodesResDto, TotpCodeDto, TotpEnrollResDto, TotpLoginDto,
+        },
+    },
+    handlers::auth_handler::issue_tokens,
+    models::user::User,
+    services::login_attempt_service::LoginAttemptService,
+    types::{
+        claims::Claims, client_info::ClientInfo, error::CustomError,
+        token_transport::TokenTransport,
+    },
+};
+use axum::{Json, extract::State};
+use axum_extra::extract::CookieJar;
+use chrono::Utc;
+use std::sync::Arc;
+
+/// Start 2FA enrollment of the logged in user, the secret only becomes
+/// active after a first code is confirmed
+pub async fn enroll_totp(
+    claims: Claims,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<TotpEnrollResDto>, CustomError> {
+    let user = state.user_service.get_user_by_id(&claims.sub).await?;
+
+    if user.isTotpEnabled {
+        return Err(CustomError::DuplicateKey("TOTP".to_owned()));
+    }
+
+    let secret = state.totp_service.generate_secret()?;
+    let (secret_base32, otpauth_uri) = state.totp_service.enrollment(&secret, &user.email)?;
+
+    let encrypted = state.totp_service.encrypt_secret(&secret, &claims.sub)?;
+
+    state
+        .user_service
+        .set_pending_totp_secret(&claims.sub, &encrypted)
+        .await?;
+
+    Ok(Json(TotpEnrollResDto {
+        secret: secret_base32,
+        otpauth_uri,
+    }))
+}
+
+/// Confirm enrollment with a first code, which enables 2FA and returns
+/// the recovery codes. They are only shown this one time
+pub async fn confirm_totp(
+    claims: Claims,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<TotpCodeDto>,
+) -> Result<Json<RecoveryCodesResDto>, CustomError> {
+    if payload.code.is_empty() {
+        return Err(CustomError::MissingCredentials);
+    }
+
+    let user = state.user_service.get_user_by_id(&claims.sub).await?;
+
+    let pending = user
+        .pendingTotpSecret
+        .ok_or(CustomError::NotFoundError("pending TOTP secret".to_owned()))?;
+
+    let secret = state.totp_service.decrypt_secret(&pending, &claims.sub)?;
+
+    let step = state
+        .totp_service
+        .verify_code(&secret, &payload.code, None)?
+        .ok_or(CustomError::WrongCredentials)?;
+
+    let recovery_codes = state.totp_service.generate_recovery_codes()?;
+
+    let recovery_code_hashes = recovery_codes
+        .iter()
+        .map(|code| state.auth_service.hash_raw_token(code))
+        .collect();
+
+    state
+        .user_service
+        .enable_totp(&claims.sub, &pending, recovery_code_hashes, step as i64)
+        .await?;
+
+    tracing::info!("User {} has enabled 2FA", claims.sub);
+
+    Ok(Json(RecoveryCodesResDto { recovery_codes }))
+}
+
+/// Finish a login which is waiting for the second factor
+pub async fn verify_totp_login(
+    State(state): State<Arc<AppState>>,
+    client: ClientInfo,
+    transport: TokenTransport,
+    Json(payload): Json<TotpLoginDto>,
+) -> Result<(CookieJar, Json<AuthResDto>), CustomError> {
+    if payload.mfa_token.is_empty() || payload.code.is_empty() {
+        return Err(CustomError::MissingCredentials);
+    }
+
+    let mfa_claims = state.auth_service.decode_mfa_token(&payload.mfa_token)?;
+
+    let user = state.user_service.get_user_by_id(&mfa_claims.sub).await?;
+
+    let attempt_key = LoginAttemptService::user_key(&user.id.to_hex());
+
+    state
+        .login_attempt_service
+        .ensure_can_attempt(&attempt_key)
+        .await?;
+
+    let Some(factor) = check_second_factor(&state, &user, &payload.code)? else {
+        state
+            .login_attempt_service
+            .record_failure(&attempt_key)
+            .await?;
+
+        return Err(CustomError::WrongCredentials);
+    };
+
+    // One password step mints one session. The token is only used up by a good code,
+    // and only the request which used it up may spend the code, so a replayed token
+    // can't burn a recovery code or a TOTP step
+    if !state
+        .mfa_token_service
+        .consume(&mfa_claims.jti, mfa_claims.exp)
+        .await?
+    {
+        return Err(CustomError::InvalidToken);
+    }
+
+    // Fails when the same code was spent concurrently with another token
+    if !spend_second_factor(&state, &user, &factor).await? {
+        return Err(CustomError::WrongCredentials);
+    }
+
+    state.login_attempt_service.reset(&attempt_key).await?;
+
+    tracing::info!("User {} has logged in with 2FA", user.email);
+
+    let family_id = state.auth_service.new_token_family();
+
+    let tokens = issue_tokens(&state, &user, family_id, Utc::now(), &client).await?;
+
+    let (jar, tokens) = transport.deliver(tokens)?;
+
+    Ok((jar, Json(tokens)))
+}
+
+/// Turn off 2FA, requires both the password and a current code
+pub async fn disable_totp(
+    claims: Claims,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<DisableTotpDto>,
+) -> Result<Json<GeneralResDto>, CustomError> {
+    if payload.password.is_empty() || payload.code.is_empty() {
+        return Err(CustomError::MissingCredentials);
+    }
+
+    let user = state.user_service.get_user_by_id(&claims.sub).await?;
+
+    if !user.isTotpEnabled {
+        return Err(CustomError::NotFoundError("TOTP".to_owned()));
+    }
+
+    // Counted like the second login step, so the code can't be guessed without limit
+    let attempt_key = LoginAttemptService::user_key(&claims.sub);
+
+    state
+        .login_attempt_service
+        .ensure_can_attempt(&attempt_key)
+        .await?;
+
+    let password_ok = state
+        .auth_service
+        .verify_password(payload.password, user.password.clone())
+        .is_ok();
+
+    let factor = check_second_factor(&state, &user, &payload.code)?;
+
+    let verified = match &factor {
+        Some(factor) if password_ok => spend_second_factor(&state, &user, factor).await?,
+        _ => false,
+    };
+
+    if !verified {
+        state
+            .login_attempt_service
+            .record_failure(&attempt_key)
+            .await?;
+
+        return Err(CustomError::WrongCredentials);
+    }
+
+    state.login_attempt_service.reset(&attempt_key).await?;
+
+    state.user_service.disable_totp(&claims.sub).await?;
+
+    tracing::info!("User {} has disabled 2FA", claims.sub);
+
+    Ok(Json(GeneralResDto {
+        status_code: 200,
+        message: "Ok".to_owned(),
+    }))
+}
+
+/// A second factor which was checked but not spent yet
+enum SecondFactor {
+    TotpStep(u64),
+    RecoveryCode { hash: String },
+}
+
+/// Check a TOTP code, or otherwise look for a matching recovery code.
+/// Nothing is used up yet, see `spend_second_factor`
+fn check_second_factor(
+    state: &AppState,
+    user: &User,
+    code: &str,
+) -> Result<Option<SecondFactor>, CustomError> {
+    let encrypted = user.totpSecret.as_ref().ok_or(CustomError::InvalidToken)?;
+
+    let secret = state
+        .totp_service
+        .decrypt_secret(encrypted, &user.id.to_hex())?;
+
+    let last_step = user.totpLastStep.map(|step| step as u64);
+
+    if let Some(step) = state.totp_service.verify_code(&secret, code, last_step)? {
+        return Ok(Some(SecondFactor::TotpStep(step)));
+    }
+
+    let hash = state
+        .auth_service
+        .hash_raw_token(&state.totp_service.normalize_recovery_code(code));
+
+    Ok(user
+        .totpRecoveryCodes
+        .contains(&hash)
+        .then_some(SecondFactor::RecoveryCode { hash }))
+}
+
+/// Record the TOTP step or consume the recovery code, returns false if a concurrent
+/// request spent it first
+async fn spend_second_factor(
+    state: &AppState,
+    user: &User,
+    factor: &SecondFactor,
+) -> Result<bool, CustomError> {
+    let user_id = user.id.to_hex();
+
+    match factor {
+        SecondFactor::TotpStep(step) => {
+            state
+                .user_service
+                .record_totp_step(&user_id, *step as i64)
+                .await
+        }
+        SecondFactor::RecoveryCode { hash } => {
+            let consumed = state
+                .user_service
+                .consume_recovery_code(&user_id, hash)
+                .await?;
+
+            if consumed {
+                tracing::info!("User {} used a recovery code", user.id);
+            }
+
+            Ok(consumed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        services::login_attempt_service::LOGIN_DELAY_AFTER_FAILURES,
+        test_support::{claims_of, insert_user, test_state, transport_with},
+    };
+
+    const PASSWORD: &str = "Correct-Horse-9";
+
+    /// Store a user with 2FA enabled and return them with their recovery codes
+    async fn insert_totp_user(state: &AppState) -> (User, Vec<String>) {
+        let user = insert_user(state, "alice", PASSWORD).await;
+        let user_id = user.id.to_hex();
+
+        let secret = state.totp_service.generate_secret().unwrap();
+        let encrypted = state
+            .totp_service
+            .encrypt_secret(&secret, &user_id)
+            .unwrap();
+        let recovery_codes = state.totp_service.generate_recovery_codes().unwrap();
+        let recovery_code_hashes = recovery_codes
+            .iter()
+            .map(|code| state.auth_service.hash_raw_token(code))
+            .collect();
+
+        state
+            .user_service
+            .enable_totp(&user_id, &encrypted, recovery_code_hashes, 0)
+            .await
+            .unwrap();
+
+        let user = state.user_service.get_user_by_id(&user_id).await.unwrap();
+
+        (user, recovery_codes)
+    }
+
+    async fn log_in_with_code(
+        state: &Arc<AppState>,
+        mfa_token: &str,
+        code: &str,
+    ) -> Result<AuthResDto, CustomError> {
+        let (_jar, Json(res)) = verify_totp_login(
+            State(state.clone()),
+            ClientInfo {
+                user_agent: None,
+                ip: None,
+            },
+            transport_with(&[]).await,
+            Json(TotpLoginDto {
+                mfa_token: mfa_token.to_owned(),
+                code: code.to_owned(),
+            }),
+        )
+        .await?;
+
+        Ok(res)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn used_mfa_token_does_not_burn_a_recovery_code() {
+        let (state, _r2, _db) = test_state().await;
+        let (user, recovery_codes) = insert_totp_user(&state).await;
+        let user_id = user.id.to_hex();
+
+        let mfa_token = state.auth_service.generate_mfa_token(&user_id).unwrap();
+        assert!(
+            log_in_with_code(&state, &mfa_token, &recovery_codes[0])
+                .await
+                .is_ok()
+        );
+
+        let result = log_in_with_code(&state, &mfa_token, &recovery_codes[1]).await;
+        assert!(matches!(result, Err(CustomError::InvalidToken)));
+
+        // the code offered with the replayed token still works
+        let mfa_token = state.auth_service.generate_mfa_token(&user_id).unwrap();
+        assert!(
+            log_in_with_code(&state, &mfa_token, &recovery_codes[1])
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn wrong_code_does_not_use_up_the_mfa_token() {
+        let (state, _r2, _db) = test_state().await;
+        let (user, recovery_codes) = insert_totp_user(&state).await;
+
+        let mfa_token = state
+            .auth_service
+            .generate_mfa_token(&user.id.to_hex())
+            .unwrap();
+
+        let result = log_in_with_code(&state, &mfa_token, "wrong-code").await;
+        assert!(matches!(result, Err(CustomError::WrongCredentials)));
+
+        assert!(
+            log_in_with_code(&state, &mfa_token, &recovery_codes[0])
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn concurrent_replay_spends_a_single_recovery_code() {
+        let (state, _r2, _db) = test_state().await;
+        let (user, recovery_codes) = insert_totp_user(&state).await;
+        let user_id = user.id.to_hex();
+
+        let mfa_token = state.auth_service.generate_mfa_token(&user_id).unwrap();
+
+        let (first, second) = tokio::join!(
+            log_in_with_code(&state, &mfa_token, &recovery_codes[0]),
+            log_in_with_code(&state, &mfa_token, &recovery_codes[1])
+        );
+        assert!(first.is_ok() ^ second.is_ok());
+
+        let stored = state.user_service.get_user_by_id(&user_id).await.unwrap();
+        assert_eq!(stored.totpRecoveryCodes.len(), recovery_codes.len() - 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn wrong_codes_to_disable_totp_are_counted() {
+        let (state, _r2, _db) = test_state().await;
+        let (user, _recovery_codes) = insert_totp_user(&state).await;
+        let claims = claims_of(&user.id.to_hex(), "family-a");
+
+        let disable_with = |code: &str| {
+            disable_totp(
+                claims.clone(),
+                State(state.clone()),
+                Json(DisableTotpDto {
+                    password: PASSWORD.to_owned(),
+                    code: code.to_owned(),
+                }),
+            )
+        };
+
+        for _ in 0..LOGIN_DELAY_AFTER_FAILURES {
+            let result = disable_with("wrong-code").await;
+            assert!(matches!(result, Err(CustomError::WrongCredentials)));
+        }
+
+        let result = disable_with("wrong-code").await;
+        assert!(matches!(result, Err(CustomError::TooManyRequests(_))));
+
+        let stored = state
+            .user_service
+            .get_user_by_id(&user.id.to_hex())
+            .await
+            .unwrap();
+        assert!(stored.isTotpEnabled);
+    }
+}
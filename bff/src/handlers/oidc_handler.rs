@@ -0,0 +1,315 @@
+use crate::{
+    AppState,
+    dtos::{
+        auth_dto::{LoginResDto, MfaPendingResDto},
+        oidc_dto::{OidcCallbackDto, OidcStartResDto},
+    },
+    handlers::auth_handler::issue_tokens,
+    models::{
+        linked_identity::{NewLinkedIdentity, OidcAuthRequest},
+        user::NewUser,
+    },
+    services::oidc_service::{OIDC_AUTH_REQUEST_EXP_SECS, OidcIdentity},
+    types::{
+        client_info::ClientInfo,
+        error::CustomError,
+        token_transport::{TokenTransport, secure_cookie},
+    },
+};
+use aws_lc_rs::constant_time::verify_slices_are_equal;
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use axum_extra::extract::{CookieJar, cookie::Cookie};
+use bson::oid::ObjectId;
+use chrono::{Duration, Utc};
+use std::sync::Arc;
+
+/// Holds the `state` of the flow started by this browser
+const OIDC_STATE_COOKIE: &str = "oidc_state";
+
+/// Start an authorization code flow with the provider, the frontend
+/// redirects the browser to the returned URL
+pub async fn start_oidc_login(
+    State(state): State<Arc<AppState>>,
+    Path(provider_name): Path<String>,
+    jar: CookieJar,
+) -> Result<(CookieJar, Json<OidcStartResDto>), CustomError> {
+    let provider = state.oidc_service.provider(&provider_name)?;
+
+    let params = state.oidc_service.new_auth_params()?;
+
+    let authorization_url = state
+        .oidc_service
+        .authorization_url(provider, &params)
+        .await?;
+
+    let jar = jar.add(state_cookie(params.state.clone()));
+
+    state
+        .linked_identity_service
+        .create_auth_request(&OidcAuthRequest {
+            state: params.state,
+            provider: provider_name,
+            codeVerifier: params.code_verifier,
+            nonce: params.nonce,
+            expiresAt: Utc::now() + Duration::seconds(OIDC_AUTH_REQUEST_EXP_SECS),
+        })
+        .await?;
+
+    Ok((jar, Json(OidcStartResDto { authorization_url })))
+}
+
+/// Finish the flow with the code the provider redirected back with
+pub async fn oidc_callback(
+    State(state): State<Arc<AppState>>,
+    client: ClientInfo,
+    transport: TokenTransport,
+    jar: CookieJar,
+    Json(payload): Json<OidcCallbackDto>,
+) -> Result<(CookieJar, Json<LoginResDto>), CustomError> {
+    if payload.code.is_empty() || payload.state.is_empty() {
+        return Err(CustomError::MissingCredentials);
+    }
+
+    // Otherwise an attacker could finish their own flow in the victim's browser
+    // and log the victim into the attacker's account
+    verify_state_binding(&jar, &payload.state)?;
+
+    let request = state
+        .linked_identity_service
+        .consume_auth_request(&payload.state)
+        .await?;
+
+    let provider = state.oidc_service.provider(&request.provider)?;
+
+    let identity = state
+        .oidc_service
+        .exchange_code(
+            provider,
+            &payload.code,
+            &request.codeVerifier,
+            &request.nonce,
+        )
+        .await?;
+
+    let linked = state
+        .linked_identity_service
+        .get_identity(&request.provider, &identity.subject)
+        .await?;
+
+    let user_id = match linked {
+        Some(linked) => {
+            let email = identity.email.as_deref().unwrap_or(&linked.email);
+
+            state
+                .linked_identity_service
+                .update_last_login(&linked.id, email)
+                .await?;
+
+            linked.userId
+        }
+        None => link_identity(&state, &request.provider, &identity).await?,
+    };
+
+    let user = state.user_service.get_user_by_id(&user_id.to_hex()).await?;
+
+    let used_state = state_cookie(String::new());
+
+    // The provider replaces the password, not the second factor
+    if user.isTotpEnabled {
+        let mfa_token = state.auth_service.generate_mfa_token(&user_id.to_hex())?;
+
+        return Ok((
+            jar.remove(used_state),
+            Json(LoginResDto::MfaPending(MfaPendingResDto::new(mfa_token))),
+        ));
+    }
+
+    tracing::info!("User {} has logged in with {}", user_id, request.provider);
+
+    let family_id = state.auth_service.new_token_family();
+
+    let tokens = issue_tokens(&state, &user, family_id, Utc::now(), &client).await?;
+
+    let (jar, tokens) = transport.deliver(tokens)?;
+
+    Ok((jar.remove(used_state), Json(LoginResDto::Tokens(tokens))))
+}
+
+fn state_cookie(value: String) -> Cookie<'static> {
+    let max_age = time::Duration::seconds(OIDC_AUTH_REQUEST_EXP_SECS);
+
+    secure_cookie(OIDC_STATE_COOKIE, value, "/auth/oidc", true, max_age)
+}
+
+/// The callback has to come from the browser which started the flow
+fn verify_state_binding(jar: &CookieJar, state: &str) -> Result<(), CustomError> {
+    let bound = jar
+        .get(OIDC_STATE_COOKIE)
+        .map(|c| c.value())
+        .unwrap_or_default();
+
+    if bound.is_empty() || verify_slices_are_equal(bound.as_bytes(), state.as_bytes()).is_err() {
+        tracing::warn!("OIDC callback state isn't bound to this browser");
+        return Err(CustomError::Forbidden);
+    }
+
+    Ok(())
+}
+
+/// Link a first time identity to the user owning its verified email,
+/// or create a new user for it
+async fn link_identity(
+    state: &AppState,
+    provider: &str,
+    identity: &OidcIdentity,
+) -> Result<ObjectId, CustomError> {
+    let email = match &identity.email {
+        Some(email) if identity.email_verified => email.clone(),
+        _ => {
+            tracing::warn!("{} identity has no verified email", provider);
+            return Err(CustomError::WrongCredentials);
+        }
+    };
+
+    let user_id = match state.user_service.get_user_by_email(&email).await {
+        // Someone else may have registered the address without proving they own it
+        Ok(user) if !user.isEmailVerified => {
+            return Err(CustomError::DuplicateKey(email));
+        }
+        Ok(user) => user.id,
+        Err(CustomError::NotFoundError(_)) => create_oidc_user(state, &email).await?,
+        Err(err) => return Err(err),
+    };
+
+    state
+        .linked_identity_service
+        .create_identity(&NewLinkedIdentity {
+            userId: user_id,
+            provider: provider.to_owned(),
+            subject: identity.subject.clone(),
+            email,
+            createdAt: Utc::now(),
+            lastLoginAt: Utc::now(),
+        })
+        .await?;
+
+    Ok(user_id)
+}
+
+/// Users created by a provider get a random password, they can set one
+/// through the reset password flow
+async fn create_oidc_user(state: &AppState, email: &str) -> Result<ObjectId, CustomError> {
+    let (random_password, _) = state.auth_service.generate_reset_pass_token()?;
+
+    let password_hash = state
+        .auth_service
+        .hash_password(random_password)
+        .map_err(|_| CustomError::HashError)?;
+
+    let local_part: String = email
+        .split('@')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .take(20)
+        .collect();
+
+    let suffix = ObjectId::new().to_hex();
+
+    let user = NewUser {
+        email: email.to_owned(),
+        username: format!("{}_{}", local_part, &suffix[suffix.len() - 6..]),
+        password: password_hash,
+        isEmailVerified: true,
+        lastLoginAt: Utc::now(),
+        createdAt: Utc::now(),
+        updatedAt: Utc::now(),
+    };
+
+    state.user_service.create_user(&user).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dtos::auth_dto::RegisterReqDto,
+        handlers::auth_handler::register,
+        test_support::{test_state, transport_with},
+        utils::email::normalize_email,
+    };
+
+    #[test]
+    fn state_bound_to_the_browser_is_accepted() {
+        let jar = CookieJar::new().add(Cookie::new(OIDC_STATE_COOKIE, "state-1"));
+
+        assert!(verify_state_binding(&jar, "state-1").is_ok());
+    }
+
+    #[test]
+    fn state_from_another_browser_is_rejected() {
+        let jar = CookieJar::new().add(Cookie::new(OIDC_STATE_COOKIE, "state-1"));
+
+        assert!(matches!(
+            verify_state_binding(&jar, "state-2"),
+            Err(CustomError::Forbidden)
+        ));
+        assert!(matches!(
+            verify_state_binding(&CookieJar::new(), "state-2"),
+            Err(CustomError::Forbidden)
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn identity_is_linked_to_the_user_registered_with_mixed_case() {
+        let (state, _r2, _db) = test_state().await;
+
+        let (_jar, Json(registered)) = register(
+            State(state.clone()),
+            ClientInfo {
+                user_agent: None,
+                ip: None,
+            },
+            transport_with(&[]).await,
+            Json(RegisterReqDto {
+                email: " Alice@Example.com ".to_owned(),
+                username: "alice".to_owned(),
+                password: "correct-Horse-battery-9".to_owned(),
+            }),
+        )
+        .await
+        .unwrap();
+        let user = state
+            .user_service
+            .get_user_by_email("alice@example.com")
+            .await
+            .unwrap();
+        let claims = state
+            .auth_service
+            .decode_access_token(&registered.access_token)
+            .unwrap();
+        assert_eq!(claims.sub, user.id.to_hex());
+        state
+            .user_service
+            .update_email_verified(&user.id.to_hex())
+            .await
+            .unwrap();
+
+        let identity = OidcIdentity {
+            subject: "subject-1".to_owned(),
+            email: Some(normalize_email("ALICE@example.COM")),
+            email_verified: true,
+        };
+
+        assert_eq!(
+            link_identity(&state, "google", &identity).await.unwrap(),
+            user.id
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use crate::{
+    AppState,
+    dtos::{
+        auth_dto::AuthResDto,
+        general_res_dto::GeneralResDto,
+        passkey_dto::{
+            PasskeyAuthenticationDto, PasskeyCreationOptionsDto, PasskeyRegistrationDto,
+            PasskeyRequestOptionsDto,
+        },
+    },
+    handlers::auth_handler::issue_tokens,
+    models::webauthn_credential::{NewWebauthnCredential, WebauthnCeremony, WebauthnChallenge},
+    services::webauthn_service::{PASSKEY_CHALLENGE_EXP_SECS, normalize_b64url},
+    types::{
+        claims::Claims, client_info::ClientInfo, error::CustomError,
+        token_transport::TokenTransport,
+    },
+};
+use axum::{Json, extract::State};
+use axum_extra::extract::CookieJar;
+use chrono::{Duration, Utc};
+use std::sync::Arc;
+
+/// Start registering a passkey for the logged in user
+pub async fn start_passkey_registration(
+    claims: Claims,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<PasskeyCreationOptionsDto>, CustomError> {
+    let user = state.user_service.get_user_by_id(&claims.sub).await?;
+
+    let existing = state
+        .webauthn_credential_service
+        .get_credentials_by_user(&user.id)
+        .await?;
+
+    let challenge = state.webauthn_service.generate_challenge()?;
+
+    state
+        .webauthn_credential_service
+        .create_challenge(&WebauthnChallenge {
+            challenge: challenge.clone(),
+            ceremony: WebauthnCeremony::Registration,
+            userId: Some(user.id),
+            expiresAt: Utc::now() + Duration::seconds(PASSKEY_CHALLENGE_EXP_SECS),
+        })
+        .await?;
+
+    let options = state.webauthn_service.creation_options(
+        challenge,
+        &user.id,
+        &user.username,
+        existing.into_iter().map(|c| c.credentialId).collect(),
+    );
+
+    Ok(Json(options))
+}
+
+/// Verify the new credential and store it
+pub async fn finish_passkey_registration(
+    claims: Claims,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<PasskeyRegistrationDto>,
+) -> Result<Json<GeneralResDto>, CustomError> {
+    let verified = state.webauthn_service.verify_registration(&payload)?;
+
+    let challenge = state
+        .webauthn_credential_service
+        .consume_challenge(&verified.challenge, WebauthnCeremony::Registration)
+        .await?;
+
+    let user = state.user_service.get_user_by_id(&claims.sub).await?;
+
+    if challenge.userId != Some(user.id) {
+        return Err(CustomError::InvalidToken);
+    }
+
+    state
+        .webauthn_credential_service
+        .create_credential(&NewWebauthnCredential {
+            userId: user.id,
+            credentialId: verified.credential_id,
+            publicKey: verified.public_key,
+            publicKeyAlg: verified.public_key_alg,
+            signCount: verified.sign_count as i64,
+            createdAt: Utc::now(),
+        })
+        .await?;
+
+    tracing::info!("User {} has registered a passkey", user.id);
+
+    Ok(Json(GeneralResDto {
+        status_code: 200,
+        message: "Ok".to_owned(),
+    }))
+}
+
+/// Start a passkey login, the authenticator picks the credential
+pub async fn start_passkey_login(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<PasskeyRequestOptionsDto>, CustomError> {
+    let challenge = state.webauthn_service.generate_challenge()?;
+
+    state
+        .webauthn_credential_service
+        .create_challenge(&WebauthnChallenge {
+            challenge: challenge.clone(),
+            ceremony: WebauthnCeremony::Authentication,
+            userId: None,
+            expiresAt: Utc::now() + Duration::seconds(PASSKEY_CHALLENGE_EXP_SECS),
+        })
+        .await?;
+
+    Ok(Json(state.webauthn_service.request_options(challenge)))
+}
+
+/// Verify the assertion and issue a token pair for a new session
+pub async fn finish_passkey_login(
+    State(state): State<Arc<AppState>>,
+    client: ClientInfo,
+    transport: TokenTransport,
+    Json(payload): Json<PasskeyAuthenticationDto>,
+) -> Result<(CookieJar, Json<AuthResDto>), CustomError> {
+    let credential = match state
+        .webauthn_credential_service
+        .get_credential(&normalize_b64url(&payload.raw_id))
+        .await
+    {
+        Ok(credential) => credential,
+        Err(CustomError::NotFoundError(_)) => return Err(CustomError::WrongCredentials),
+        Err(err) => return Err(err),
+    };
+
+    if let Some(user_handle) = &payload.response.user_handle
+        && normalize_b64url(user_handle) != state.webauthn_service.user_handle(&credential.userId)
+    {
+        return Err(CustomError::WrongCredentials);
+    }
+
+    let verified = state.webauthn_service.verify_assertion(
+        &payload,
+        &credential.publicKey,
+        credential.publicKeyAlg,
+        credential.signCount as u32,
+    )?;
+
+    state
+        .webauthn_credential_service
+        .consume_challenge(&verified.challenge, WebauthnCeremony::Authentication)
+        .await?;
+
+    state
+        .webauthn_credential_service
+        .update_sign_count(
+            &credential.id,
+            credential.signCount,
+            verified.sign_count as i64,
+        )
+        .await?;
+
+    let user = state
+        .user_service
+        .get_user_by_id(&credential.userId.to_hex())
+        .await?;
+
+    tracing::info!("User {} has logged in with a passkey", user.email);
+
+    let family_id = state.auth_service.new_token_family();
+
+    let tokens = issue_tokens(&state, &user, family_id, Utc::now(), &client).await?;
+
+    let (jar, tokens) = transport.deliver(tokens)?;
+
+    Ok((jar, Json(tokens)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dtos::passkey_dto::AssertionResponse,
+        test_support::{SoftwareAuthenticator, claims_of, insert_user, test_state, transport_with},
+    };
+
+    fn client() -> ClientInfo {
+        ClientInfo {
+            user_agent: None,
+            ip: None,
+        }
+    }
+
+    /// The same assertion, as an attacker who captured it would send it again
+    fn replay(payload: &PasskeyAuthenticationDto) -> PasskeyAuthenticationDto {
+        PasskeyAuthenticationDto {
+            raw_id: payload.raw_id.clone(),
+            response: AssertionResponse {
+                client_data_json: payload.response.client_data_json.clone(),
+                authenticator_data: payload.response.authenticator_data.clone(),
+                signature: payload.response.signature.clone(),
+                user_handle: payload.response.user_handle.clone(),
+            },
+        }
+    }
+
+    async fn log_in(
+        state: &Arc<AppState>,
+        payload: PasskeyAuthenticationDto,
+    ) -> Result<AuthResDto, CustomError> {
+        let (_jar, Json(tokens)) = finish_passkey_login(
+            State(state.clone()),
+            client(),
+            transport_with(&[]).await,
+            Json(payload),
+        )
+        .await?;
+
+        Ok(tokens)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn passkey_logs_in_once_per_challenge() {
+        let (state, _r2, _db) = test_state().await;
+        let user = insert_user(&state, "alice", "Password1!").await;
+        let claims = claims_of(&user.id.to_hex(), "sid");
+        let mut authenticator = SoftwareAuthenticator::for_app();
+
+        let Json(options) = start_passkey_registration(claims.clone(), State(state.clone()))
+            .await
+            .unwrap();
+        let registered = finish_passkey_registration(
+            claims,
+            State(state.clone()),
+            Json(authenticator.register(&options.challenge)),
+        )
+        .await;
+        assert!(registered.is_ok());
+
+        let Json(options) = start_passkey_login(State(state.clone())).await.unwrap();
+        let assertion = authenticator.assert(&options.challenge);
+        let captured = replay(&assertion);
+
+        let tokens = log_in(&state, assertion).await.unwrap();
+        let session = state
+            .auth_service
+            .decode_access_token(&tokens.access_token)
+            .unwrap();
+        assert_eq!(session.sub, user.id.to_hex());
+
+        // The counter already moved past the captured assertion
+        assert!(matches!(
+            log_in(&state, captured).await,
+            Err(CustomError::WrongCredentials)
+        ));
+
+        // A fresh signature can't reuse the consumed challenge either
+        assert!(matches!(
+            log_in(&state, authenticator.assert(&options.challenge)).await,
+            Err(CustomError::InvalidToken)
+        ));
+
+        let credential = state
+            .webauthn_credential_service
+            .get_credential(&authenticator.credential_id())
+            .await
+            .unwrap();
+        assert_eq!(credential.signCount, 1);
+    }
+}
@@ -0,0 +1,246 @@
+use crate::{
+    AppState,
+    dtos::profile_dto::{MeResDto, UpdateProfileDto},
+    types::{claims::Claims, error::CustomError, validation::FieldError},
+};
+use axum::{Json, extract::State};
+use chrono_tz::Tz;
+use std::sync::Arc;
+
+const DISPLAY_NAME_MAX_CHARS: usize = 50;
+const BIO_MAX_CHARS: usize = 500;
+const AVATAR_KEY_MAX_LEN: usize = 256;
+const LOCALE_MAX_LEN: usize = 35;
+
+/// Profile of the logged in user
+pub async fn get_me(
+    claims: Claims,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<MeResDto>, CustomError> {
+    let user = state.user_service.get_user_by_id(&claims.sub).await?;
+
+    Ok(Json(MeResDto::from(user)))
+}
+
+/// Change the editable profile fields of the logged in user
+pub async fn update_me(
+    claims: Claims,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<UpdateProfileDto>,
+) -> Result<Json<MeResDto>, CustomError> {
+    let avatar_prefix = format!("{}avatars/", state.storage_service.user_prefix(&claims.sub));
+
+    let payload = validate_profile(payload, &avatar_prefix)?;
+    let changes = payload.changes();
+
+    if !changes.is_empty() {
+        state
+            .user_service
+            .update_profile(&claims.sub, &changes)
+            .await?;
+
+        tracing::info!("User {} has updated the profile", claims.sub);
+    }
+
+    let user = state.user_service.get_user_by_id(&claims.sub).await?;
+
+    Ok(Json(MeResDto::from(user)))
+}
+
+/// Trim every field and check it, collecting the reason of each rejected field
+fn validate_profile(
+    payload: UpdateProfileDto,
+    avatar_prefix: &str,
+) -> Result<UpdateProfileDto, CustomError> {
+    let trim = |value: Option<String>| value.map(|v| v.trim().to_owned());
+
+    let profile = UpdateProfileDto {
+        display_name: trim(payload.display_name),
+        bio: trim(payload.bio),
+        avatar_key: trim(payload.avatar_key),
+        locale: trim(payload.locale),
+        timezone: trim(payload.timezone),
+    };
+
+    let mut errors = Vec::new();
+
+    if let Some(name) = profile.display_name.as_deref().filter(|v| !v.is_empty()) {
+        if name.chars().count() > DISPLAY_NAME_MAX_CHARS {
+            errors.push(FieldError::new(
+                "display_name",
+                &format!("must be at most {} characters", DISPLAY_NAME_MAX_CHARS),
+            ));
+        } else if name.chars().any(char::is_control) {
+            errors.push(FieldError::new(
+                "display_name",
+                "must not contain control characters",
+            ));
+        }
+    }
+
+    if let Some(bio) = profile.bio.as_deref().filter(|v| !v.is_empty()) {
+        if bio.chars().count() > BIO_MAX_CHARS {
+            errors.push(FieldError::new(
+                "bio",
+                &format!("must be at most {} characters", BIO_MAX_CHARS),
+            ));
+        } else if bio.chars().any(|c| c.is_control() && c != '\n') {
+            errors.push(FieldError::new(
+                "bio",
+                "must not contain control characters",
+            ));
+        }
+    }
+
+    if let Some(key) = profile.avatar_key.as_deref().filter(|v| !v.is_empty()) {
+        let name = key.strip_prefix(avatar_prefix).unwrap_or_default();
+
+        if key.len() > AVATAR_KEY_MAX_LEN
+            || name.is_empty()
+            || name.contains('/')
+            || name.starts_with('.')
+        {
+            errors.push(FieldError::new(
+                "avatar_key",
+                "must be an object in the avatars folder of the user",
+            ));
+        }
+    }
+
+    if let Some(locale) = profile.locale.as_deref().filter(|v| !v.is_empty())
+        && !is_language_tag(locale)
+    {
+        errors.push(FieldError::new(
+            "locale",
+            "must be a language tag like en-US",
+        ));
+    }
+
+    if let Some(timezone) = profile.timezone.as_deref().filter(|v| !v.is_empty())
+        && timezone.parse::<Tz>().is_err()
+    {
+        errors.push(FieldError::new(
+            "timezone",
+            "must be an IANA time zone like Europe/Berlin",
+        ));
+    }
+
+    if !errors.is_empty() {
+        return Err(CustomError::ValidationError(errors));
+    }
+
+    Ok(profile)
+}
+
+/// Loose BCP 47 check: a 2-3 letter language followed by alphanumeric subtags
+fn is_language_tag(value: &str) -> bool {
+    let mut subtags = value.split('-');
+
+    let language_ok = subtags
+        .next()
+        .is_some_and(|l| (2..=3).contains(&l.len()) && l.chars().all(|c| c.is_ascii_alphabetic()));
+
+    value.len() <= LOCALE_MAX_LEN
+        && language_ok
+        && subtags
+            .all(|s| (1..=8).contains(&s.len()) && s.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AVATAR_PREFIX: &str = "users/user-1/avatars/";
+
+    fn profile() -> UpdateProfileDto {
+        UpdateProfileDto {
+            display_name: None,
+            bio: None,
+            avatar_key: None,
+            locale: None,
+            timezone: None,
+        }
+    }
+
+    fn rejected_fields(payload: UpdateProfileDto) -> Vec<String> {
+        match validate_profile(payload, AVATAR_PREFIX) {
+            Err(CustomError::ValidationError(errors)) => {
+                errors.into_iter().map(|e| e.field).collect()
+            }
+            other => panic!("expected a validation error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn valid_profile_is_trimmed() {
+        let profile = validate_profile(
+            UpdateProfileDto {
+                display_name: Some("  Alice  ".to_owned()),
+                bio: Some("Line one\nLine two".to_owned()),
+                avatar_key: Some(format!("{}me.png", AVATAR_PREFIX)),
+                locale: Some("en-US".to_owned()),
+                timezone: Some("Europe/Berlin".to_owned()),
+            },
+            AVATAR_PREFIX,
+        )
+        .unwrap();
+
+        assert_eq!(profile.display_name.as_deref(), Some("Alice"));
+        assert_eq!(profile.changes().len(), 5);
+    }
+
+    #[test]
+    fn empty_fields_clear_the_value() {
+        let profile = validate_profile(
+            UpdateProfileDto {
+                bio: Some("   ".to_owned()),
+                ..profile()
+            },
+            AVATAR_PREFIX,
+        )
+        .unwrap();
+
+        assert_eq!(profile.changes(), vec![("bio", None)]);
+    }
+
+    #[test]
+    fn every_invalid_field_is_reported() {
+        let fields = rejected_fields(UpdateProfileDto {
+            display_name: Some("a".repeat(DISPLAY_NAME_MAX_CHARS + 1)),
+            bio: Some("bell\u{7}".to_owned()),
+            avatar_key: Some("users/user-2/avatars/me.png".to_owned()),
+            locale: Some("english".to_owned()),
+            timezone: Some("Mars/Olympus".to_owned()),
+        });
+
+        assert_eq!(
+            fields,
+            vec!["display_name", "bio", "avatar_key", "locale", "timezone"]
+        );
+    }
+
+    #[test]
+    fn avatar_must_stay_in_the_avatars_folder() {
+        for key in [
+            AVATAR_PREFIX.to_owned(),
+            format!("{}nested/me.png", AVATAR_PREFIX),
+            format!("{}.hidden", AVATAR_PREFIX),
+        ] {
+            let fields = rejected_fields(UpdateProfileDto {
+                avatar_key: Some(key),
+                ..profile()
+            });
+
+            assert_eq!(fields, vec!["avatar_key"]);
+        }
+    }
+
+    #[test]
+    fn language_tags() {
+        assert!(is_language_tag("en"));
+        assert!(is_language_tag("zh-Hant-TW"));
+        assert!(!is_language_tag("e"));
+        assert!(!is_language_tag("en_US"));
+        assert!(!is_language_tag("en-"));
+    }
+}
@@ -0,0 +1,194 @@
+use crate::{
+    AppState,
+    dtos::{general_res_dto::GeneralResDto, session_dto::SessionResDto},
+    models::refresh_token::RefreshToken,
+    types::{claims::Claims, error::CustomError},
+};
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use std::{collections::HashSet, sync::Arc};
+
+/// List the active sessions (devices) of the logged in user
+pub async fn list_sessions(
+    claims: Claims,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<SessionResDto>>, CustomError> {
+    let tokens = state
+        .refresh_token_service
+        .get_active_tokens_by_user(&claims.sub)
+        .await?;
+
+    Ok(Json(sessions_of(tokens, &claims.sid)))
+}
+
+/// One session per token family, `tokens` must be sorted newest first
+/// so only the latest token of each family is kept
+pub fn sessions_of(tokens: Vec<RefreshToken>, current_family_id: &str) -> Vec<SessionResDto> {
+    let mut seen_families = HashSet::new();
+
+    tokens
+        .into_iter()
+        .filter(|t| t.familyId.is_empty() || seen_families.insert(t.familyId.clone()))
+        .map(|t| SessionResDto::new(t, current_family_id))
+        .collect()
+}
+
+/// Revoke a single session of the logged in user
+pub async fn revoke_session(
+    claims: Claims,
+    State(state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+) -> Result<Json<GeneralResDto>, CustomError> {
+    let token = state
+        .refresh_token_service
+        .get_token_by_id(&session_id, &claims.sub)
+        .await?;
+
+    if token.familyId.is_empty() {
+        state
+            .refresh_token_service
+            .revoke_token(&token.token)
+            .await?;
+    } else {
+        state
+            .refresh_token_service
+            .revoke_family(&token.familyId)
+            .await?;
+
+        state
+            .access_denylist_service
+            .revoke_session(&token.familyId)
+            .await?;
+    }
+
+    tracing::info!("User {} revoked session {}", claims.sub, session_id);
+
+    Ok(Json(GeneralResDto {
+        status_code: 200,
+        message: "Ok".to_owned(),
+    }))
+}
+
+/// Log out of every session of the logged in user, including the current one
+pub async fn revoke_all_sessions(
+    claims: Claims,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<GeneralResDto>, CustomError> {
+    let revoked = state
+        .refresh_token_service
+        .revoke_all_user_tokens(&claims.sub)
+        .await?;
+
+    state
+        .access_denylist_service
+        .revoke_user(&claims.sub, None)
+        .await?;
+
+    tracing::info!("User {} logged out of {} sessions", claims.sub, revoked);
+
+    Ok(Json(GeneralResDto {
+        status_code: 200,
+        message: "Ok".to_owned(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{claims_of, insert_user, start_session, test_state};
+    use bson::oid::ObjectId;
+    use chrono::{Duration, Utc};
+
+    fn token(family_id: &str, minutes_ago: i64) -> RefreshToken {
+        let created_at = Utc::now() - Duration::minutes(minutes_ago);
+
+        RefreshToken {
+            id: ObjectId::new(),
+            userId: ObjectId::new(),
+            token: ObjectId::new().to_hex(),
+            familyId: family_id.to_owned(),
+            isRevoked: false,
+            createdAt: created_at,
+            expiresAt: created_at + Duration::days(7),
+            usedAt: None,
+            graceUsedAt: None,
+            sessionStartedAt: Some(created_at - Duration::hours(1)),
+            userAgent: Some("Firefox".to_owned()),
+            ip: Some("203.0.113.7".to_owned()),
+        }
+    }
+
+    #[test]
+    fn each_family_is_one_session_shown_by_its_latest_token() {
+        let latest = token("family-a", 1);
+        let tokens = vec![
+            latest.clone(),
+            token("family-b", 2),
+            token("family-a", 3),
+            token("", 4),
+            token("", 5),
+        ];
+
+        let sessions = sessions_of(tokens, "family-a");
+
+        assert_eq!(sessions.len(), 4);
+        assert_eq!(sessions[0].id, latest.id.to_hex());
+        assert_eq!(sessions[0].created_at, latest.sessionStartedAt.unwrap());
+        assert_eq!(sessions[0].last_used_at, latest.createdAt);
+        assert_eq!(
+            sessions.iter().map(|s| s.is_current).collect::<Vec<_>>(),
+            [true, false, false, false]
+        );
+    }
+
+    async fn sessions_of_user(state: &Arc<AppState>, user_id: &str) -> Vec<SessionResDto> {
+        let Json(sessions) = list_sessions(claims_of(user_id, "family-a"), State(state.clone()))
+            .await
+            .unwrap();
+
+        sessions
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn session_is_revoked_only_by_its_user() {
+        let (state, _r2, _db) = test_state().await;
+        let alice = insert_user(&state, "alice", "Correct-Horse-9").await;
+        let bob = insert_user(&state, "bob", "Correct-Horse-9").await;
+        start_session(&state, &alice, "family-a").await;
+        start_session(&state, &alice, "family-b").await;
+        start_session(&state, &bob, "family-c").await;
+        let alice_id = alice.id.to_hex();
+
+        let sessions = sessions_of_user(&state, &alice_id).await;
+        assert_eq!(sessions.len(), 2);
+        let current = sessions.iter().find(|s| s.is_current).unwrap().id.clone();
+        let other = sessions.iter().find(|s| !s.is_current).unwrap().id.clone();
+
+        let bob_session = sessions_of_user(&state, &bob.id.to_hex()).await[0]
+            .id
+            .clone();
+        let result = revoke_session(
+            claims_of(&alice_id, "family-a"),
+            State(state.clone()),
+            Path(bob_session),
+        )
+        .await;
+        assert!(matches!(result, Err(CustomError::NotFoundError(_))));
+
+        let result = revoke_session(
+            claims_of(&alice_id, "family-a"),
+            State(state.clone()),
+            Path(other),
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let sessions = sessions_of_user(&state, &alice_id).await;
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, current);
+        assert_eq!(sessions_of_user(&state, &bob.id.to_hex()).await.len(), 1);
+    }
+}
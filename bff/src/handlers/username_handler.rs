@@ -0,0 +1,122 @@
+use crate::{
+    AppState,
+    dtos::{
+        general_res_dto::GeneralResDto,
+        username_dto::{ChangeUsernameDto, UsernameAvailabilityResDto, UsernameQuery},
+    },
+    models::{
+        security_event::{NewSecurityEvent, SecurityEventKind},
+        username_history::NewUsernameHistory,
+    },
+    services::username_history_service::{USERNAME_CHANGE_COOLDOWN_DAYS, USERNAME_HOLD_DAYS},
+    types::{claims::Claims, error::CustomError, validation::FieldError},
+    utils::username::normalize_username,
+};
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use chrono::{Duration, Utc};
+use std::sync::Arc;
+
+/// Check whether a username can be registered or changed to
+pub async fn check_username(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<UsernameQuery>,
+) -> Result<Json<UsernameAvailabilityResDto>, CustomError> {
+    let (username, reason) = match normalize_username(&query.username) {
+        Ok(username) => {
+            let reason = state
+                .username_history_service
+                .username_taken_reason(&state.user_service, &username, None)
+                .await?;
+            (username, reason)
+        }
+        Err(reason) => (query.username.trim().to_lowercase(), Some(reason)),
+    };
+
+    Ok(Json(UsernameAvailabilityResDto {
+        username,
+        available: reason.is_none(),
+        reason,
+    }))
+}
+
+/// Change the username of the logged in user.
+///
+/// The old username is recorded in the history, so it keeps pointing to the
+/// user and stays reserved for them for a while
+pub async fn change_username(
+    claims: Claims,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<ChangeUsernameDto>,
+) -> Result<Json<GeneralResDto>, CustomError> {
+    let username = normalize_username(&payload.username).map_err(|reason| {
+        CustomError::ValidationError(vec![FieldError::new("username", &reason)])
+    })?;
+
+    let user = state.user_service.get_user_by_id(&claims.sub).await?;
+
+    if user.username == username {
+        return Ok(Json(GeneralResDto {
+            status_code: 200,
+            message: "Ok".to_owned(),
+        }));
+    }
+
+    if let Some(latest) = state
+        .username_history_service
+        .get_latest_change(&user.id)
+        .await?
+    {
+        let next_change_at = latest.changedAt + Duration::days(USERNAME_CHANGE_COOLDOWN_DAYS);
+
+        if next_change_at > Utc::now() {
+            return Err(CustomError::TooManyRequests(
+                (next_change_at - Utc::now()).num_seconds().max(1) as u64,
+            ));
+        }
+    }
+
+    if state
+        .username_history_service
+        .username_taken_reason(&state.user_service, &username, Some(&user.id))
+        .await?
+        .is_some()
+    {
+        return Err(CustomError::DuplicateKey(username));
+    }
+
+    state
+        .user_service
+        .update_username(&claims.sub, &username)
+        .await?;
+
+    let now = Utc::now();
+
+    state
+        .username_history_service
+        .create_entry(&NewUsernameHistory {
+            userId: user.id,
+            username: user.username.clone(),
+            newUsername: username.clone(),
+            changedAt: now,
+            heldUntil: now + Duration::days(USERNAME_HOLD_DAYS),
+        })
+        .await?;
+
+    state
+        .security_event_service
+        .record_event(&NewSecurityEvent {
+            userId: user.id,
+            kind: SecurityEventKind::UsernameChanged,
+            detail: format!("Username changed from {} to {}", user.username, username),
+            createdAt: now,
+        })
+        .await?;
+
+    Ok(Json(GeneralResDto {
+        status_code: 200,
+        message: "Ok".to_owned(),
+    }))
+}
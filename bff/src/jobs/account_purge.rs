@@ -0,0 +1,185 @@
+use chrono::Utc;
+use std::{sync::Arc, time::Duration};
+
+use crate::{AppState, models::account_purge::AccountPurge, types::error::CustomError};
+
+/// How often deleted accounts are checked for something left to remove
+const ACCOUNT_PURGE_INTERVAL_SECS: u64 = 5 * 60;
+
+/// Remove what is left of deleted accounts, forever
+pub async fn run(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(ACCOUNT_PURGE_INTERVAL_SECS));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = purge_due_accounts(&state).await {
+            tracing::error!("Account purge run failed: {:?}", err);
+        }
+    }
+}
+
+async fn purge_due_accounts(state: &AppState) -> Result<(), CustomError> {
+    let now = Utc::now();
+
+    for purge in state.account_purge_service.get_due_purges(now).await? {
+        if let Err(err) = purge_account(state, &purge).await {
+            tracing::warn!(
+                "Purge of user {} failed after {} attempts: {:?}",
+                purge.userId,
+                purge.attempts + 1,
+                err
+            );
+
+            state.account_purge_service.retry_later(&purge).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn purge_account(state: &AppState, purge: &AccountPurge) -> Result<(), CustomError> {
+    // The purge is scheduled before the account is deleted, so a deletion which
+    // failed halfway leaves a purge behind. An account still in use is never purged
+    if !state.user_service.is_user_deleted(&purge.userId).await? {
+        if purge.purgeAt <= Utc::now() {
+            tracing::warn!(
+                "User {} was never deleted, dropping its purge",
+                purge.userId
+            );
+            return state.account_purge_service.cancel_purge(purge).await;
+        }
+
+        return state.account_purge_service.retry_later(purge).await;
+    }
+
+    if !purge.objectsDeleted {
+        let prefix = state.storage_service.user_prefix(&purge.userId.to_hex());
+
+        let count = state
+            .storage_service
+            .delete_objects_with_prefix(&prefix)
+            .await?;
+
+        tracing::info!("Deleted {} objects under {}", count, prefix);
+
+        if purge.purgeAt > Utc::now() {
+            return state
+                .account_purge_service
+                .mark_objects_deleted(purge)
+                .await;
+        }
+    }
+
+    let count = state.account_purge_service.purge_user_data(purge).await?;
+
+    tracing::info!("Purged {} documents of user {}", count, purge.userId);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::account_purge::NewAccountPurge,
+        services::{
+            account_purge_service::AccountPurgeService, login_attempt_service::LoginAttemptService,
+        },
+        test_support::{insert_user, start_session, test_state},
+    };
+    use bson::Document;
+    use chrono::Duration;
+
+    async fn schedule_past_purge(state: &AppState, user_id: bson::oid::ObjectId) {
+        let past = Utc::now() - Duration::minutes(1);
+
+        state
+            .account_purge_service
+            .schedule_purge(&NewAccountPurge {
+                userId: user_id,
+                objectsDeleted: false,
+                attempts: 0,
+                purgeAt: past,
+                nextAttemptAt: past,
+            })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn account_in_use_is_never_purged() {
+        let (state, r2, _db) = test_state().await;
+        let user = insert_user(&state, "alice", "Correct-Horse-9").await;
+        let key = format!(
+            "{}avatar.png",
+            state.storage_service.user_prefix(&user.id.to_hex())
+        );
+        r2.put(&key);
+        schedule_past_purge(&state, user.id).await;
+
+        purge_due_accounts(&state).await.unwrap();
+
+        assert_eq!(r2.keys(), vec![key]);
+        assert!(
+            state
+                .user_service
+                .get_user_by_id(&user.id.to_hex())
+                .await
+                .is_ok()
+        );
+        let later = Utc::now() + Duration::days(365);
+        assert!(
+            state
+                .account_purge_service
+                .get_due_purges(later)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn deleted_account_is_purged() {
+        let (state, r2, db) = test_state().await;
+        let user = insert_user(&state, "alice", "Correct-Horse-9").await;
+        r2.put(&format!(
+            "{}avatar.png",
+            state.storage_service.user_prefix(&user.id.to_hex())
+        ));
+        start_session(&state, &user, "family-a").await;
+        state
+            .login_attempt_service
+            .record_failure(&LoginAttemptService::user_key(&user.id.to_hex()))
+            .await
+            .unwrap();
+        schedule_past_purge(&state, user.id).await;
+        state
+            .user_service
+            .soft_delete(&user.id.to_hex(), Utc::now())
+            .await
+            .unwrap();
+
+        purge_due_accounts(&state).await.unwrap();
+
+        assert!(r2.keys().is_empty());
+        for (name, filter) in AccountPurgeService::user_data_filters(&user.id) {
+            let left = db
+                .collection::<Document>(name)
+                .count_documents(filter)
+                .await
+                .unwrap();
+            assert_eq!(left, 0, "{} still has documents of the user", name);
+        }
+        assert!(state.user_service.is_user_deleted(&user.id).await.unwrap());
+        assert!(
+            state
+                .user_service
+                .get_user_by_id(&user.id.to_hex())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use chrono::Utc;
+use std::{sync::Arc, time::Duration};
+
+use crate::{AppState, types::error::CustomError};
+
+/// How often export archives are checked for an expired download link
+const EXPORT_CLEANUP_INTERVAL_SECS: u64 = 15 * 60;
+
+/// Fail abandoned exports and delete the archives of exports whose download link ran
+/// out or which failed, forever. The first run happens right at startup, so exports
+/// whose task was lost in a restart don't hold back new requests for long
+pub async fn run(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(EXPORT_CLEANUP_INTERVAL_SECS));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = delete_stale_archives(&state).await {
+            tracing::error!("Export cleanup run failed: {:?}", err);
+        }
+    }
+}
+
+async fn delete_stale_archives(state: &AppState) -> Result<(), CustomError> {
+    let abandoned = state
+        .data_export_service
+        .fail_abandoned_exports(Utc::now())
+        .await?;
+
+    if abandoned > 0 {
+        tracing::warn!("Failed {} abandoned data exports", abandoned);
+    }
+
+    for export in state
+        .data_export_service
+        .get_stale_exports(Utc::now())
+        .await?
+    {
+        // a failed deletion keeps the object key, so the next run tries again
+        if let Some(object_key) = &export.objectKey
+            && let Err(err) = state.storage_service.delete_object(object_key).await
+        {
+            tracing::warn!("Archive of data export {} is kept: {:?}", export.id, err);
+            continue;
+        }
+
+        state.data_export_service.expire_export(&export).await?;
+
+        tracing::info!("Deleted the archive of data export {}", export.id);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::data_export::{DataExportStatus, NewDataExport},
+        services::data_export_service::DATA_EXPORT_ABANDONED_MINS,
+        test_support::{insert_user, test_state},
+    };
+    use bson::oid::ObjectId;
+    use chrono::{DateTime, Duration};
+
+    async fn uploaded_export(state: &AppState, user_id: ObjectId, object_key: &str) -> ObjectId {
+        uploaded_export_at(state, user_id, object_key, Utc::now()).await
+    }
+
+    async fn uploaded_export_at(
+        state: &AppState,
+        user_id: ObjectId,
+        object_key: &str,
+        created_at: DateTime<Utc>,
+    ) -> ObjectId {
+        let export_id = state
+            .data_export_service
+            .create_export(&NewDataExport {
+                userId: user_id,
+                status: DataExportStatus::Pending,
+                createdAt: created_at,
+            })
+            .await
+            .unwrap();
+
+        state
+            .data_export_service
+            .set_object_key(&export_id, object_key)
+            .await
+            .unwrap();
+
+        export_id
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn archive_of_failed_export_is_deleted() {
+        let (state, r2, _db) = test_state().await;
+        let user = insert_user(&state, "alice", "Correct-Horse-9").await;
+
+        let failed_id = uploaded_export(&state, user.id, "failed.zip").await;
+        r2.put("failed.zip");
+        state
+            .data_export_service
+            .fail_export(&failed_id)
+            .await
+            .unwrap();
+
+        let completed_id = uploaded_export(&state, user.id, "completed.zip").await;
+        r2.put("completed.zip");
+        state
+            .data_export_service
+            .complete_export(&completed_id)
+            .await
+            .unwrap();
+
+        delete_stale_archives(&state).await.unwrap();
+
+        assert_eq!(r2.keys(), vec!["completed.zip".to_owned()]);
+        let stale = state
+            .data_export_service
+            .get_stale_exports(Utc::now())
+            .await
+            .unwrap();
+        assert!(stale.is_empty());
+
+        // the failed export doesn't turn into an expired one counting against the cooldown
+        let recent = state
+            .data_export_service
+            .get_exports_created_since(&user.id, Utc::now() - Duration::hours(1))
+            .await
+            .unwrap();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].id, completed_id);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn abandoned_export_is_failed_and_its_archive_deleted() {
+        let (state, r2, _db) = test_state().await;
+        let user = insert_user(&state, "alice", "Correct-Horse-9").await;
+
+        let abandoned_at = Utc::now() - Duration::minutes(DATA_EXPORT_ABANDONED_MINS + 1);
+        uploaded_export_at(&state, user.id, "abandoned.zip", abandoned_at).await;
+        r2.put("abandoned.zip");
+        let running_id = uploaded_export(&state, user.id, "running.zip").await;
+        r2.put("running.zip");
+
+        delete_stale_archives(&state).await.unwrap();
+
+        assert_eq!(r2.keys(), vec!["running.zip".to_owned()]);
+        let recent = state
+            .data_export_service
+            .get_exports_created_since(&user.id, Utc::now() - Duration::hours(1))
+            .await
+            .unwrap();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].id, running_id);
+    }
+}
@@ -1,50 +1,139 @@
 mod config {
+    pub mod data_export;
     pub mod db;
+    pub mod email;
+    pub mod oidc;
+    pub mod password_hashing;
+    pub mod password_policy;
     pub mod r2;
+    pub mod rate_limit;
+    pub mod service_clients;
+    pub mod trusted_proxies;
+}
+mod jobs {
+    pub mod account_purge;
+    pub mod export_cleanup;
 }
 mod routes;
+mod middlewares {
+    pub mod rate_limit;
+}
 mod handlers {
+    pub mod admin_handler;
     pub mod auth_handler;
+    pub mod export_handler;
+    pub mod introspection_handler;
+    pub mod jwks_handler;
+    pub mod mfa_handler;
+    pub mod oidc_handler;
+    pub mod passkey_handler;
+    pub mod profile_handler;
+    pub mod session_handler;
+    pub mod username_handler;
 }
 mod dtos {
+    pub mod admin_dto;
     pub mod auth_dto;
+    pub mod data_export_dto;
     pub mod general_res_dto;
+    pub mod introspection_dto;
+    pub mod mfa_dto;
+    pub mod oidc_dto;
+    pub mod passkey_dto;
+    pub mod profile_dto;
+    pub mod session_dto;
+    pub mod username_dto;
 }
 mod models {
+    pub mod account_purge;
+    pub mod data_export;
+    pub mod email_verif_token;
+    pub mod linked_identity;
+    pub mod login_attempt;
+    pub mod migration;
+    pub mod rate_limit;
     pub mod refresh_token;
+    pub mod reset_pass_token;
+    pub mod revoked_access;
+    pub mod security_event;
+    pub mod used_mfa_token;
     pub mod user;
-    pub mod email_verif_token;
+    pub mod username_history;
+    pub mod webauthn_credential;
 }
 mod services {
+    pub mod access_denylist_service;
+    pub mod account_purge_service;
     pub mod auth_service;
+    pub mod data_export_service;
     pub mod email_service;
+    pub mod email_verif_token_service;
+    pub mod linked_identity_service;
+    pub mod login_attempt_service;
+    pub mod mfa_token_service;
+    pub mod oidc_service;
+    pub mod password_policy_service;
+    pub mod rate_limit_service;
     pub mod refresh_token_service;
+    pub mod reset_pass_token_service;
+    pub mod security_event_service;
     pub mod storage_service;
+    pub mod totp_service;
     pub mod user_service;
-    pub mod email_verif_token_service;
+    pub mod username_history_service;
+    pub mod webauthn_credential_service;
+    pub mod webauthn_service;
 }
 mod types {
     pub mod app_state;
+    pub mod authorization;
+    pub mod change_email;
     pub mod claims;
+    pub mod client_info;
+    pub mod data_export;
     pub mod email;
     pub mod error;
     pub mod keys;
+    pub mod magic_link;
+    pub mod mfa_claims;
     pub mod refresh_claims;
+    pub mod reset_password;
+    pub mod role;
+    pub mod service_client;
+    pub mod token_transport;
+    pub mod validation;
     pub mod verify_email;
 }
+#[cfg(test)]
+mod test_support;
 mod utils {
     pub mod datetime;
     pub mod db_util;
+    pub mod email;
+    pub mod env;
+    pub mod username;
 }
 
 use crate::{
-    config::{db, r2},
+    config::{db, email::EMAIL_CONFIG, r2, rate_limit::rate_limit_backend},
     services::{
-        auth_service::AuthService, email_service::EmailService,
-        refresh_token_service::RefreshTokenService, storage_service::StorageService,
-        user_service::UserService, email_verif_token_service::VerifEmailTokenService,
+        access_denylist_service::AccessDenylistService, account_purge_service::AccountPurgeService,
+        auth_service::AuthService, data_export_service::DataExportService,
+        email_service::EmailService, email_verif_token_service::VerifEmailTokenService,
+        linked_identity_service::LinkedIdentityService, login_attempt_service::LoginAttemptService,
+        mfa_token_service::MfaTokenService, oidc_service::OidcService,
+        password_policy_service::PasswordPolicyService, rate_limit_service::RateLimitService,
+        refresh_token_service::RefreshTokenService,
+        reset_pass_token_service::ResetPassTokenService,
+        security_event_service::SecurityEventService, storage_service::StorageService,
+        totp_service::TotpService, user_service::UserService,
+        username_history_service::UsernameHistoryService,
+        webauthn_credential_service::WebauthnCredentialService, webauthn_service::WebauthnService,
+    },
+    types::{
+        app_state::AppState,
+        token_transport::{CSRF_HEADER, TRANSPORT_HEADER},
     },
-    types::app_state::AppState,
 };
 use axum::{
     extract::Request,
@@ -55,7 +144,11 @@ use axum::{
 };
 use dotenvy::dotenv;
 use routes::create_router;
-use std::{env::var, sync::Arc};
+use std::{
+    env::var,
+    net::SocketAddr,
+    sync::{Arc, LazyLock},
+};
 use tower_http::{
     classify::{ServerErrorsAsFailures, SharedClassifier},
     cors::CorsLayer,
@@ -70,6 +163,9 @@ async fn main() {
     dotenv().ok();
     init_tracing();
 
+    // fail now rather than when the first email goes out
+    LazyLock::force(&EMAIL_CONFIG);
+
     let db = db::connect_db().await.unwrap();
     tracing::info!("✅ Connected to MongoDB");
 
@@ -82,20 +178,46 @@ async fn main() {
         .allow_origin(frontend.parse::<HeaderValue>().unwrap())
         .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
         .allow_credentials(true)
-        .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE]);
+        .allow_headers([
+            AUTHORIZATION,
+            ACCEPT,
+            CONTENT_TYPE,
+            CSRF_HEADER,
+            TRANSPORT_HEADER,
+        ]);
 
-    let app = create_router(Arc::new(AppState {
+    let state = Arc::new(AppState {
         user_service: UserService::new(db.clone()),
         refresh_token_service: RefreshTokenService::new(db.clone()),
         auth_service: AuthService::new(),
         storage_service: StorageService::new(r2_client),
         email_service: EmailService::new(),
-        verif_email_token_service: VerifEmailTokenService::new(db),
-    }))
-    .layer(cors)
-    .layer(init_req_tracer())
-    .layer(PropagateRequestIdLayer::x_request_id())
-    .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid));
+        verif_email_token_service: VerifEmailTokenService::new(db.clone()),
+        reset_pass_token_service: ResetPassTokenService::new(db.clone()),
+        security_event_service: SecurityEventService::new(db.clone()),
+        login_attempt_service: LoginAttemptService::new(db.clone()),
+        rate_limit_service: RateLimitService::new(rate_limit_backend(), db.clone()),
+        totp_service: TotpService::new(),
+        webauthn_credential_service: WebauthnCredentialService::new(db.clone()),
+        webauthn_service: WebauthnService::new(),
+        linked_identity_service: LinkedIdentityService::new(db.clone()),
+        oidc_service: OidcService::new(),
+        data_export_service: DataExportService::new(db.clone()),
+        username_history_service: UsernameHistoryService::new(db.clone()),
+        access_denylist_service: AccessDenylistService::new(db.clone()),
+        password_policy_service: PasswordPolicyService::new(),
+        account_purge_service: AccountPurgeService::new(db.clone()),
+        mfa_token_service: MfaTokenService::new(db),
+    });
+
+    tokio::spawn(jobs::account_purge::run(state.clone()));
+    tokio::spawn(jobs::export_cleanup::run(state.clone()));
+
+    let app = create_router(state)
+        .layer(cors)
+        .layer(init_req_tracer())
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid));
 
     let port = var("BFF_PORT").expect("BFF_PORT is not set");
 
@@ -105,10 +227,13 @@ async fn main() {
 
     tracing::info!("listening on {}", listener.local_addr().unwrap());
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await
-        .unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await
+    .unwrap();
 }
 
 async fn shutdown_signal() {
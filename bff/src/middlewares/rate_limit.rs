@@ -0,0 +1,256 @@
+use crate::{
+    AppState,
+    config::rate_limit::{DEFAULT_ROUTE_LIMIT, ROUTE_LIMITS},
+    types::{client_info::client_ip, error::CustomError},
+    utils::{datetime::now_epoch, email::normalize_email},
+};
+use axum::{
+    body::{Body, to_bytes},
+    extract::{MatchedPath, Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+
+/// Biggest body buffered to find the identifier of the request
+const MAX_BUFFERED_BODY_BYTES: usize = 64 * 1024;
+
+/// Limit requests per client IP and, for routes that take an email,
+/// per target identifier, answering with 429 once a limit is exceeded
+pub async fn rate_limit(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_owned())
+        .unwrap_or_else(|| request.uri().path().to_owned());
+
+    let limit = ROUTE_LIMITS
+        .get(route.as_str())
+        .copied()
+        .unwrap_or(DEFAULT_ROUTE_LIMIT);
+
+    let (parts, body) = request.into_parts();
+
+    let mut checks = Vec::new();
+
+    // Clients without a known IP aren't put in one shared bucket, where any of
+    // them could throttle all the others
+    match client_ip(&parts) {
+        Some(ip) => checks.push((format!("{}:ip:{}", route, ip), limit.per_ip)),
+        None => tracing::warn!(
+            "No client IP for a request to {}, skipping its IP limit",
+            route
+        ),
+    }
+
+    // The body has to be buffered to read the email, then put back for the handler
+    let body = match limit.per_identifier {
+        Some(max) => {
+            let bytes = match to_bytes(body, MAX_BUFFERED_BODY_BYTES).await {
+                Ok(bytes) => bytes,
+                Err(_) => return CustomError::MissingCredentials.into_response(),
+            };
+
+            if let Some(identifier) = body_identifier(&bytes) {
+                checks.push((format!("{}:id:{}", route, identifier), max));
+            }
+
+            Body::from(bytes)
+        }
+        None => body,
+    };
+
+    // (limit, remaining, reset_at) of the most restrictive check
+    let mut tightest: Option<(u64, u64, usize)> = None;
+
+    for (key, max) in checks {
+        let (count, reset_at) = match state.rate_limit_service.hit(&key, limit.window_secs).await {
+            Ok(v) => v,
+            Err(err) => {
+                // Don't lock everyone out when the counter store is down
+                tracing::error!("Rate limit check failed for {}: {:?}", key, err);
+                continue;
+            }
+        };
+
+        let remaining = max.saturating_sub(count);
+
+        if tightest.is_none_or(|(_, r, _)| remaining < r) {
+            tightest = Some((max, remaining, reset_at));
+        }
+
+        if count > max {
+            tracing::warn!("Rate limit exceeded for {}", key);
+
+            let retry_after = reset_at.saturating_sub(now_epoch()).max(1) as u64;
+            let mut response = CustomError::TooManyRequests(retry_after).into_response();
+
+            set_rate_limit_headers(&mut response, max, 0, retry_after);
+
+            return response;
+        }
+    }
+
+    let mut response = next.run(Request::from_parts(parts, body)).await;
+
+    if let Some((max, remaining, reset_at)) = tightest {
+        set_rate_limit_headers(
+            &mut response,
+            max,
+            remaining,
+            reset_at.saturating_sub(now_epoch()) as u64,
+        );
+    }
+
+    response
+}
+
+/// Get the email the request targets from its JSON body
+fn body_identifier(bytes: &[u8]) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_slice(bytes).ok()?;
+
+    value
+        .get("email")
+        .and_then(|v| v.as_str())
+        .map(normalize_email)
+        .filter(|v| !v.is_empty())
+}
+
+fn set_rate_limit_headers(response: &mut Response, limit: u64, remaining: u64, reset_secs: u64) {
+    let headers = response.headers_mut();
+
+    headers.insert("ratelimit-limit", HeaderValue::from(limit));
+    headers.insert("ratelimit-remaining", HeaderValue::from(remaining));
+    headers.insert("ratelimit-reset", HeaderValue::from(reset_secs));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::unreachable_state;
+    use axum::{
+        Router,
+        http::{StatusCode, header::RETRY_AFTER},
+        middleware::from_fn_with_state,
+        routing::post,
+    };
+    use serde_json::json;
+    use std::net::SocketAddr;
+
+    /// Serve `routes` behind the rate limiter, returning the base URL
+    async fn serve(routes: &[&'static str]) -> String {
+        serve_with(routes, true).await
+    }
+
+    /// Like `serve`, but without `connect_info` requests have no client IP
+    async fn serve_with(routes: &[&'static str], connect_info: bool) -> String {
+        let state = unreachable_state().await;
+
+        let mut app = Router::new();
+        for route in routes {
+            app = app.route(route, post(|| async { "ok" }));
+        }
+        let app = app.route_layer(from_fn_with_state(state, rate_limit));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            if connect_info {
+                axum::serve(
+                    listener,
+                    app.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .await
+                .unwrap()
+            } else {
+                axum::serve(listener, app).await.unwrap()
+            }
+        });
+
+        base
+    }
+
+    async fn post_email(url: &str, email: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(url)
+            .json(&json!({ "email": email }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn identifier_limit_counts_every_spelling_of_the_email() {
+        let base = serve(&["/auth/login"]).await;
+        let url = format!("{}/auth/login", base);
+        let limit = ROUTE_LIMITS["/auth/login"].per_identifier.unwrap();
+
+        for _ in 0..limit {
+            let response = post_email(&url, "Alice@Example.com").await;
+
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(response.headers().contains_key("ratelimit-remaining"));
+        }
+
+        let response = post_email(&url, " alice@example.com ").await;
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(RETRY_AFTER));
+        assert_eq!(response.headers()["ratelimit-remaining"], "0");
+
+        // Another account can still be targeted from the same address
+        let response = post_email(&url, "bob@example.com").await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn ip_limit_applies_per_route() {
+        let base = serve(&["/auth/reset_password", "/auth/refresh"]).await;
+        let url = format!("{}/auth/reset_password", base);
+        let limit = ROUTE_LIMITS["/auth/reset_password"].per_ip;
+
+        for _ in 0..limit {
+            assert_eq!(post_email(&url, "").await.status(), StatusCode::OK);
+        }
+
+        assert_eq!(
+            post_email(&url, "").await.status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            post_email(&format!("{}/auth/refresh", base), "")
+                .await
+                .status(),
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_without_a_client_ip_do_not_share_a_bucket() {
+        let base = serve_with(&["/auth/reset_password"], false).await;
+        let url = format!("{}/auth/reset_password", base);
+        let limit = ROUTE_LIMITS["/auth/reset_password"].per_ip;
+
+        for _ in 0..=limit {
+            assert_eq!(post_email(&url, "").await.status(), StatusCode::OK);
+        }
+    }
+
+    #[test]
+    fn identifier_is_the_normalized_email() {
+        assert_eq!(
+            body_identifier(br#"{"email": " Alice@Example.com "}"#).as_deref(),
+            Some("alice@example.com")
+        );
+        assert_eq!(body_identifier(br#"{"email": "  "}"#), None);
+        assert_eq!(body_identifier(br#"{"username": "alice"}"#), None);
+        assert_eq!(body_identifier(b"not json"), None);
+    }
+}
@@ -0,0 +1,43 @@
+use bson::{oid::ObjectId, serde_helpers::datetime::FromChrono04DateTime};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+pub const ACCOUNT_PURGES_COLL: &str = "account_purges";
+
+/// What is left to remove of a deleted account. The R2 objects go first, the
+/// documents keyed by the user once `purgeAt` is reached. The record is kept
+/// until both are done, failed steps are retried at `nextAttemptAt`
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountPurge {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub userId: ObjectId,
+    #[serde(default)]
+    pub objectsDeleted: bool,
+    #[serde(default)]
+    pub attempts: u32,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub purgeAt: DateTime<Utc>,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub nextAttemptAt: DateTime<Utc>,
+}
+
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewAccountPurge {
+    pub userId: ObjectId,
+    pub objectsDeleted: bool,
+    pub attempts: u32,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub purgeAt: DateTime<Utc>,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub nextAttemptAt: DateTime<Utc>,
+}
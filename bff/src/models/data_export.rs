@@ -0,0 +1,59 @@
+use bson::{oid::ObjectId, serde_helpers::datetime::FromChrono04DateTime};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+pub const DATA_EXPORTS_COLL: &str = "data_exports";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DataExportStatus {
+    Pending,
+    Completed,
+    Failed,
+    /// The download link ran out and the archive was deleted
+    Expired,
+}
+
+impl DataExportStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DataExportStatus::Pending => "pending",
+            DataExportStatus::Completed => "completed",
+            DataExportStatus::Failed => "failed",
+            DataExportStatus::Expired => "expired",
+        }
+    }
+}
+
+/// Export of the personal data of a user, requested by the user
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DataExport {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub userId: ObjectId,
+    pub status: DataExportStatus,
+    /// R2 key of the archive once it was uploaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub objectKey: Option<String>,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub createdAt: DateTime<Utc>,
+
+    #[serde_as(as = "Option<FromChrono04DateTime>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completedAt: Option<DateTime<Utc>>,
+}
+
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewDataExport {
+    pub userId: ObjectId,
+    pub status: DataExportStatus,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub createdAt: DateTime<Utc>,
+}
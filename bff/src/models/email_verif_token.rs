@@ -5,6 +5,26 @@ use serde_with::serde_as;
 
 pub const EMAIL_VERIF_TOKENS_COLL: &str = "email_verif_tokens";
 
+/// What the emailed token proves when it is redeemed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailTokenPurpose {
+    #[default]
+    VerifyEmail,
+    ChangeEmail,
+    MagicLink,
+}
+
+impl EmailTokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailTokenPurpose::VerifyEmail => "verify_email",
+            EmailTokenPurpose::ChangeEmail => "change_email",
+            EmailTokenPurpose::MagicLink => "magic_link",
+        }
+    }
+}
+
 #[allow(non_snake_case)]
 #[serde_as]
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -13,6 +33,11 @@ pub struct EmailVerifToken {
     pub id: ObjectId,
     pub userId: ObjectId,
     pub tokenHash: String,
+    #[serde(default)]
+    pub purpose: EmailTokenPurpose,
+    /// Address to switch to, only for `ChangeEmail` tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub newEmail: Option<String>,
 
     #[serde_as(as = "FromChrono04DateTime")]
     pub expiresAt: DateTime<Utc>,
@@ -31,6 +56,9 @@ pub struct EmailVerifToken {
 pub struct NewEmailVerifToken {
     pub userId: ObjectId,
     pub tokenHash: String,
+    pub purpose: EmailTokenPurpose,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub newEmail: Option<String>,
 
     #[serde_as(as = "FromChrono04DateTime")]
     pub expiresAt: DateTime<Utc>,
@@ -0,0 +1,57 @@
+use bson::{oid::ObjectId, serde_helpers::datetime::FromChrono04DateTime};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+pub const LINKED_IDENTITIES_COLL: &str = "linked_identities";
+pub const OIDC_AUTH_REQUESTS_COLL: &str = "oidc_auth_requests";
+
+/// Account of an external OpenID Connect provider linked to a user
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LinkedIdentity {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub userId: ObjectId,
+    pub provider: String,
+    /// `sub` claim of the provider, stable for the account
+    pub subject: String,
+    pub email: String,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub createdAt: DateTime<Utc>,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub lastLoginAt: DateTime<Utc>,
+}
+
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewLinkedIdentity {
+    pub userId: ObjectId,
+    pub provider: String,
+    pub subject: String,
+    pub email: String,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub createdAt: DateTime<Utc>,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub lastLoginAt: DateTime<Utc>,
+}
+
+/// Pending authorization request, kept until the provider redirects back
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OidcAuthRequest {
+    pub state: String,
+    pub provider: String,
+    pub codeVerifier: String,
+    pub nonce: String,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub expiresAt: DateTime<Utc>,
+}
@@ -0,0 +1,28 @@
+use bson::{oid::ObjectId, serde_helpers::datetime::FromChrono04DateTime};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+pub const LOGIN_ATTEMPTS_COLL: &str = "login_attempts";
+
+/// Consecutive failed logins of a user, or of an email without an account
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoginAttempt {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    /// Either `user:<user_id>` or `email:<email>`
+    pub key: String,
+    pub failedCount: u32,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub lastFailedAt: DateTime<Utc>,
+
+    #[serde_as(as = "Option<FromChrono04DateTime>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lockedUntil: Option<DateTime<Utc>>,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub updatedAt: DateTime<Utc>,
+}
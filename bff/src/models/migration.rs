@@ -0,0 +1,22 @@
+use bson::serde_helpers::datetime::FromChrono04DateTime;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+pub const MIGRATIONS_COLL: &str = "migrations";
+
+/// Data migration run at startup. The record is taken before the migration
+/// runs, so only one instance ever runs it
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Migration {
+    /// Name of the migration
+    #[serde(rename = "_id")]
+    pub id: String,
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub startedAt: DateTime<Utc>,
+    #[serde_as(as = "Option<FromChrono04DateTime>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completedAt: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,20 @@
+use bson::{oid::ObjectId, serde_helpers::datetime::FromChrono04DateTime};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+pub const RATE_LIMITS_COLL: &str = "rate_limits";
+
+/// Request counter of one key within one fixed window
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RateLimitCounter {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub key: String,
+    pub count: i64,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub expiresAt: DateTime<Utc>,
+}
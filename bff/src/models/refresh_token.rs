@@ -13,6 +13,8 @@ pub struct RefreshToken {
     pub id: ObjectId,
     pub userId: ObjectId,
     pub token: String,
+    #[serde(default)]
+    pub familyId: String,
     pub isRevoked: bool,
 
     #[serde_as(as = "FromChrono04DateTime")]
@@ -24,6 +26,22 @@ pub struct RefreshToken {
     #[serde_as(as = "Option<FromChrono04DateTime>")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub usedAt: Option<DateTime<Utc>>,
+
+    /// When the token, already rotated, was redeemed once more inside the grace window
+    #[serde_as(as = "Option<FromChrono04DateTime>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub graceUsedAt: Option<DateTime<Utc>>,
+
+    /// When the login which started this token family happened
+    #[serde_as(as = "Option<FromChrono04DateTime>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sessionStartedAt: Option<DateTime<Utc>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub userAgent: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
 }
 
 #[allow(non_snake_case)]
@@ -32,6 +50,7 @@ pub struct RefreshToken {
 pub struct NewRefreshToken {
     pub userId: ObjectId,
     pub token: String,
+    pub familyId: String,
     pub isRevoked: bool,
 
     #[serde_as(as = "FromChrono04DateTime")]
@@ -43,4 +62,14 @@ pub struct NewRefreshToken {
     #[serde_as(as = "Option<FromChrono04DateTime>")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub usedAt: Option<DateTime<Utc>>,
+
+    #[serde_as(as = "Option<FromChrono04DateTime>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sessionStartedAt: Option<DateTime<Utc>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub userAgent: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
 }
@@ -0,0 +1,44 @@
+use bson::{oid::ObjectId, serde_helpers::datetime::FromChrono04DateTime};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+pub const RESET_PASS_TOKENS_COLL: &str = "reset_pass_tokens";
+
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResetPassToken {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub userId: ObjectId,
+    pub tokenHash: String,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub expiresAt: DateTime<Utc>,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub createdAt: DateTime<Utc>,
+
+    #[serde_as(as = "Option<FromChrono04DateTime>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usedAt: Option<DateTime<Utc>>,
+}
+
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NewResetPassToken {
+    pub userId: ObjectId,
+    pub tokenHash: String,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub expiresAt: DateTime<Utc>,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub createdAt: DateTime<Utc>,
+
+    #[serde_as(as = "Option<FromChrono04DateTime>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usedAt: Option<DateTime<Utc>>,
+}
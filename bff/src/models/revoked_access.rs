@@ -0,0 +1,26 @@
+use bson::{oid::ObjectId, serde_helpers::datetime::FromChrono04DateTime};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+pub const REVOKED_ACCESS_COLL: &str = "revoked_access";
+
+/// Access tokens which are rejected before they expire. `key` is one of
+/// `jti:<jti>` for a single token, `sid:<session>` for every token of a session
+/// or `user:<id>` for tokens of the user issued before `revokedAt`.
+/// Documents are removed once every token they match has expired anyway
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RevokedAccess {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub key: String,
+    /// Session whose tokens are spared by a `user:` entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exceptSid: Option<String>,
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub revokedAt: DateTime<Utc>,
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub expiresAt: DateTime<Utc>,
+}
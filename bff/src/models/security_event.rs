@@ -0,0 +1,42 @@
+use bson::{oid::ObjectId, serde_helpers::datetime::FromChrono04DateTime};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+pub const SECURITY_EVENTS_COLL: &str = "security_events";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityEventKind {
+    RefreshTokenReuse,
+    AccountLocked,
+    AccountDisabled,
+    AccountEnabled,
+    UsernameChanged,
+}
+
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SecurityEvent {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub userId: ObjectId,
+    pub kind: SecurityEventKind,
+    pub detail: String,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub createdAt: DateTime<Utc>,
+}
+
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewSecurityEvent {
+    pub userId: ObjectId,
+    pub kind: SecurityEventKind,
+    pub detail: String,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub createdAt: DateTime<Utc>,
+}
@@ -0,0 +1,32 @@
+use bson::{oid::ObjectId, serde_helpers::datetime::FromChrono04DateTime};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+pub const USED_MFA_TOKENS_COLL: &str = "used_mfa_tokens";
+
+/// MFA pending token which already finished a login, kept until it expires
+/// so it can't finish another one
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UsedMfaToken {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub jti: String,
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub usedAt: DateTime<Utc>,
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub expiresAt: DateTime<Utc>,
+}
+
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewUsedMfaToken {
+    pub jti: String,
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub usedAt: DateTime<Utc>,
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub expiresAt: DateTime<Utc>,
+}
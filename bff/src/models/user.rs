@@ -3,6 +3,8 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 
+use crate::types::role::{Permission, Role};
+
 pub const USERS_COLL: &str = "users";
 
 #[allow(non_snake_case)]
@@ -15,6 +17,48 @@ pub struct User {
     pub email: String,
     pub password: String,
     pub isEmailVerified: bool,
+    #[serde(default)]
+    pub isTotpEnabled: bool,
+    /// Encrypted TOTP secret, set once 2FA is confirmed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totpSecret: Option<String>,
+    /// Encrypted TOTP secret waiting for the first code to be confirmed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pendingTotpSecret: Option<String>,
+    /// Time step of the last accepted TOTP code, codes up to it are refused
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totpLastStep: Option<i64>,
+    /// Hashes of the unused recovery codes
+    #[serde(default)]
+    pub totpRecoveryCodes: Vec<String>,
+    #[serde(default)]
+    pub roles: Vec<Role>,
+    /// Permissions granted on top of the ones of the roles
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+    /// Disabled by an operator, such users can't log in or use their tokens
+    #[serde(default)]
+    pub isDisabled: bool,
+    /// Set once the user deleted the account, the document is purged at `purgeAt`
+    #[serde_as(as = "Option<FromChrono04DateTime>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deletedAt: Option<DateTime<Utc>>,
+    #[serde_as(as = "Option<FromChrono04DateTime>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub purgeAt: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub displayName: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bio: Option<String>,
+    /// R2 key of the avatar image, under the prefix of the user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatarKey: Option<String>,
+    /// BCP 47 language tag, e.g. `en-US`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    /// IANA time zone name, e.g. `Europe/Berlin`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
     #[serde_as(as = "FromChrono04DateTime")]
     pub lastLoginAt: DateTime<Utc>,
     #[serde_as(as = "FromChrono04DateTime")]
@@ -0,0 +1,36 @@
+use bson::{oid::ObjectId, serde_helpers::datetime::FromChrono04DateTime};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+pub const USERNAME_HISTORY_COLL: &str = "username_history";
+
+/// A username given up by a user. It keeps pointing to the user and can't be
+/// taken by anyone else until `heldUntil`
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UsernameHistory {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub userId: ObjectId,
+    pub username: String,
+    pub newUsername: String,
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub changedAt: DateTime<Utc>,
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub heldUntil: DateTime<Utc>,
+}
+
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewUsernameHistory {
+    pub userId: ObjectId,
+    pub username: String,
+    pub newUsername: String,
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub changedAt: DateTime<Utc>,
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub heldUntil: DateTime<Utc>,
+}
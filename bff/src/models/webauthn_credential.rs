@@ -0,0 +1,78 @@
+use bson::{oid::ObjectId, serde_helpers::datetime::FromChrono04DateTime};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+pub const WEBAUTHN_CREDENTIALS_COLL: &str = "webauthn_credentials";
+pub const WEBAUTHN_CHALLENGES_COLL: &str = "webauthn_challenges";
+
+/// Passkey registered by a user
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebauthnCredential {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub userId: ObjectId,
+    /// base64url credential id chosen by the authenticator
+    pub credentialId: String,
+    /// base64url COSE key, or an uncompressed P-256 point for credentials
+    /// registered before other algorithms than ES256 were accepted
+    pub publicKey: String,
+    /// COSE algorithm of `publicKey`, missing for the older P-256 points
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publicKeyAlg: Option<i64>,
+    pub signCount: i64,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub createdAt: DateTime<Utc>,
+
+    #[serde_as(as = "Option<FromChrono04DateTime>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lastUsedAt: Option<DateTime<Utc>>,
+}
+
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewWebauthnCredential {
+    pub userId: ObjectId,
+    pub credentialId: String,
+    pub publicKey: String,
+    pub publicKeyAlg: i64,
+    pub signCount: i64,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub createdAt: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebauthnCeremony {
+    Registration,
+    Authentication,
+}
+
+impl WebauthnCeremony {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebauthnCeremony::Registration => "registration",
+            WebauthnCeremony::Authentication => "authentication",
+        }
+    }
+}
+
+/// Challenge handed out at the start of a ceremony, consumed when it finishes
+#[allow(non_snake_case)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebauthnChallenge {
+    pub challenge: String,
+    pub ceremony: WebauthnCeremony,
+    /// Only known for registrations, authentications use discoverable credentials
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub userId: Option<ObjectId>,
+
+    #[serde_as(as = "FromChrono04DateTime")]
+    pub expiresAt: DateTime<Utc>,
+}
@@ -1,10 +1,32 @@
 use crate::{
     AppState,
-    handlers::auth_handler::{login, logout, refresh, register, verify_email},
+    handlers::admin_handler::{
+        disable_user, enable_user, force_logout, force_verify_email, get_user, get_user_roles,
+        search_users, update_user_roles,
+    },
+    handlers::auth_handler::{
+        change_password, confirm_email_change, delete_account, login, logout, magic_link_login,
+        refresh, register, request_email_change, resend_verification, reset_password,
+        send_magic_link, send_reset_pass_link, verify_email,
+    },
+    handlers::export_handler::request_data_export,
+    handlers::introspection_handler::introspect,
+    handlers::jwks_handler::jwks,
+    handlers::mfa_handler::{confirm_totp, disable_totp, enroll_totp, verify_totp_login},
+    handlers::oidc_handler::{oidc_callback, start_oidc_login},
+    handlers::passkey_handler::{
+        finish_passkey_login, finish_passkey_registration, start_passkey_login,
+        start_passkey_registration,
+    },
+    handlers::profile_handler::{get_me, update_me},
+    handlers::session_handler::{list_sessions, revoke_all_sessions, revoke_session},
+    handlers::username_handler::{change_username, check_username},
+    middlewares::rate_limit::rate_limit,
 };
 use axum::{
     Router,
-    routing::{get, post},
+    middleware::from_fn_with_state,
+    routing::{delete, get, post},
 };
 use std::sync::Arc;
 
@@ -14,10 +36,59 @@ pub fn create_router(app_state: Arc<AppState>) -> Router {
         .route("/login", post(login))
         .route("/logout", post(logout))
         .route("/refresh", post(refresh))
-        .route("/verify_email", get(verify_email));
-    
+        .route("/verify_email", get(verify_email))
+        .route("/send_reset_pass_link", post(send_reset_pass_link))
+        .route("/reset_password", post(reset_password))
+        .route("/resend_verification", post(resend_verification))
+        .route("/change_password", post(change_password))
+        .route("/change_email", post(request_email_change))
+        .route("/confirm_email_change", get(confirm_email_change))
+        .route("/delete_account", post(delete_account))
+        .route("/username_available", get(check_username))
+        .route("/change_username", post(change_username))
+        .route("/data_export", post(request_data_export))
+        .route("/magic_link", post(send_magic_link))
+        .route("/magic_link/login", post(magic_link_login))
+        .route("/2fa/enroll", post(enroll_totp))
+        .route("/2fa/confirm", post(confirm_totp))
+        .route("/2fa/verify", post(verify_totp_login))
+        .route("/2fa/disable", post(disable_totp))
+        .route("/passkey/register/start", post(start_passkey_registration))
+        .route(
+            "/passkey/register/finish",
+            post(finish_passkey_registration),
+        )
+        .route("/passkey/login/start", post(start_passkey_login))
+        .route("/passkey/login/finish", post(finish_passkey_login))
+        .route("/oidc/{provider}/start", post(start_oidc_login))
+        .route("/oidc/callback", post(oidc_callback))
+        .route_layer(from_fn_with_state(app_state.clone(), rate_limit));
+
+    let session_routes = Router::new()
+        .route("/", get(list_sessions).delete(revoke_all_sessions))
+        .route("/{session_id}", delete(revoke_session));
+
+    let admin_routes = Router::new()
+        .route("/users", get(search_users))
+        .route("/users/{user_id}", get(get_user))
+        .route("/users/{user_id}/disable", post(disable_user))
+        .route("/users/{user_id}/enable", post(enable_user))
+        .route("/users/{user_id}/verify_email", post(force_verify_email))
+        .route("/users/{user_id}/logout", post(force_logout))
+        .route(
+            "/users/{user_id}/roles",
+            get(get_user_roles).put(update_user_roles),
+        );
+
+    let internal_routes = Router::new().route("/introspect", post(introspect));
+
     Router::new()
         .route("/", get(|| async { "Auth Service Running 🚀" }))
+        .route("/me", get(get_me).patch(update_me))
+        .route("/.well-known/jwks.json", get(jwks))
         .nest("/auth", auth_routes)
+        .nest("/sessions", session_routes)
+        .nest("/admin", admin_routes)
+        .nest("/internal", internal_routes)
         .with_state(app_state)
 }
@@ -0,0 +1,315 @@
+use bson::{Bson, doc};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use futures::TryStreamExt;
+use mongodb::{Database, options::ReturnDocument};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration as StdDuration, Instant},
+};
+
+use crate::{
+    models::revoked_access::{REVOKED_ACCESS_COLL, RevokedAccess},
+    services::auth_service::ACCESS_EXP_SECS,
+    types::{claims::Claims, error::CustomError},
+};
+
+/// How stale the in-process copy of the denylist may get, i.e. how long a
+/// revocation made by another instance can take to be enforced here
+const DENYLIST_CACHE_SECS: u64 = 5;
+
+#[derive(Default)]
+struct DenylistCache {
+    entries: HashMap<String, RevokedAccess>,
+    loaded_at: Option<Instant>,
+}
+
+impl DenylistCache {
+    /// Whether a live entry matches the token, its session or its user
+    fn revokes(&self, claims: &Claims, now: DateTime<Utc>) -> bool {
+        let live = |key: String| self.entries.get(&key).filter(|e| e.expiresAt > now);
+
+        if !claims.jti.is_empty() && live(format!("jti:{}", claims.jti)).is_some() {
+            return true;
+        }
+
+        if !claims.sid.is_empty() && live(format!("sid:{}", claims.sid)).is_some() {
+            return true;
+        }
+
+        // `iat` only has seconds, so a token issued in the second of the revocation may
+        // be older or newer than it. Such tokens are rejected too, which at worst makes
+        // a client refresh once more after a second rather than keep a revoked token
+        live(format!("user:{}", claims.sub)).is_some_and(|entry| {
+            (claims.iat as i64) <= entry.revokedAt.timestamp()
+                && entry.exceptSid.as_deref() != Some(claims.sid.as_str())
+        })
+    }
+}
+
+/// Revoked access tokens, stored in Mongo and mirrored in memory so checking
+/// a request doesn't need a query. Entries only live as long as an access token
+pub struct AccessDenylistService {
+    db: Database,
+    cache: Mutex<DenylistCache>,
+}
+
+impl AccessDenylistService {
+    pub fn new(db: Database) -> Self {
+        Self {
+            db,
+            cache: Mutex::new(DenylistCache::default()),
+        }
+    }
+
+    /// Reject the single access token of `claims`
+    pub async fn revoke_token(&self, claims: &Claims) -> Result<(), CustomError> {
+        if claims.jti.is_empty() {
+            return Ok(());
+        }
+
+        let expires_at = Utc
+            .timestamp_opt(claims.exp as i64, 0)
+            .single()
+            .unwrap_or_else(access_token_expiry);
+
+        self.add(format!("jti:{}", claims.jti), None, expires_at)
+            .await
+    }
+
+    /// Reject every access token issued for the session `sid`
+    pub async fn revoke_session(&self, sid: &str) -> Result<(), CustomError> {
+        if sid.is_empty() {
+            return Ok(());
+        }
+
+        self.add(format!("sid:{}", sid), None, access_token_expiry())
+            .await
+    }
+
+    /// Reject every access token of the user issued until now, except the ones of
+    /// the session `except_sid`
+    pub async fn revoke_user(
+        &self,
+        user_id: &str,
+        except_sid: Option<&str>,
+    ) -> Result<(), CustomError> {
+        self.add(
+            format!("user:{}", user_id),
+            except_sid.map(str::to_owned),
+            access_token_expiry(),
+        )
+        .await
+    }
+
+    pub async fn is_revoked(&self, claims: &Claims) -> Result<bool, CustomError> {
+        self.refresh_cache().await?;
+
+        Ok(self.cache.lock().unwrap().revokes(claims, Utc::now()))
+    }
+
+    async fn add(
+        &self,
+        key: String,
+        except_sid: Option<String>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), CustomError> {
+        let revoked_at = Utc::now();
+
+        // An entry may only keep an exception while it's live if the earlier revocation
+        // had the same one, otherwise the sessions it revoked would come back
+        let except_sid = match except_sid {
+            Some(sid) => Bson::Document(doc! {
+                "$cond": [
+                    {
+                        "$or": [
+                            { "$lte": ["$expiresAt", revoked_at] },
+                            { "$eq": ["$exceptSid", { "$literal": &sid }] }
+                        ]
+                    },
+                    { "$literal": &sid },
+                    Bson::Null
+                ]
+            }),
+            None => Bson::Null,
+        };
+
+        let entry = self
+            .db
+            .collection::<RevokedAccess>(REVOKED_ACCESS_COLL)
+            .find_one_and_update(
+                doc! { "key": &key },
+                vec![doc! {
+                    "$set": {
+                        "exceptSid": except_sid,
+                        "revokedAt": { "$max": ["$revokedAt", revoked_at] },
+                        "expiresAt": { "$max": ["$expiresAt", expires_at] }
+                    }
+                }],
+            )
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .await
+            .map_err(|err| {
+                tracing::error!("Error revoking access tokens for {}: {:?}", key, err);
+                CustomError::MongoError(err)
+            })?;
+
+        tracing::info!("Access tokens for {} have been revoked", key);
+
+        if let Some(entry) = entry {
+            self.cache.lock().unwrap().entries.insert(key, entry);
+        }
+
+        Ok(())
+    }
+
+    /// Reload the denylist from Mongo once the in-process copy is stale
+    async fn refresh_cache(&self) -> Result<(), CustomError> {
+        let is_fresh = self
+            .cache
+            .lock()
+            .unwrap()
+            .loaded_at
+            .is_some_and(|at| at.elapsed() < StdDuration::from_secs(DENYLIST_CACHE_SECS));
+
+        if is_fresh {
+            return Ok(());
+        }
+
+        let entries: Vec<RevokedAccess> = self
+            .db
+            .collection::<RevokedAccess>(REVOKED_ACCESS_COLL)
+            .find(doc! { "expiresAt": { "$gt": Utc::now() } })
+            .await
+            .map_err(|err| {
+                tracing::error!("Error loading revoked access tokens: {:?}", err);
+                CustomError::MongoError(err)
+            })?
+            .try_collect()
+            .await
+            .map_err(|err| {
+                tracing::error!("Error collecting revoked access tokens: {:?}", err);
+                CustomError::MongoError(err)
+            })?;
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.entries = entries.into_iter().map(|e| (e.key.clone(), e)).collect();
+        cache.loaded_at = Some(Instant::now());
+
+        Ok(())
+    }
+}
+
+/// Latest expiry of an access token issued right now
+fn access_token_expiry() -> DateTime<Utc> {
+    Utc::now() + Duration::seconds(ACCESS_EXP_SECS as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{claims_of, test_db};
+    use bson::oid::ObjectId;
+
+    fn cache_of(entries: &[(&str, Option<&str>, DateTime<Utc>)]) -> DenylistCache {
+        DenylistCache {
+            entries: entries
+                .iter()
+                .map(|(key, except_sid, revoked_at)| {
+                    let entry = RevokedAccess {
+                        id: ObjectId::new(),
+                        key: key.to_string(),
+                        exceptSid: except_sid.map(str::to_owned),
+                        revokedAt: *revoked_at,
+                        expiresAt: *revoked_at + Duration::minutes(15),
+                    };
+                    (entry.key.clone(), entry)
+                })
+                .collect(),
+            loaded_at: None,
+        }
+    }
+
+    #[test]
+    fn token_and_session_entries_revoke() {
+        let now = Utc::now();
+        let claims = claims_of("user-1", "family-a");
+
+        assert!(!cache_of(&[]).revokes(&claims, now));
+        assert!(cache_of(&[("jti:test-jti", None, now)]).revokes(&claims, now));
+        assert!(cache_of(&[("sid:family-a", None, now)]).revokes(&claims, now));
+        assert!(!cache_of(&[("sid:family-b", None, now)]).revokes(&claims, now));
+    }
+
+    #[test]
+    fn user_entry_revokes_older_tokens_of_other_sessions() {
+        let now = Utc::now();
+        let mut claims = claims_of("user-1", "family-a");
+        claims.iat = (now - Duration::minutes(1)).timestamp() as usize;
+
+        assert!(cache_of(&[("user:user-1", None, now)]).revokes(&claims, now));
+        assert!(!cache_of(&[("user:user-1", Some("family-a"), now)]).revokes(&claims, now));
+
+        // issued in the same second, maybe just before the revocation
+        claims.iat = now.timestamp() as usize;
+
+        assert!(cache_of(&[("user:user-1", None, now)]).revokes(&claims, now));
+
+        claims.iat = (now + Duration::seconds(1)).timestamp() as usize;
+
+        assert!(!cache_of(&[("user:user-1", None, now)]).revokes(&claims, now));
+    }
+
+    #[test]
+    fn expired_entries_are_ignored() {
+        let now = Utc::now();
+        let claims = claims_of("user-1", "family-a");
+
+        let cache = cache_of(&[("jti:test-jti", None, now - Duration::minutes(20))]);
+
+        assert!(!cache.revokes(&claims, now));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn later_exception_keeps_an_earlier_full_revocation() {
+        let db = test_db().await;
+        let service = AccessDenylistService::new(db.clone());
+        let mut claims = claims_of("user-1", "family-a");
+        claims.iat = (Utc::now() - Duration::minutes(1)).timestamp() as usize;
+
+        service.revoke_user("user-1", None).await.unwrap();
+        service
+            .revoke_user("user-1", Some("family-a"))
+            .await
+            .unwrap();
+
+        // a fresh instance reads the stored entry instead of its own cache
+        let service = AccessDenylistService::new(db.clone());
+        assert!(service.is_revoked(&claims).await.unwrap());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn exception_applies_without_an_earlier_revocation() {
+        let db = test_db().await;
+        let service = AccessDenylistService::new(db.clone());
+        let mut claims = claims_of("user-1", "family-a");
+        claims.iat = (Utc::now() - Duration::minutes(1)).timestamp() as usize;
+
+        service
+            .revoke_user("user-1", Some("family-a"))
+            .await
+            .unwrap();
+        service
+            .revoke_user("user-1", Some("family-a"))
+            .await
+            .unwrap();
+
+        let service = AccessDenylistService::new(db.clone());
+        assert!(!service.is_revoked(&claims).await.unwrap());
+        claims.sid = "family-b".to_owned();
+        assert!(service.is_revoked(&claims).await.unwrap());
+    }
+}
@@ -0,0 +1,260 @@
+use bson::{Document, doc, oid::ObjectId};
+use chrono::{DateTime, Duration, Utc};
+use futures::TryStreamExt;
+use mongodb::Database;
+
+use crate::{
+    models::{
+        account_purge::{ACCOUNT_PURGES_COLL, AccountPurge, NewAccountPurge},
+        data_export::DATA_EXPORTS_COLL,
+        email_verif_token::EMAIL_VERIF_TOKENS_COLL,
+        linked_identity::LINKED_IDENTITIES_COLL,
+        login_attempt::LOGIN_ATTEMPTS_COLL,
+        refresh_token::REFRESH_TOKENS_COLL,
+        reset_pass_token::RESET_PASS_TOKENS_COLL,
+        security_event::SECURITY_EVENTS_COLL,
+        user::USERS_COLL,
+        username_history::USERNAME_HISTORY_COLL,
+        webauthn_credential::WEBAUTHN_CREDENTIALS_COLL,
+    },
+    services::login_attempt_service::LoginAttemptService,
+    types::error::CustomError,
+};
+
+/// First retry of a failed purge step, doubled on every further failure
+const PURGE_RETRY_BASE_SECS: i64 = 60;
+const PURGE_RETRY_MAX_SECS: i64 = 6 * 3600;
+
+pub struct AccountPurgeService {
+    db: Database,
+}
+
+impl AccountPurgeService {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Every collection holding documents of the user, with the filter matching them.
+    /// The user document itself comes last so a failed purge can still be found
+    pub fn user_data_filters(user_id: &ObjectId) -> Vec<(&'static str, Document)> {
+        let by_user_id = || doc! { "userId": user_id };
+
+        vec![
+            (SECURITY_EVENTS_COLL, by_user_id()),
+            (USERNAME_HISTORY_COLL, by_user_id()),
+            (DATA_EXPORTS_COLL, by_user_id()),
+            (
+                LOGIN_ATTEMPTS_COLL,
+                doc! { "key": LoginAttemptService::user_key(&user_id.to_hex()) },
+            ),
+            (REFRESH_TOKENS_COLL, by_user_id()),
+            (EMAIL_VERIF_TOKENS_COLL, by_user_id()),
+            (RESET_PASS_TOKENS_COLL, by_user_id()),
+            (WEBAUTHN_CREDENTIALS_COLL, by_user_id()),
+            (LINKED_IDENTITIES_COLL, by_user_id()),
+            (USERS_COLL, doc! { "_id": user_id }),
+        ]
+    }
+
+    /// When to try again after `attempts` failed attempts
+    pub fn next_attempt_at(attempts: u32, now: DateTime<Utc>) -> DateTime<Utc> {
+        let delay = PURGE_RETRY_BASE_SECS
+            .saturating_mul(1 << attempts.min(16))
+            .min(PURGE_RETRY_MAX_SECS);
+
+        now + Duration::seconds(delay)
+    }
+
+    /// Schedule the purge of the user, replacing an earlier schedule so a deletion
+    /// which failed halfway can be requested again
+    pub async fn schedule_purge(&self, data: &NewAccountPurge) -> Result<(), CustomError> {
+        match self
+            .db
+            .collection::<NewAccountPurge>(ACCOUNT_PURGES_COLL)
+            .replace_one(doc! { "userId": data.userId }, data)
+            .upsert(true)
+            .await
+        {
+            Ok(_) => {
+                tracing::info!("Scheduled purge of user {}", data.userId);
+                Ok(())
+            }
+            Err(err) => {
+                tracing::error!("Error scheduling purge of user {}: {:?}", data.userId, err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    /// Purges with a step to run at `now`
+    pub async fn get_due_purges(
+        &self,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<AccountPurge>, CustomError> {
+        let cursor = self
+            .db
+            .collection::<AccountPurge>(ACCOUNT_PURGES_COLL)
+            .find(doc! { "nextAttemptAt": { "$lte": now } })
+            .await
+            .map_err(|err| {
+                tracing::error!("Error finding due account purges: {:?}", err);
+                CustomError::MongoError(err)
+            })?;
+
+        cursor.try_collect().await.map_err(|err| {
+            tracing::error!("Error collecting due account purges: {:?}", err);
+            CustomError::MongoError(err)
+        })
+    }
+
+    /// The R2 objects are gone, the rest waits for the end of the grace period
+    pub async fn mark_objects_deleted(&self, purge: &AccountPurge) -> Result<(), CustomError> {
+        self.update_purge(
+            &purge.id,
+            doc! {
+                "$set": {
+                    "objectsDeleted": true,
+                    "attempts": 0,
+                    "nextAttemptAt": purge.purgeAt
+                }
+            },
+        )
+        .await
+    }
+
+    pub async fn retry_later(&self, purge: &AccountPurge) -> Result<(), CustomError> {
+        self.update_purge(
+            &purge.id,
+            doc! {
+                "$inc": { "attempts": 1 },
+                "$set": {
+                    "nextAttemptAt": Self::next_attempt_at(purge.attempts, Utc::now())
+                }
+            },
+        )
+        .await
+    }
+
+    /// Delete every document of the user and then the purge record itself,
+    /// returns how many documents were deleted
+    pub async fn purge_user_data(&self, purge: &AccountPurge) -> Result<u64, CustomError> {
+        let mut deleted = 0;
+
+        for (collection, filter) in Self::user_data_filters(&purge.userId) {
+            deleted += self
+                .db
+                .collection::<Document>(collection)
+                .delete_many(filter)
+                .await
+                .map_err(|err| {
+                    tracing::error!(
+                        "Error purging {} of user {}: {:?}",
+                        collection,
+                        purge.userId,
+                        err
+                    );
+                    CustomError::MongoError(err)
+                })?
+                .deleted_count;
+        }
+
+        match self
+            .db
+            .collection::<AccountPurge>(ACCOUNT_PURGES_COLL)
+            .delete_one(doc! { "_id": purge.id })
+            .await
+        {
+            Ok(_) => Ok(deleted),
+            Err(err) => {
+                tracing::error!("Error removing account purge {}: {:?}", purge.id, err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    /// Drop a purge whose account was never deleted
+    pub async fn cancel_purge(&self, purge: &AccountPurge) -> Result<(), CustomError> {
+        match self
+            .db
+            .collection::<AccountPurge>(ACCOUNT_PURGES_COLL)
+            .delete_one(doc! { "_id": purge.id })
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                tracing::error!("Error removing account purge {}: {:?}", purge.id, err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    async fn update_purge(&self, id: &ObjectId, update: Document) -> Result<(), CustomError> {
+        match self
+            .db
+            .collection::<AccountPurge>(ACCOUNT_PURGES_COLL)
+            .update_one(doc! { "_id": id }, update)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                tracing::error!("Error updating account purge {}: {:?}", id, err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn purge_covers_every_user_keyed_collection() {
+        let user_id = ObjectId::new();
+        let filters = AccountPurgeService::user_data_filters(&user_id);
+        let collections: Vec<&str> = filters.iter().map(|(name, _)| *name).collect();
+
+        for name in [
+            SECURITY_EVENTS_COLL,
+            USERNAME_HISTORY_COLL,
+            DATA_EXPORTS_COLL,
+            LOGIN_ATTEMPTS_COLL,
+            REFRESH_TOKENS_COLL,
+            EMAIL_VERIF_TOKENS_COLL,
+            RESET_PASS_TOKENS_COLL,
+            WEBAUTHN_CREDENTIALS_COLL,
+            LINKED_IDENTITIES_COLL,
+        ] {
+            assert!(collections.contains(&name), "{} is not purged", name);
+        }
+        assert_eq!(collections.last(), Some(&USERS_COLL));
+
+        let login_attempts = &filters
+            .iter()
+            .find(|(name, _)| *name == LOGIN_ATTEMPTS_COLL)
+            .unwrap()
+            .1;
+        assert_eq!(
+            login_attempts.get_str("key").unwrap(),
+            format!("user:{}", user_id.to_hex())
+        );
+    }
+
+    #[test]
+    fn retries_back_off_up_to_a_cap() {
+        let now = Utc::now();
+
+        assert_eq!(
+            AccountPurgeService::next_attempt_at(0, now),
+            now + Duration::seconds(PURGE_RETRY_BASE_SECS)
+        );
+        assert_eq!(
+            AccountPurgeService::next_attempt_at(3, now),
+            now + Duration::seconds(PURGE_RETRY_BASE_SECS * 8)
+        );
+        assert_eq!(
+            AccountPurgeService::next_attempt_at(u32::MAX, now),
+            now + Duration::seconds(PURGE_RETRY_MAX_SECS)
+        );
+    }
+}
@@ -1,23 +1,46 @@
 use crate::{
+    config::password_hashing::ARGON2_PARAMS,
     dtos::auth_dto::AuthResDto,
-    types::{claims::Claims, error::CustomError, keys::KEYS, refresh_claims::RefreshClaims},
+    types::{
+        claims::Claims,
+        error::CustomError,
+        keys::KEYS,
+        mfa_claims::MfaClaims,
+        refresh_claims::RefreshClaims,
+        role::{Permission, Role},
+    },
     utils::datetime::now_epoch,
 };
 use argon2::{
-    Argon2,
+    Argon2, Params, Version,
     password_hash::{
         Error, PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng,
     },
 };
 use bson::uuid;
-use jsonwebtoken::{Algorithm, Header, Validation, decode, encode, errors::ErrorKind};
+use jsonwebtoken::{Algorithm, Validation, decode, encode, errors::ErrorKind};
 use rand::TryRngCore;
 use sha2::{Digest, Sha256};
-use std::env::var;
+use std::{env::var, sync::LazyLock};
+
+pub const ACCESS_EXP_SECS: u32 = 15 * 60;
+pub const REFRESH_EXP_SECS: u32 = 7 * 24 * 3600;
+pub const EMAIL_VERIFICATION_EXP_SECS: u32 = 3600;
+pub const RESET_PASS_EXP_SECS: u32 = 30 * 60;
+pub const VERIF_EMAIL_RESEND_COOLDOWN_SECS: i64 = 60;
+pub const VERIF_EMAIL_DAILY_CAP: usize = 5;
+pub const MAGIC_LINK_EXP_SECS: u32 = 15 * 60;
+/// Grace period between deleting an account and purging it
+pub const ACCOUNT_PURGE_AFTER_DAYS: i64 = 30;
+const MFA_EXP_SECS: u32 = 5 * 60;
 
-const ACCESS_EXP_MINUTES: u32 = 15 * 60;
-pub const REFRESH_EXP_DAYS: u32 = 7 * 24 * 3600;
-pub const EMAIL_VERIFICATION_EXP_MINUTES: u32 = 3600;
+/// Hash of a random password with the configured params, verified against when
+/// the account doesn't exist so unknown emails answer as slowly as wrong passwords
+static DUMMY_PASSWORD_HASH: LazyLock<String> = LazyLock::new(|| {
+    AuthService::new()
+        .hash_password(uuid::Uuid::new().to_string())
+        .expect("Can't hash the dummy password")
+});
 
 pub struct AuthService;
 
@@ -26,53 +49,118 @@ impl AuthService {
         Self {}
     }
 
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(
+            argon2::Algorithm::Argon2id,
+            Version::V0x13,
+            ARGON2_PARAMS.clone(),
+        )
+    }
+
     pub fn hash_password(&self, password: String) -> Result<String, Error> {
         let password_as_bytes = password.as_bytes();
         let salt = SaltString::generate(&mut OsRng);
 
-        let argon2 = Argon2::default();
-
-        let password_hash = argon2.hash_password(password_as_bytes, &salt)?.to_string();
+        let password_hash = self
+            .argon2()
+            .hash_password(password_as_bytes, &salt)?
+            .to_string();
 
         Ok(password_hash)
     }
 
+    /// Verify against an Argon2 PHC string using the parameters stored in it,
+    /// or a bcrypt hash of an imported user
     pub fn verify_password(&self, password: String, password_hash: String) -> Result<(), Error> {
+        if is_bcrypt_hash(&password_hash) {
+            return match bcrypt::verify(&password, &password_hash) {
+                Ok(true) => Ok(()),
+                Ok(false) => Err(Error::Password),
+                Err(err) => {
+                    tracing::error!("Error verifying bcrypt hash: {:?}", err);
+                    Err(Error::Password)
+                }
+            };
+        }
+
         let password_as_bytes = password.as_bytes();
 
         let parsed_hash = PasswordHash::new(&password_hash)?;
 
-        Argon2::default().verify_password(password_as_bytes, &parsed_hash)
+        self.argon2()
+            .verify_password(password_as_bytes, &parsed_hash)
+    }
+
+    /// Verify `password` against a hash no password matches, see `DUMMY_PASSWORD_HASH`
+    pub fn verify_dummy_password(&self, password: String) {
+        let _ = self.verify_password(password, DUMMY_PASSWORD_HASH.clone());
+    }
+
+    /// Whether a stored hash uses a legacy algorithm or weaker Argon2
+    /// parameters than the configured ones
+    pub fn needs_rehash(&self, password_hash: &str) -> bool {
+        if is_bcrypt_hash(password_hash) {
+            return true;
+        }
+
+        let Ok(parsed_hash) = PasswordHash::new(password_hash) else {
+            return true;
+        };
+
+        if parsed_hash.algorithm != argon2::Algorithm::Argon2id.ident()
+            || parsed_hash.version != Some(Version::V0x13.into())
+        {
+            return true;
+        }
+
+        match Params::try_from(&parsed_hash) {
+            Ok(params) => {
+                params.m_cost() < ARGON2_PARAMS.m_cost()
+                    || params.t_cost() < ARGON2_PARAMS.t_cost()
+                    || params.p_cost() < ARGON2_PARAMS.p_cost()
+            }
+            Err(_) => true,
+        }
+    }
+
+    /// Create an id for a new chain of rotated refresh tokens, i.e. a new login session
+    pub fn new_token_family(&self) -> String {
+        uuid::Uuid::new().to_string()
     }
 
     pub fn generate_tokens(
         &self,
         user_id: &str,
+        family_id: &str,
+        roles: &[Role],
+        permissions: &[Permission],
     ) -> Result<(AuthResDto, String, usize), CustomError> {
         let claims = Claims {
             sub: user_id.to_owned(),
-            exp: now_epoch() + ACCESS_EXP_MINUTES as usize,
+            exp: now_epoch() + ACCESS_EXP_SECS as usize,
+            sid: family_id.to_owned(),
+            jti: uuid::Uuid::new().to_string(),
+            iat: now_epoch(),
+            roles: roles.to_vec(),
+            perms: permissions.to_vec(),
             aud: var("JWT_AUDIENCE").expect("JWT_AUDIENCE missing"),
             iss: var("JWT_ISSUER").expect("JWT_ISSUER missing"),
         };
 
         let refresh_claims = RefreshClaims {
             sub: user_id.to_owned(),
-            exp: now_epoch() + REFRESH_EXP_DAYS as usize,
+            exp: now_epoch() + REFRESH_EXP_SECS as usize,
             jti: uuid::Uuid::new().to_string(),
-            aud: var("JWT_AUDIENCE").expect("JWT_AUDIENCE missing"),
+            fam: family_id.to_owned(),
+            aud: self.refresh_audience(),
             iss: var("JWT_ISSUER").expect("JWT_ISSUER missing"),
         };
 
-        let access_token = encode(&Header::new(Algorithm::EdDSA), &claims, &KEYS.encoding)
+        let access_token = encode(&KEYS.header(), &claims, &KEYS.encoding)
             .map_err(|_| CustomError::TokenCreation)?;
 
-        let refresh_token = encode(
-            &Header::new(Algorithm::EdDSA),
-            &refresh_claims,
-            &KEYS.encoding,
-        )
-        .map_err(|_| CustomError::TokenCreation)?;
+        let refresh_token = encode(&KEYS.header(), &refresh_claims, &KEYS.encoding)
+            .map_err(|_| CustomError::TokenCreation)?;
 
         Ok((
             AuthResDto::new(access_token, refresh_token),
@@ -81,16 +169,85 @@ impl AuthService {
         ))
     }
 
-    pub fn decode_refresh_token(&self, refresh_token: &str) -> Result<RefreshClaims, CustomError> {
+    /// Validate an access token the same way for every route and for introspection.
+    /// Refresh and MFA tokens have their own audiences and are rejected here
+    pub fn decode_access_token(&self, access_token: &str) -> Result<Claims, CustomError> {
         let mut validation = Validation::new(Algorithm::EdDSA);
         validation.set_audience(&[var("JWT_AUDIENCE").expect("JWT_AUDIENCE missing")]);
         validation.set_issuer(&[var("JWT_ISSUER").expect("JWT_ISSUER missing")]);
 
-        match decode::<RefreshClaims>(
+        match decode::<Claims>(access_token, KEYS.decoding_key(access_token)?, &validation) {
+            Ok(value) => Ok(value.claims),
+            Err(err) => match err.kind() {
+                ErrorKind::ExpiredSignature => Err(CustomError::TokenExpired),
+                _ => Err(CustomError::InvalidToken),
+            },
+        }
+    }
+
+    /// Audience of refresh tokens, distinct so they can't be used as access tokens
+    fn refresh_audience(&self) -> String {
+        format!(
+            "{}/refresh",
+            var("JWT_AUDIENCE").expect("JWT_AUDIENCE missing")
+        )
+    }
+
+    /// Refresh tokens issued before they got their own audience carry the access token
+    /// audience and are still accepted, so existing sessions survive the deploy. They
+    /// run out `REFRESH_EXP_SECS` after it, then the fallback can be removed
+    pub fn decode_refresh_token(&self, refresh_token: &str) -> Result<RefreshClaims, CustomError> {
+        let access_audience = var("JWT_AUDIENCE").expect("JWT_AUDIENCE missing");
+
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_audience(&[self.refresh_audience(), access_audience.clone()]);
+        validation.set_issuer(&[var("JWT_ISSUER").expect("JWT_ISSUER missing")]);
+
+        let claims = match decode::<RefreshClaims>(
             refresh_token,
-            &KEYS.decoding,
+            KEYS.decoding_key(refresh_token)?,
             &validation,
         ) {
+            Ok(value) => value.claims,
+            Err(err) => {
+                return match err.kind() {
+                    ErrorKind::ExpiredSignature => Err(CustomError::TokenExpired),
+                    _ => Err(CustomError::InvalidToken),
+                };
+            }
+        };
+
+        // Access tokens carry the old audience too, unlike old refresh tokens they decode as such
+        if claims.aud == access_audience && self.decode_access_token(refresh_token).is_ok() {
+            return Err(CustomError::InvalidToken);
+        }
+
+        Ok(claims)
+    }
+
+    /// Audience of MFA pending tokens, distinct so they can't be used as access tokens
+    fn mfa_audience(&self) -> String {
+        format!("{}/mfa", var("JWT_AUDIENCE").expect("JWT_AUDIENCE missing"))
+    }
+
+    pub fn generate_mfa_token(&self, user_id: &str) -> Result<String, CustomError> {
+        let claims = MfaClaims {
+            sub: user_id.to_owned(),
+            exp: now_epoch() + MFA_EXP_SECS as usize,
+            jti: uuid::Uuid::new().to_string(),
+            aud: self.mfa_audience(),
+            iss: var("JWT_ISSUER").expect("JWT_ISSUER missing"),
+        };
+
+        encode(&KEYS.header(), &claims, &KEYS.encoding).map_err(|_| CustomError::TokenCreation)
+    }
+
+    pub fn decode_mfa_token(&self, mfa_token: &str) -> Result<MfaClaims, CustomError> {
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_audience(&[self.mfa_audience()]);
+        validation.set_issuer(&[var("JWT_ISSUER").expect("JWT_ISSUER missing")]);
+
+        match decode::<MfaClaims>(mfa_token, KEYS.decoding_key(mfa_token)?, &validation) {
             Ok(value) => Ok(value.claims),
             Err(err) => match err.kind() {
                 ErrorKind::ExpiredSignature => Err(CustomError::TokenExpired),
@@ -100,6 +257,15 @@ impl AuthService {
     }
 
     pub fn generate_email_verification_token(&self) -> Result<(String, String), CustomError> {
+        self.generate_raw_token()
+    }
+
+    pub fn generate_reset_pass_token(&self) -> Result<(String, String), CustomError> {
+        self.generate_raw_token()
+    }
+
+    /// Generate a random token, returned as (raw_token, token_hash)
+    fn generate_raw_token(&self) -> Result<(String, String), CustomError> {
         let mut bytes = [0u8; 32];
         rand::rngs::OsRng
             .try_fill_bytes(&mut bytes)
@@ -118,3 +284,213 @@ impl AuthService {
         hex::encode(hasher.finalize())
     }
 }
+
+fn is_bcrypt_hash(password_hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"]
+        .iter()
+        .any(|prefix| password_hash.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::init_env;
+
+    fn tokens() -> (String, String) {
+        init_env();
+
+        let (tokens, _, _) = AuthService::new()
+            .generate_tokens("user", "family", &[], &[])
+            .unwrap();
+
+        (tokens.access_token, tokens.refresh_token.unwrap())
+    }
+
+    #[test]
+    fn dummy_hash_costs_as_much_as_a_real_one() {
+        let auth_service = AuthService::new();
+
+        assert!(!auth_service.needs_rehash(&DUMMY_PASSWORD_HASH));
+        assert!(
+            auth_service
+                .verify_password("password".to_owned(), DUMMY_PASSWORD_HASH.clone())
+                .is_err()
+        );
+    }
+
+    fn argon2_hash(algorithm: argon2::Algorithm, params: Params, password: &str) -> String {
+        Argon2::new(algorithm, Version::V0x13, params)
+            .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn current_hashes_are_kept() {
+        let auth_service = AuthService::new();
+        let password_hash = auth_service.hash_password("password".to_owned()).unwrap();
+
+        assert!(!auth_service.needs_rehash(&password_hash));
+    }
+
+    #[test]
+    fn weaker_argon2_hashes_are_upgraded() {
+        let auth_service = AuthService::new();
+        let weak_params = Params::new(ARGON2_PARAMS.m_cost() / 2, 1, 1, None).unwrap();
+
+        let weak_hash = argon2_hash(argon2::Algorithm::Argon2id, weak_params, "password");
+        let argon2i_hash = argon2_hash(
+            argon2::Algorithm::Argon2i,
+            ARGON2_PARAMS.clone(),
+            "password",
+        );
+
+        assert!(auth_service.needs_rehash(&weak_hash));
+        assert!(auth_service.needs_rehash(&argon2i_hash));
+        // The stored parameters are used, so old hashes still verify
+        assert!(
+            auth_service
+                .verify_password("password".to_owned(), weak_hash)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn bcrypt_hashes_verify_and_are_upgraded() {
+        let auth_service = AuthService::new();
+        let bcrypt_hash = bcrypt::hash("password", 4).unwrap();
+
+        assert!(auth_service.needs_rehash(&bcrypt_hash));
+        assert!(
+            auth_service
+                .verify_password("password".to_owned(), bcrypt_hash.clone())
+                .is_ok()
+        );
+        assert!(
+            auth_service
+                .verify_password("wrong".to_owned(), bcrypt_hash)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn unparsable_hashes_are_upgraded() {
+        assert!(AuthService::new().needs_rehash("not-a-hash"));
+    }
+
+    #[test]
+    fn access_token_carries_roles_and_permissions() {
+        init_env();
+
+        let auth_service = AuthService::new();
+        let (tokens, _, _) = auth_service
+            .generate_tokens("user", "family", &[Role::Admin], &[Permission::ReadUsers])
+            .unwrap();
+
+        let claims = auth_service
+            .decode_access_token(&tokens.access_token)
+            .unwrap();
+
+        assert_eq!(claims.roles, vec![Role::Admin]);
+        assert_eq!(claims.perms, vec![Permission::ReadUsers]);
+    }
+
+    #[test]
+    fn access_token_decodes_as_access_token() {
+        let (access_token, _) = tokens();
+
+        let claims = AuthService::new()
+            .decode_access_token(&access_token)
+            .unwrap();
+
+        assert_eq!(claims.sub, "user");
+        assert_eq!(claims.sid, "family");
+        assert!(!claims.jti.is_empty());
+    }
+
+    #[test]
+    fn refresh_token_is_not_an_access_token() {
+        let (_, refresh_token) = tokens();
+
+        assert!(matches!(
+            AuthService::new().decode_access_token(&refresh_token),
+            Err(CustomError::InvalidToken)
+        ));
+    }
+
+    #[test]
+    fn access_token_is_not_a_refresh_token() {
+        let (access_token, refresh_token) = tokens();
+        let auth_service = AuthService::new();
+
+        assert!(auth_service.decode_refresh_token(&refresh_token).is_ok());
+        assert!(matches!(
+            auth_service.decode_refresh_token(&access_token),
+            Err(CustomError::InvalidToken)
+        ));
+    }
+
+    #[test]
+    fn refresh_token_from_before_its_audience_is_accepted() {
+        init_env();
+
+        // Shaped like refresh tokens issued before they had their own audience
+        let legacy = RefreshClaims {
+            sub: "user".to_owned(),
+            exp: now_epoch() + 60,
+            jti: "jti".to_owned(),
+            fam: String::new(),
+            aud: var("JWT_AUDIENCE").unwrap(),
+            iss: var("JWT_ISSUER").unwrap(),
+        };
+        let token = encode(&KEYS.header(), &legacy, &KEYS.encoding).unwrap();
+
+        let claims = AuthService::new().decode_refresh_token(&token).unwrap();
+
+        assert_eq!(claims.jti, "jti");
+    }
+
+    #[test]
+    fn mfa_token_is_not_an_access_token() {
+        init_env();
+        let auth_service = AuthService::new();
+
+        let mfa_token = auth_service.generate_mfa_token("user").unwrap();
+
+        assert!(auth_service.decode_access_token(&mfa_token).is_err());
+    }
+
+    #[test]
+    fn access_token_without_session_claims_is_rejected() {
+        init_env();
+
+        // Shaped like tokens issued before sid, jti and iat existed
+        let legacy = RefreshClaims {
+            sub: "user".to_owned(),
+            exp: now_epoch() + 60,
+            jti: "jti".to_owned(),
+            fam: String::new(),
+            aud: var("JWT_AUDIENCE").unwrap(),
+            iss: var("JWT_ISSUER").unwrap(),
+        };
+        let token = encode(&KEYS.header(), &legacy, &KEYS.encoding).unwrap();
+
+        assert!(matches!(
+            AuthService::new().decode_access_token(&token),
+            Err(CustomError::InvalidToken)
+        ));
+    }
+
+    #[test]
+    fn reset_tokens_are_random_and_only_their_hash_is_kept() {
+        let auth_service = AuthService::new();
+
+        let (raw_token, token_hash) = auth_service.generate_reset_pass_token().unwrap();
+        let (other_token, _) = auth_service.generate_reset_pass_token().unwrap();
+
+        assert_eq!(raw_token.len(), 64);
+        assert_ne!(raw_token, other_token);
+        assert_ne!(raw_token, token_hash);
+        assert_eq!(auth_service.hash_raw_token(&raw_token), token_hash);
+    }
+}
@@ -0,0 +1,272 @@
+use bson::{doc, oid::ObjectId};
+use chrono::{DateTime, Duration, Utc};
+use futures::TryStreamExt;
+use mongodb::Database;
+use reqwest::Client;
+use std::{env::var, time::Duration as StdDuration};
+
+use crate::{
+    config::data_export::DATA_EXPORT_SOURCES,
+    models::data_export::{DATA_EXPORTS_COLL, DataExport, DataExportStatus, NewDataExport},
+    types::error::CustomError,
+};
+
+/// How long the emailed download link works
+pub const DATA_EXPORT_LINK_EXP_SECS: u64 = 24 * 3600;
+/// A user can request one export per this many hours
+pub const DATA_EXPORT_COOLDOWN_HOURS: i64 = 24;
+/// A service which doesn't answer within this fails the export instead of holding it
+const DATA_EXPORT_SOURCE_TIMEOUT_SECS: u64 = 30;
+/// An export still pending after this many minutes was abandoned, e.g. by a restart
+pub const DATA_EXPORT_ABANDONED_MINS: i64 = 30;
+
+pub struct DataExportService {
+    db: Database,
+    client: Client,
+    service_token: Option<String>,
+}
+
+impl DataExportService {
+    pub fn new(db: Database) -> Self {
+        Self {
+            db,
+            client: Client::builder()
+                .timeout(StdDuration::from_secs(DATA_EXPORT_SOURCE_TIMEOUT_SECS))
+                .build()
+                .expect("Failed to build the data export HTTP client"),
+            service_token: var("DATA_EXPORT_SERVICE_TOKEN").ok(),
+        }
+    }
+
+    pub async fn create_export(&self, data: &NewDataExport) -> Result<ObjectId, CustomError> {
+        match self
+            .db
+            .collection::<NewDataExport>(DATA_EXPORTS_COLL)
+            .insert_one(data)
+            .await
+        {
+            Ok(v) => {
+                tracing::info!("Created data export with id: {}", v.inserted_id);
+                Ok(v.inserted_id.as_object_id().unwrap())
+            }
+            Err(err) => {
+                tracing::error!("Error creating data export: {:?}", err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    /// Exports of the user which didn't fail, requested after `since`, newest first
+    pub async fn get_exports_created_since(
+        &self,
+        user_id: &ObjectId,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<DataExport>, CustomError> {
+        let cursor = self
+            .db
+            .collection::<DataExport>(DATA_EXPORTS_COLL)
+            .find(doc! {
+                "userId": user_id,
+                "status": { "$ne": DataExportStatus::Failed.as_str() },
+                "createdAt": { "$gt": since }
+            })
+            .sort(doc! { "createdAt": -1 })
+            .await
+            .map_err(|err| {
+                tracing::debug!("Error finding data exports of {}: {}", user_id, err);
+                CustomError::MongoError(err)
+            })?;
+
+        cursor.try_collect().await.map_err(|err| {
+            tracing::debug!("Error collecting data exports of {}: {}", user_id, err);
+            CustomError::MongoError(err)
+        })
+    }
+
+    /// Remember the uploaded archive, so it's cleaned up whatever happens to the export
+    pub async fn set_object_key(&self, id: &ObjectId, object_key: &str) -> Result<(), CustomError> {
+        self.update_export(id, doc! { "$set": { "objectKey": object_key } })
+            .await
+    }
+
+    pub async fn complete_export(&self, id: &ObjectId) -> Result<(), CustomError> {
+        self.update_export(
+            id,
+            doc! {
+                "$set": {
+                    "status": DataExportStatus::Completed.as_str(),
+                    "completedAt": Utc::now()
+                }
+            },
+        )
+        .await
+    }
+
+    pub async fn fail_export(&self, id: &ObjectId) -> Result<(), CustomError> {
+        self.update_export(
+            id,
+            doc! {
+                "$set": {
+                    "status": DataExportStatus::Failed.as_str(),
+                    "completedAt": Utc::now()
+                }
+            },
+        )
+        .await
+    }
+
+    /// Fail the exports which are pending since before `DATA_EXPORT_ABANDONED_MINS`,
+    /// as their task is gone and they would hold back the cooldown forever
+    pub async fn fail_abandoned_exports(&self, now: DateTime<Utc>) -> Result<u64, CustomError> {
+        match self
+            .db
+            .collection::<DataExport>(DATA_EXPORTS_COLL)
+            .update_many(
+                doc! {
+                    "status": DataExportStatus::Pending.as_str(),
+                    "createdAt": { "$lte": now - Duration::minutes(DATA_EXPORT_ABANDONED_MINS) }
+                },
+                doc! {
+                    "$set": {
+                        "status": DataExportStatus::Failed.as_str(),
+                        "completedAt": now
+                    }
+                },
+            )
+            .await
+        {
+            Ok(value) => Ok(value.modified_count),
+            Err(err) => {
+                tracing::error!("Error failing abandoned data exports: {:?}", err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    /// Exports whose archive is of no use anymore at `now`: completed ones whose
+    /// download link ran out and failed ones which got as far as the upload
+    pub async fn get_stale_exports(
+        &self,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<DataExport>, CustomError> {
+        let cursor = self
+            .db
+            .collection::<DataExport>(DATA_EXPORTS_COLL)
+            .find(doc! {
+                "$or": [
+                    {
+                        "status": DataExportStatus::Completed.as_str(),
+                        "completedAt": { "$lte": Self::links_expired_before(now) }
+                    },
+                    {
+                        "status": DataExportStatus::Failed.as_str(),
+                        "objectKey": { "$exists": true }
+                    }
+                ]
+            })
+            .await
+            .map_err(|err| {
+                tracing::error!("Error finding stale data exports: {:?}", err);
+                CustomError::MongoError(err)
+            })?;
+
+        cursor.try_collect().await.map_err(|err| {
+            tracing::error!("Error collecting stale data exports: {:?}", err);
+            CustomError::MongoError(err)
+        })
+    }
+
+    /// The archive of the export was deleted. A failed export stays failed, so it
+    /// doesn't hold back the next request
+    pub async fn expire_export(&self, export: &DataExport) -> Result<(), CustomError> {
+        let update = if export.status == DataExportStatus::Failed {
+            doc! { "$unset": { "objectKey": "" } }
+        } else {
+            doc! {
+                "$set": { "status": DataExportStatus::Expired.as_str() },
+                "$unset": { "objectKey": "" }
+            }
+        };
+
+        self.update_export(&export.id, update).await
+    }
+
+    /// Links of exports completed before this are expired at `now`
+    pub fn links_expired_before(now: DateTime<Utc>) -> DateTime<Utc> {
+        now - Duration::seconds(DATA_EXPORT_LINK_EXP_SECS as i64)
+    }
+
+    async fn update_export(
+        &self,
+        id: &ObjectId,
+        update: bson::Document,
+    ) -> Result<(), CustomError> {
+        match self
+            .db
+            .collection::<DataExport>(DATA_EXPORTS_COLL)
+            .update_one(doc! { "_id": id }, update)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                tracing::error!("Error updating data export {}: {:?}", id, err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    /// Content the other services hold about the user, as (source name, JSON) pairs
+    pub async fn fetch_external_data(
+        &self,
+        user_id: &str,
+    ) -> Result<Vec<(String, serde_json::Value)>, CustomError> {
+        let mut data = Vec::with_capacity(DATA_EXPORT_SOURCES.len());
+
+        for (name, url) in DATA_EXPORT_SOURCES.iter() {
+            let mut req = self.client.get(url).query(&[("user_id", user_id)]);
+
+            if let Some(token) = &self.service_token {
+                req = req.bearer_auth(token);
+            }
+
+            let value = req
+                .send()
+                .await
+                .and_then(|res| res.error_for_status())
+                .map_err(|err| {
+                    tracing::error!("Error fetching {} export of {}: {:?}", name, user_id, err);
+                    CustomError::ReqwestError(err)
+                })?
+                .json::<serde_json::Value>()
+                .await?;
+
+            data.push((name.clone(), value));
+        }
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn links_expire_once_their_lifetime_is_over() {
+        let now = Utc.with_ymd_and_hms(2026, 3, 2, 12, 0, 0).unwrap();
+
+        assert_eq!(
+            DataExportService::links_expired_before(now),
+            Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn expired_status_is_stored_as_its_name() {
+        let status: DataExportStatus = serde_json::from_str("\"expired\"").unwrap();
+
+        assert_eq!(status, DataExportStatus::Expired);
+        assert_eq!(status.as_str(), "expired");
+    }
+}
@@ -1,12 +1,10 @@
 use reqwest::Client;
 
-use crate::types::{email::Email, error::CustomError, verify_email::VerifyEmail};
-
-use std::env::var;
-
-pub enum EmailTemplateValues {
-    VerifyEmailValues(VerifyEmail),
-}
+use crate::{
+    config::email::EMAIL_CONFIG,
+    services::storage_service::StorageService,
+    types::{email::Email, error::CustomError},
+};
 
 pub struct EmailService {}
 
@@ -15,11 +13,13 @@ impl EmailService {
         Self {}
     }
 
+    /// Replace every `{{name}}` placeholder of the template with its value,
+    /// `values` being the (name, value) pairs of one of the email value types
     pub fn prepare_template(
         &self,
         bytes: &[u8],
         ext: &str,
-        values: EmailTemplateValues,
+        values: &[(&str, &str)],
     ) -> Result<String, CustomError> {
         if !ext.contains("html") {
             return Err(CustomError::EmailTemplateError);
@@ -32,21 +32,41 @@ impl EmailService {
         };
 
         // Implement replace all placeholders with values
-        match values {
-            EmailTemplateValues::VerifyEmailValues(fields) => {
-                for (name, value) in fields.as_array() {
-                    template = template.replace(&format!("{{{{{}}}}}", name), value)
-                }
-
-                Ok(template)
-            }
+        for (name, value) in values {
+            template = template.replace(&format!("{{{{{}}}}}", name), value)
         }
+
+        Ok(template)
+    }
+
+    /// Fill the email template stored in R2 under `template_key` and send it
+    /// to the recipient given as (name, email)
+    pub async fn send_templated_email(
+        &self,
+        storage: &StorageService,
+        template_key: &str,
+        values: &[(&str, &str)],
+        recipient: (&str, &str),
+        subject: &str,
+    ) -> Result<(), CustomError> {
+        let (object_bytes, ext) = storage
+            .get_object(template_key)
+            .await
+            .map_err(|_| CustomError::R2Error)?;
+
+        let object_extension = ext.ok_or(CustomError::R2Error)?;
+
+        let email_html = self.prepare_template(&object_bytes, &object_extension, values)?;
+
+        let email: Email = Email::new(vec![recipient], email_html, subject);
+
+        self.send_transactional_email(email).await
     }
 
     pub async fn send_transactional_email(&self, email: Email) -> Result<(), CustomError> {
         let client = Client::new();
 
-        let api_key = var("BREVO_API_KEY").expect("BREVO_API_KEY missing");
+        let api_key = &EMAIL_CONFIG.brevo_api_key;
 
         let res = client
             .post("https://api.brevo.com/v3/smtp/email")
@@ -58,7 +78,7 @@ impl EmailService {
             .await?;
 
         if res.status().is_success() {
-            tracing::info!("Email verification has been sent");
+            tracing::info!("Transactional email has been sent");
             return Ok(());
         }
 
@@ -70,3 +90,32 @@ impl EmailService {
         Err(CustomError::SendEmailError)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_are_replaced_by_their_values() {
+        let html = EmailService::new()
+            .prepare_template(
+                b"<p>Hi {{username}}, {{username}} go to {{url}} {{unknown}}</p>",
+                "text/html",
+                &[("username", "alice"), ("url", "https://x.example")],
+            )
+            .unwrap();
+
+        assert_eq!(
+            html,
+            "<p>Hi alice, alice go to https://x.example {{unknown}}</p>"
+        );
+    }
+
+    #[test]
+    fn non_html_templates_are_rejected() {
+        assert!(matches!(
+            EmailService::new().prepare_template(b"Hi", "text/plain", &[]),
+            Err(CustomError::EmailTemplateError)
+        ));
+    }
+}
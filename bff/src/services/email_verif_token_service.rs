@@ -1,12 +1,15 @@
-use bson::{doc, oid::ObjectId};
-use chrono::Utc;
+use bson::{Bson, doc, oid::ObjectId};
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
 use mongodb::{
     Database,
     error::{ErrorKind, WriteFailure},
 };
 
 use crate::{
-    models::email_verif_token::{NewEmailVerifToken, EMAIL_VERIF_TOKENS_COLL, EmailVerifToken},
+    models::email_verif_token::{
+        EMAIL_VERIF_TOKENS_COLL, EmailTokenPurpose, EmailVerifToken, NewEmailVerifToken,
+    },
     types::error::CustomError,
 };
 
@@ -66,7 +69,8 @@ impl VerifEmailTokenService {
         &self,
         token_hash: &str,
         user_id: &str,
-    ) -> Result<(), CustomError> {
+        purpose: EmailTokenPurpose,
+    ) -> Result<EmailVerifToken, CustomError> {
         let user_obj_id = ObjectId::parse_str(user_id).map_err(|e| {
             tracing::error!("Error while parsing {}: {:?}", user_id, e);
             CustomError::InvalidIDError(user_id.to_owned())
@@ -79,6 +83,7 @@ impl VerifEmailTokenService {
                 doc! {
                     "tokenHash": token_hash,
                     "userId": user_obj_id,
+                    "purpose": purpose_filter(purpose),
                     "usedAt": { "$eq": null },
                     "expiresAt": { "$gt": Utc::now() }
                 },
@@ -88,7 +93,7 @@ impl VerifEmailTokenService {
             )
             .await
         {
-            Ok(Some(_)) => Ok(()),
+            Ok(Some(token)) => Ok(token),
             Ok(None) => Err(CustomError::InvalidToken),
             Err(err) => {
                 tracing::error!(
@@ -100,4 +105,130 @@ impl VerifEmailTokenService {
             }
         }
     }
+
+    /// Get tokens of the user with the given purpose created after `since`, newest first
+    pub async fn get_tokens_created_since(
+        &self,
+        user_id: &ObjectId,
+        purpose: EmailTokenPurpose,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<EmailVerifToken>, CustomError> {
+        let cursor = self
+            .db
+            .collection::<EmailVerifToken>(EMAIL_VERIF_TOKENS_COLL)
+            .find(doc! {
+                "userId": user_id,
+                "purpose": purpose_filter(purpose),
+                "createdAt": { "$gt": since }
+            })
+            .sort(doc! { "createdAt": -1 })
+            .await
+            .map_err(|err| {
+                tracing::error!("Error finding email verif tokens of {}: {:?}", user_id, err);
+                CustomError::MongoError(err)
+            })?;
+
+        cursor.try_collect().await.map_err(|err| {
+            tracing::error!(
+                "Error collecting email verif tokens of {}: {:?}",
+                user_id,
+                err
+            );
+            CustomError::MongoError(err)
+        })
+    }
+
+    /// Mark every outstanding token of the user with the given purpose as used
+    pub async fn invalidate_user_tokens(
+        &self,
+        user_id: &ObjectId,
+        purpose: EmailTokenPurpose,
+    ) -> Result<(), CustomError> {
+        match self
+            .db
+            .collection::<EmailVerifToken>(EMAIL_VERIF_TOKENS_COLL)
+            .update_many(
+                doc! {
+                    "userId": user_id,
+                    "purpose": purpose_filter(purpose),
+                    "usedAt": { "$eq": null }
+                },
+                doc! {
+                    "$set": { "usedAt": Utc::now() }
+                },
+            )
+            .await
+        {
+            Ok(v) => {
+                tracing::debug!(
+                    "{} email verif tokens have been invalidated",
+                    v.modified_count
+                );
+                Ok(())
+            }
+            Err(err) => {
+                tracing::error!(
+                    "Error invalidating email verif tokens of {}: {:?}",
+                    user_id,
+                    err
+                );
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    /// Delete every token of the user, whatever the purpose
+    pub async fn delete_user_tokens(&self, user_id: &ObjectId) -> Result<u64, CustomError> {
+        match self
+            .db
+            .collection::<EmailVerifToken>(EMAIL_VERIF_TOKENS_COLL)
+            .delete_many(doc! { "userId": user_id })
+            .await
+        {
+            Ok(v) => {
+                tracing::debug!(
+                    "{} email tokens of {} have been deleted",
+                    v.deleted_count,
+                    user_id
+                );
+                Ok(v.deleted_count)
+            }
+            Err(err) => {
+                tracing::error!("Error deleting email tokens of {}: {:?}", user_id, err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    /// Every token of the user, whatever the purpose, newest first
+    pub async fn get_user_tokens(
+        &self,
+        user_id: &ObjectId,
+    ) -> Result<Vec<EmailVerifToken>, CustomError> {
+        let cursor = self
+            .db
+            .collection::<EmailVerifToken>(EMAIL_VERIF_TOKENS_COLL)
+            .find(doc! { "userId": user_id })
+            .sort(doc! { "createdAt": -1 })
+            .await
+            .map_err(|err| {
+                tracing::debug!("Error finding email tokens of {}: {}", user_id, err);
+                CustomError::MongoError(err)
+            })?;
+
+        cursor.try_collect().await.map_err(|err| {
+            tracing::debug!("Error collecting email tokens of {}: {}", user_id, err);
+            CustomError::MongoError(err)
+        })
+    }
+}
+
+/// Tokens created before the purpose field existed are all email verifications
+fn purpose_filter(purpose: EmailTokenPurpose) -> Bson {
+    match purpose {
+        EmailTokenPurpose::VerifyEmail => {
+            Bson::Document(doc! { "$in": [Bson::Null, purpose.as_str()] })
+        }
+        _ => Bson::String(purpose.as_str().to_owned()),
+    }
 }
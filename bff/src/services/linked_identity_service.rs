@@ -0,0 +1,154 @@
+use bson::{doc, oid::ObjectId};
+use chrono::Utc;
+use futures::TryStreamExt;
+use mongodb::{
+    Database,
+    error::{ErrorKind, WriteFailure},
+};
+
+use crate::{
+    models::linked_identity::{
+        LINKED_IDENTITIES_COLL, LinkedIdentity, NewLinkedIdentity, OIDC_AUTH_REQUESTS_COLL,
+        OidcAuthRequest,
+    },
+    types::error::CustomError,
+};
+
+pub struct LinkedIdentityService {
+    db: Database,
+}
+
+impl LinkedIdentityService {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    pub async fn create_auth_request(&self, data: &OidcAuthRequest) -> Result<(), CustomError> {
+        match self
+            .db
+            .collection::<OidcAuthRequest>(OIDC_AUTH_REQUESTS_COLL)
+            .insert_one(data)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                tracing::error!("Error creating OIDC auth request: {:?}", err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    /// Atomically remove the pending request of `state` so a callback is only accepted once
+    pub async fn consume_auth_request(&self, state: &str) -> Result<OidcAuthRequest, CustomError> {
+        match self
+            .db
+            .collection::<OidcAuthRequest>(OIDC_AUTH_REQUESTS_COLL)
+            .find_one_and_delete(doc! {
+                "state": state,
+                "expiresAt": { "$gt": Utc::now() }
+            })
+            .await
+        {
+            Ok(Some(request)) => Ok(request),
+            Ok(None) => Err(CustomError::InvalidToken),
+            Err(err) => {
+                tracing::error!("Error consuming OIDC auth request: {:?}", err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    pub async fn get_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<LinkedIdentity>, CustomError> {
+        self.db
+            .collection::<LinkedIdentity>(LINKED_IDENTITIES_COLL)
+            .find_one(doc! { "provider": provider, "subject": subject })
+            .await
+            .map_err(|err| {
+                tracing::debug!("Error finding linked identity: {}", err);
+                CustomError::MongoError(err)
+            })
+    }
+
+    pub async fn create_identity(&self, data: &NewLinkedIdentity) -> Result<ObjectId, CustomError> {
+        match self
+            .db
+            .collection::<NewLinkedIdentity>(LINKED_IDENTITIES_COLL)
+            .insert_one(data)
+            .await
+        {
+            Ok(v) => {
+                tracing::info!("Linked {} identity to user {}", data.provider, data.userId);
+                Ok(v.inserted_id.as_object_id().unwrap())
+            }
+            Err(error) => {
+                tracing::error!("Error creating linked identity: {:?}", error);
+
+                match error.kind.as_ref() {
+                    ErrorKind::Write(WriteFailure::WriteError(w)) if w.code == 11000 => Err(
+                        CustomError::DuplicateKey(format!("{} identity", data.provider)),
+                    ),
+                    _ => Err(CustomError::MongoError(error)),
+                }
+            }
+        }
+    }
+
+    pub async fn update_last_login(&self, id: &ObjectId, email: &str) -> Result<(), CustomError> {
+        match self
+            .db
+            .collection::<LinkedIdentity>(LINKED_IDENTITIES_COLL)
+            .update_one(
+                doc! { "_id": id },
+                doc! {
+                    "$set": { "email": email, "lastLoginAt": Utc::now() }
+                },
+            )
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                tracing::error!("Error updating linked identity {}: {:?}", id, err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    pub async fn delete_user_identities(&self, user_id: &ObjectId) -> Result<u64, CustomError> {
+        match self
+            .db
+            .collection::<LinkedIdentity>(LINKED_IDENTITIES_COLL)
+            .delete_many(doc! { "userId": user_id })
+            .await
+        {
+            Ok(v) => Ok(v.deleted_count),
+            Err(err) => {
+                tracing::error!("Error deleting linked identities of {}: {:?}", user_id, err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    pub async fn get_user_identities(
+        &self,
+        user_id: &ObjectId,
+    ) -> Result<Vec<LinkedIdentity>, CustomError> {
+        let cursor = self
+            .db
+            .collection::<LinkedIdentity>(LINKED_IDENTITIES_COLL)
+            .find(doc! { "userId": user_id })
+            .await
+            .map_err(|err| {
+                tracing::debug!("Error finding linked identities of {}: {}", user_id, err);
+                CustomError::MongoError(err)
+            })?;
+
+        cursor.try_collect().await.map_err(|err| {
+            tracing::debug!("Error collecting linked identities of {}: {}", user_id, err);
+            CustomError::MongoError(err)
+        })
+    }
+}
@@ -0,0 +1,233 @@
+use bson::doc;
+use chrono::{DateTime, Utc};
+use mongodb::{Database, options::ReturnDocument};
+
+use crate::{
+    models::login_attempt::{LOGIN_ATTEMPTS_COLL, LoginAttempt},
+    types::error::CustomError,
+    utils::email::normalize_email,
+};
+
+/// Failures allowed before each attempt has to wait
+pub const LOGIN_DELAY_AFTER_FAILURES: u32 = 3;
+pub const LOGIN_MAX_DELAY_SECS: i64 = 60;
+/// Failures after which the account gets locked
+pub const LOGIN_LOCKOUT_AFTER_FAILURES: u32 = 10;
+pub const LOGIN_LOCKOUT_SECS: i64 = 15 * 60;
+
+pub struct LoginAttemptService {
+    db: Database,
+}
+
+impl LoginAttemptService {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    pub fn user_key(user_id: &str) -> String {
+        format!("user:{}", user_id)
+    }
+
+    pub fn email_key(email: &str) -> String {
+        format!("email:{}", normalize_email(email))
+    }
+
+    pub async fn get_attempt(&self, key: &str) -> Result<Option<LoginAttempt>, CustomError> {
+        self.db
+            .collection::<LoginAttempt>(LOGIN_ATTEMPTS_COLL)
+            .find_one(doc! { "key": key })
+            .await
+            .map_err(|err| {
+                tracing::error!("Error finding login attempt {}: {:?}", key, err);
+                CustomError::MongoError(err)
+            })
+    }
+
+    /// Reject the login attempt if the key is locked or still has to wait
+    /// after its previous failure
+    pub async fn ensure_can_attempt(&self, key: &str) -> Result<(), CustomError> {
+        let attempt = match self.get_attempt(key).await? {
+            Some(attempt) => attempt,
+            None => return Ok(()),
+        };
+
+        Self::check_attempt(&attempt, Utc::now())
+    }
+
+    /// Whether a login may be attempted at `now` after the failures of `attempt`
+    pub fn check_attempt(attempt: &LoginAttempt, now: DateTime<Utc>) -> Result<(), CustomError> {
+        if let Some(locked_until) = attempt.lockedUntil
+            && locked_until > now
+        {
+            return Err(CustomError::AccountLocked(
+                (locked_until - now).num_seconds().max(1) as u64,
+            ));
+        }
+
+        let delay = login_delay_secs(attempt.failedCount);
+        let elapsed = (now - attempt.lastFailedAt).num_seconds();
+
+        if delay > elapsed {
+            return Err(CustomError::TooManyRequests((delay - elapsed) as u64));
+        }
+
+        Ok(())
+    }
+
+    /// Count a failed login, locking the key once it failed too many times.
+    ///
+    /// Returns the lock expiry if this failure caused a lockout
+    pub async fn record_failure(&self, key: &str) -> Result<Option<DateTime<Utc>>, CustomError> {
+        let attempt = self
+            .db
+            .collection::<LoginAttempt>(LOGIN_ATTEMPTS_COLL)
+            .find_one_and_update(
+                doc! { "key": key },
+                doc! {
+                    "$inc": { "failedCount": 1 },
+                    "$set": { "lastFailedAt": Utc::now(), "updatedAt": Utc::now() }
+                },
+            )
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .await
+            .map_err(|err| {
+                tracing::error!("Error recording failed login for {}: {:?}", key, err);
+                CustomError::MongoError(err)
+            })?;
+
+        let failed_count = attempt.map(|a| a.failedCount).unwrap_or(1);
+
+        if failed_count < LOGIN_LOCKOUT_AFTER_FAILURES {
+            return Ok(None);
+        }
+
+        // Start counting from zero again once the lock is over
+        let locked_until = Utc::now() + chrono::Duration::seconds(LOGIN_LOCKOUT_SECS);
+
+        self.db
+            .collection::<LoginAttempt>(LOGIN_ATTEMPTS_COLL)
+            .update_one(
+                doc! { "key": key },
+                doc! {
+                    "$set": {
+                        "failedCount": 0,
+                        "lockedUntil": locked_until,
+                        "updatedAt": Utc::now()
+                    }
+                },
+            )
+            .await
+            .map_err(|err| {
+                tracing::error!("Error locking {}: {:?}", key, err);
+                CustomError::MongoError(err)
+            })?;
+
+        tracing::warn!("{} has been locked until {}", key, locked_until);
+
+        Ok(Some(locked_until))
+    }
+
+    /// Forget the failed logins of the key, e.g. after a successful login
+    pub async fn reset(&self, key: &str) -> Result<(), CustomError> {
+        match self
+            .db
+            .collection::<LoginAttempt>(LOGIN_ATTEMPTS_COLL)
+            .delete_one(doc! { "key": key })
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                tracing::error!("Error resetting login attempts of {}: {:?}", key, err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+}
+
+/// Seconds to wait after the last failure, doubling with every failure
+/// past `LOGIN_DELAY_AFTER_FAILURES`
+fn login_delay_secs(failed_count: u32) -> i64 {
+    if failed_count < LOGIN_DELAY_AFTER_FAILURES {
+        return 0;
+    }
+
+    let exponent = (failed_count - LOGIN_DELAY_AFTER_FAILURES).min(6);
+
+    (1i64 << exponent).min(LOGIN_MAX_DELAY_SECS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::oid::ObjectId;
+    use chrono::Duration;
+
+    fn attempt(failed_count: u32, last_failed_at: DateTime<Utc>) -> LoginAttempt {
+        LoginAttempt {
+            id: ObjectId::new(),
+            key: LoginAttemptService::email_key("Alice@Example.com"),
+            failedCount: failed_count,
+            lastFailedAt: last_failed_at,
+            lockedUntil: None,
+            updatedAt: last_failed_at,
+        }
+    }
+
+    #[test]
+    fn first_failures_have_no_delay() {
+        let now = Utc::now();
+
+        assert!(LoginAttemptService::check_attempt(&attempt(2, now), now).is_ok());
+    }
+
+    #[test]
+    fn repeated_failures_have_to_wait() {
+        let now = Utc::now();
+        let attempt = attempt(LOGIN_DELAY_AFTER_FAILURES + 2, now);
+
+        assert!(matches!(
+            LoginAttemptService::check_attempt(&attempt, now),
+            Err(CustomError::TooManyRequests(4))
+        ));
+        assert!(LoginAttemptService::check_attempt(&attempt, now + Duration::seconds(4)).is_ok());
+    }
+
+    #[test]
+    fn delay_doubles_up_to_a_cap() {
+        assert_eq!(login_delay_secs(LOGIN_DELAY_AFTER_FAILURES), 1);
+        assert_eq!(login_delay_secs(LOGIN_DELAY_AFTER_FAILURES + 3), 8);
+        assert_eq!(login_delay_secs(u32::MAX), LOGIN_MAX_DELAY_SECS);
+    }
+
+    #[test]
+    fn locked_key_is_rejected_until_the_lock_is_over() {
+        let now = Utc::now();
+        let mut attempt = attempt(0, now);
+        attempt.lockedUntil = Some(now + Duration::seconds(LOGIN_LOCKOUT_SECS));
+
+        assert!(matches!(
+            LoginAttemptService::check_attempt(&attempt, now),
+            Err(CustomError::AccountLocked(secs)) if secs == LOGIN_LOCKOUT_SECS as u64
+        ));
+        assert!(
+            LoginAttemptService::check_attempt(
+                &attempt,
+                now + Duration::seconds(LOGIN_LOCKOUT_SECS)
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn email_keys_ignore_case_and_surrounding_spaces() {
+        assert_eq!(
+            LoginAttemptService::email_key("Alice@Example.com"),
+            LoginAttemptService::email_key("alice@example.com")
+        );
+        assert_eq!(
+            LoginAttemptService::email_key(" alice@example.com "),
+            LoginAttemptService::email_key("alice@example.com")
+        );
+    }
+}
@@ -0,0 +1,76 @@
+use chrono::{TimeZone, Utc};
+use mongodb::{
+    Database,
+    error::{ErrorKind, WriteFailure},
+};
+
+use crate::{
+    models::used_mfa_token::{NewUsedMfaToken, USED_MFA_TOKENS_COLL},
+    types::error::CustomError,
+};
+
+/// Keeps MFA pending tokens single use
+pub struct MfaTokenService {
+    db: Database,
+}
+
+impl MfaTokenService {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Use up the MFA pending token `jti` so it can't mint another session,
+    /// returns false if it was used already
+    pub async fn consume(&self, jti: &str, exp: usize) -> Result<bool, CustomError> {
+        let expires_at = Utc
+            .timestamp_opt(exp as i64, 0)
+            .single()
+            .unwrap_or_else(Utc::now);
+
+        // Inserted rather than upserted, so the unique jti index settles races
+        match self
+            .db
+            .collection::<NewUsedMfaToken>(USED_MFA_TOKENS_COLL)
+            .insert_one(NewUsedMfaToken {
+                jti: jti.to_owned(),
+                usedAt: Utc::now(),
+                expiresAt: expires_at,
+            })
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(err) => match err.kind.as_ref() {
+                ErrorKind::Write(WriteFailure::WriteError(w)) if w.code == 11000 => {
+                    tracing::debug!("MFA token {} was already used", jti);
+                    Ok(false)
+                }
+                _ => {
+                    tracing::error!("Error consuming MFA token {}: {:?}", jti, err);
+                    Err(CustomError::MongoError(err))
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_support::test_db, utils::datetime::now_epoch};
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn token_is_consumed_once() {
+        let db = test_db().await;
+        let service = MfaTokenService::new(db.clone());
+        let exp = now_epoch() + 300;
+
+        let (first, second) = tokio::join!(
+            service.consume("mfa-jti", exp),
+            service.consume("mfa-jti", exp)
+        );
+
+        assert!(first.unwrap() ^ second.unwrap());
+        assert!(service.consume("other-jti", exp).await.unwrap());
+    }
+}
@@ -0,0 +1,588 @@
+use base64::{Engine, engine::general_purpose};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, Validation, decode, decode_header,
+    jwk::{Jwk, JwkSet},
+};
+use rand::TryRngCore;
+use reqwest::{Client, Url};
+use serde::{Deserialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    env::var,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::oidc::{OIDC_PROVIDERS, OidcProvider},
+    types::error::CustomError,
+    utils::email::normalize_email,
+};
+
+pub const OIDC_AUTH_REQUEST_EXP_SECS: i64 = 10 * 60;
+const RANDOM_BYTES: usize = 32;
+/// How long discovery documents and key sets are reused
+const METADATA_CACHE_SECS: u64 = 60 * 60;
+/// Key sets are fetched again for an unknown `kid`, at most this often
+const JWKS_MIN_REFRESH_SECS: u64 = 60;
+/// A provider which doesn't answer within this fails the login instead of holding it
+const OIDC_REQUEST_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Deserialize)]
+struct DiscoveryDocument {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Apple sends `email_verified` as a string
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BoolOrString {
+    Bool(bool),
+    String(String),
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: Option<BoolOrString>,
+    #[serde(default)]
+    nonce: Option<String>,
+}
+
+/// Identity asserted by a provider through a validated ID token
+pub struct OidcIdentity {
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+}
+
+/// Values of one authorization request, `state` and `nonce` are echoed back
+pub struct OidcAuthParams {
+    pub state: String,
+    pub code_verifier: String,
+    pub nonce: String,
+}
+
+struct Cached<T> {
+    value: Arc<T>,
+    fetched_at: Instant,
+}
+
+/// Provider documents by URL
+type MetadataCache<T> = Mutex<HashMap<String, Cached<T>>>;
+
+pub struct OidcService {
+    client: Client,
+    redirect_url: String,
+    discovery_cache: MetadataCache<DiscoveryDocument>,
+    jwks_cache: MetadataCache<JwkSet>,
+}
+
+impl OidcService {
+    pub fn new() -> Self {
+        let redirect_url = var("OIDC_REDIRECT_URL").unwrap_or_else(|_| {
+            format!(
+                "{}/auth/oidc/callback",
+                var("FRONTEND_URL").expect("FRONTEND_URL missing")
+            )
+        });
+
+        Self {
+            client: http_client(),
+            redirect_url,
+            discovery_cache: Mutex::new(HashMap::new()),
+            jwks_cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn provider(&self, name: &str) -> Result<&'static OidcProvider, CustomError> {
+        OIDC_PROVIDERS
+            .get(name)
+            .ok_or(CustomError::NotFoundError(format!(
+                "OIDC provider {}",
+                name
+            )))
+    }
+
+    pub fn new_auth_params(&self) -> Result<OidcAuthParams, CustomError> {
+        Ok(OidcAuthParams {
+            state: random_b64url()?,
+            code_verifier: random_b64url()?,
+            nonce: random_b64url()?,
+        })
+    }
+
+    /// URL of the provider login page, with a S256 PKCE challenge
+    pub async fn authorization_url(
+        &self,
+        provider: &OidcProvider,
+        params: &OidcAuthParams,
+    ) -> Result<String, CustomError> {
+        let discovery = self.discover(provider).await?;
+
+        let code_challenge = general_purpose::URL_SAFE_NO_PAD
+            .encode(Sha256::digest(params.code_verifier.as_bytes()));
+
+        let mut query = vec![
+            ("response_type", "code"),
+            ("client_id", provider.client_id.as_str()),
+            ("redirect_uri", self.redirect_url.as_str()),
+            ("scope", provider.scopes.as_str()),
+            ("state", params.state.as_str()),
+            ("nonce", params.nonce.as_str()),
+            ("code_challenge", code_challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ];
+
+        if let Some(response_mode) = &provider.response_mode {
+            query.push(("response_mode", response_mode.as_str()));
+        }
+
+        let url =
+            Url::parse_with_params(&discovery.authorization_endpoint, &query).map_err(|e| {
+                tracing::error!("Invalid authorization endpoint: {:?}", e);
+                CustomError::OidcProviderError
+            })?;
+
+        Ok(url.to_string())
+    }
+
+    /// Redeem the authorization code and validate the returned ID token
+    pub async fn exchange_code(
+        &self,
+        provider: &OidcProvider,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<OidcIdentity, CustomError> {
+        let discovery = self.discover(provider).await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.redirect_url.as_str()),
+            ("client_id", provider.client_id.as_str()),
+            ("code_verifier", code_verifier),
+        ];
+
+        if let Some(client_secret) = &provider.client_secret {
+            form.push(("client_secret", client_secret.as_str()));
+        }
+
+        let res = self
+            .client
+            .post(&discovery.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(provider_error)?;
+
+        if !res.status().is_success() {
+            let status = res.status();
+            let body = res.text().await.unwrap_or_default();
+            tracing::warn!("OIDC token endpoint error {}: {}", status, body);
+            return Err(CustomError::WrongCredentials);
+        }
+
+        let tokens: TokenResponse = res.json().await.map_err(provider_error)?;
+
+        let claims = self
+            .validate_id_token(provider, &discovery, &tokens.id_token)
+            .await?;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            tracing::warn!("OIDC ID token nonce mismatch");
+            return Err(CustomError::InvalidToken);
+        }
+
+        let email_verified = match claims.email_verified {
+            Some(BoolOrString::Bool(v)) => v,
+            Some(BoolOrString::String(v)) => v == "true",
+            None => false,
+        };
+
+        Ok(OidcIdentity {
+            subject: claims.sub,
+            email: claims.email.as_deref().map(normalize_email),
+            email_verified,
+        })
+    }
+
+    async fn validate_id_token(
+        &self,
+        provider: &OidcProvider,
+        discovery: &DiscoveryDocument,
+        id_token: &str,
+    ) -> Result<IdTokenClaims, CustomError> {
+        let header = decode_header(id_token).map_err(|_| CustomError::InvalidToken)?;
+
+        let jwk = self
+            .find_jwk(&discovery.jwks_uri, header.kid.as_deref())
+            .await?;
+
+        // The token header is chosen by whoever made the token, so the accepted
+        // algorithms come from the key or the provider configuration
+        let algorithms = match jwk.common.key_algorithm {
+            Some(key_algorithm) => vec![
+                Algorithm::from_str(&key_algorithm.to_string())
+                    .map_err(|_| CustomError::InvalidToken)?,
+            ],
+            None => provider.algorithms.clone(),
+        };
+
+        if !algorithms.contains(&header.alg) {
+            tracing::warn!("OIDC ID token signed with unexpected {:?}", header.alg);
+            return Err(CustomError::InvalidToken);
+        }
+
+        let key = DecodingKey::from_jwk(&jwk).map_err(|e| {
+            tracing::error!("Unusable JWK from {}: {:?}", discovery.jwks_uri, e);
+            CustomError::OidcProviderError
+        })?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.algorithms = algorithms;
+        validation.set_audience(&[&provider.client_id]);
+        validation.set_issuer(&[&discovery.issuer]);
+
+        decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| {
+                tracing::warn!("Invalid OIDC ID token: {:?}", e);
+                CustomError::InvalidToken
+            })
+    }
+
+    /// Key `kid` of the provider, or its first key for tokens without `kid`.
+    /// An unknown `kid` refreshes the cached key set, as the provider may have rotated
+    async fn find_jwk(&self, jwks_uri: &str, kid: Option<&str>) -> Result<Jwk, CustomError> {
+        let find = |jwks: &JwkSet| match kid {
+            Some(kid) => jwks.find(kid).cloned(),
+            None => jwks.keys.first().cloned(),
+        };
+
+        let jwks = self
+            .cached_json(&self.jwks_cache, jwks_uri, METADATA_CACHE_SECS)
+            .await?;
+
+        if let Some(jwk) = find(&jwks) {
+            return Ok(jwk);
+        }
+
+        let jwks = self
+            .cached_json(&self.jwks_cache, jwks_uri, JWKS_MIN_REFRESH_SECS)
+            .await?;
+
+        find(&jwks).ok_or(CustomError::InvalidToken)
+    }
+
+    async fn discover(
+        &self,
+        provider: &OidcProvider,
+    ) -> Result<Arc<DiscoveryDocument>, CustomError> {
+        self.cached_json(
+            &self.discovery_cache,
+            &provider.discovery_url,
+            METADATA_CACHE_SECS,
+        )
+        .await
+    }
+
+    /// The document at `url`, fetched again once the cached copy is older than `max_age_secs`
+    async fn cached_json<T: DeserializeOwned>(
+        &self,
+        cache: &MetadataCache<T>,
+        url: &str,
+        max_age_secs: u64,
+    ) -> Result<Arc<T>, CustomError> {
+        let cached = cache
+            .lock()
+            .unwrap()
+            .get(url)
+            .filter(|c| c.fetched_at.elapsed() < Duration::from_secs(max_age_secs))
+            .map(|c| c.value.clone());
+
+        if let Some(value) = cached {
+            return Ok(value);
+        }
+
+        let value = Arc::new(self.get_json::<T>(url).await?);
+
+        cache.lock().unwrap().insert(
+            url.to_owned(),
+            Cached {
+                value: value.clone(),
+                fetched_at: Instant::now(),
+            },
+        );
+
+        Ok(value)
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T, CustomError> {
+        self.client
+            .get(url)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(provider_error)?
+            .json()
+            .await
+            .map_err(provider_error)
+    }
+}
+
+fn random_b64url() -> Result<String, CustomError> {
+    let mut bytes = [0u8; RANDOM_BYTES];
+    rand::rngs::OsRng
+        .try_fill_bytes(&mut bytes)
+        .map_err(|_| CustomError::TokenCreation)?;
+
+    Ok(general_purpose::URL_SAFE_NO_PAD.encode(bytes))
+}
+
+fn provider_error(err: reqwest::Error) -> CustomError {
+    tracing::error!("OIDC provider request failed: {:?}", err);
+    CustomError::OidcProviderError
+}
+
+/// Client for the discovery, key set and token endpoints of providers
+fn http_client() -> Client {
+    Client::builder()
+        .timeout(Duration::from_secs(OIDC_REQUEST_TIMEOUT_SECS))
+        .build()
+        .expect("Failed to build the OIDC HTTP client")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{TEST_PRIVATE_KEY_PEM, TEST_PUBLIC_KEY_PEM};
+    use crate::types::keys::Keys;
+    use axum::{Json, Router, routing::get, routing::post};
+    use jsonwebtoken::encode;
+    use serde_json::{Value, json};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const CLIENT_ID: &str = "client";
+
+    /// Local issuer serving discovery, keys and a token endpoint which answers
+    /// with whatever ID token the test put in `id_token`
+    struct MockIssuer {
+        base: String,
+        keys: Arc<Keys>,
+        id_token: Arc<Mutex<String>>,
+        discovery_hits: Arc<AtomicUsize>,
+        jwks_hits: Arc<AtomicUsize>,
+    }
+
+    impl MockIssuer {
+        async fn start(publish_alg: bool) -> Self {
+            let keys = Arc::new(Keys::new(
+                Some("mock-kid".to_owned()),
+                TEST_PRIVATE_KEY_PEM.as_bytes().to_vec(),
+                TEST_PUBLIC_KEY_PEM.as_bytes().to_vec(),
+                vec![],
+            ));
+            let id_token = Arc::new(Mutex::new(String::new()));
+            let discovery_hits = Arc::new(AtomicUsize::new(0));
+            let jwks_hits = Arc::new(AtomicUsize::new(0));
+
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let base = format!("http://{}", listener.local_addr().unwrap());
+
+            let mut jwks = keys.jwks.clone();
+            if !publish_alg {
+                jwks.keys[0].common.key_algorithm = None;
+            }
+
+            let discovery = json!({
+                "issuer": base,
+                "authorization_endpoint": format!("{}/authorize", base),
+                "token_endpoint": format!("{}/token", base),
+                "jwks_uri": format!("{}/jwks", base),
+            });
+
+            let app = Router::new()
+                .route(
+                    "/.well-known/openid-configuration",
+                    get({
+                        let hits = discovery_hits.clone();
+                        move || async move {
+                            hits.fetch_add(1, Ordering::SeqCst);
+                            Json(discovery)
+                        }
+                    }),
+                )
+                .route(
+                    "/jwks",
+                    get({
+                        let hits = jwks_hits.clone();
+                        move || async move {
+                            hits.fetch_add(1, Ordering::SeqCst);
+                            Json(jwks)
+                        }
+                    }),
+                )
+                .route(
+                    "/token",
+                    post({
+                        let id_token = id_token.clone();
+                        move || async move {
+                            let id_token = id_token.lock().unwrap().clone();
+                            Json(json!({ "id_token": id_token, "token_type": "Bearer" }))
+                        }
+                    }),
+                );
+
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+            Self {
+                base,
+                keys,
+                id_token,
+                discovery_hits,
+                jwks_hits,
+            }
+        }
+
+        fn provider(&self) -> OidcProvider {
+            OidcProvider {
+                discovery_url: format!("{}/.well-known/openid-configuration", self.base),
+                client_id: CLIENT_ID.to_owned(),
+                client_secret: None,
+                scopes: "openid email".to_owned(),
+                response_mode: None,
+                algorithms: vec![Algorithm::RS256],
+            }
+        }
+
+        /// Make the token endpoint return an ID token with `claims` merged over valid ones
+        fn issue(&self, claims: Value, kid: &str) {
+            let mut token_claims = json!({
+                "iss": self.base,
+                "aud": CLIENT_ID,
+                "sub": "subject-1",
+                "exp": crate::utils::datetime::now_epoch() + 300,
+                "email": "Alice@Example.com",
+                "email_verified": "true",
+                "nonce": "nonce-1",
+            });
+            for (name, value) in claims.as_object().unwrap() {
+                token_claims[name] = value.clone();
+            }
+
+            let mut header = self.keys.header();
+            header.kid = Some(kid.to_owned());
+
+            *self.id_token.lock().unwrap() =
+                encode(&header, &token_claims, &self.keys.encoding).unwrap();
+        }
+    }
+
+    fn service() -> OidcService {
+        OidcService {
+            client: http_client(),
+            redirect_url: "https://app.example/auth/oidc/callback".to_owned(),
+            discovery_cache: Mutex::new(HashMap::new()),
+            jwks_cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn exchange(
+        service: &OidcService,
+        issuer: &MockIssuer,
+    ) -> Result<OidcIdentity, CustomError> {
+        service
+            .exchange_code(&issuer.provider(), "code", "verifier", "nonce-1")
+            .await
+    }
+
+    #[tokio::test]
+    async fn valid_id_token_gives_the_identity() {
+        let issuer = MockIssuer::start(true).await;
+        issuer.issue(json!({}), "mock-kid");
+
+        let identity = exchange(&service(), &issuer).await.unwrap();
+
+        assert_eq!(identity.subject, "subject-1");
+        assert_eq!(identity.email.as_deref(), Some("alice@example.com"));
+        assert!(identity.email_verified);
+    }
+
+    #[tokio::test]
+    async fn nonce_mismatch_is_rejected() {
+        let issuer = MockIssuer::start(true).await;
+        issuer.issue(json!({ "nonce": "other" }), "mock-kid");
+
+        assert!(matches!(
+            exchange(&service(), &issuer).await,
+            Err(CustomError::InvalidToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn token_for_another_client_is_rejected() {
+        let issuer = MockIssuer::start(true).await;
+        issuer.issue(json!({ "aud": "someone-else" }), "mock-kid");
+
+        assert!(matches!(
+            exchange(&service(), &issuer).await,
+            Err(CustomError::InvalidToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn algorithm_outside_the_allowlist_is_rejected() {
+        // Without `alg` on the key only the configured RS256 is accepted
+        let issuer = MockIssuer::start(false).await;
+        issuer.issue(json!({}), "mock-kid");
+
+        assert!(matches!(
+            exchange(&service(), &issuer).await,
+            Err(CustomError::InvalidToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn provider_metadata_is_cached() {
+        let issuer = MockIssuer::start(true).await;
+        let service = service();
+        issuer.issue(json!({}), "mock-kid");
+
+        exchange(&service, &issuer).await.unwrap();
+        exchange(&service, &issuer).await.unwrap();
+
+        assert_eq!(issuer.discovery_hits.load(Ordering::SeqCst), 1);
+        assert_eq!(issuer.jwks_hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn unknown_kid_does_not_refetch_fresh_keys() {
+        let issuer = MockIssuer::start(true).await;
+        let service = service();
+        issuer.issue(json!({}), "mock-kid");
+        exchange(&service, &issuer).await.unwrap();
+
+        issuer.issue(json!({}), "unknown-kid");
+
+        assert!(matches!(
+            exchange(&service, &issuer).await,
+            Err(CustomError::InvalidToken)
+        ));
+        assert_eq!(issuer.jwks_hits.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,358 @@
+use sha1::{Digest, Sha1};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::password_policy::{PASSWORD_POLICY, PasswordPolicy},
+    types::{error::CustomError, validation::FieldError},
+};
+
+/// Words shorter than this aren't searched for in passwords
+const MIN_BANNED_WORD_LEN: usize = 3;
+
+/// Checks new passwords against `PASSWORD_POLICY`.
+///
+/// Breached passwords are looked up in a local copy of the Pwned Passwords
+/// range files, so passwords are never sent anywhere to be checked
+pub struct PasswordPolicyService {
+    policy: PasswordPolicy,
+    breached_ranges: Option<BreachedRanges>,
+}
+
+impl PasswordPolicyService {
+    pub fn new() -> Self {
+        let policy = PASSWORD_POLICY.clone();
+
+        let breached_ranges = policy
+            .breached_ranges_dir
+            .as_deref()
+            .map(BreachedRanges::open);
+
+        Self {
+            policy,
+            breached_ranges,
+        }
+    }
+
+    /// Check `password`, reporting every broken rule under `field`.
+    /// `personal` are values of the user, like the username, it must not contain
+    pub async fn check(
+        &self,
+        field: &str,
+        password: &str,
+        personal: &[&str],
+    ) -> Result<(), CustomError> {
+        let mut errors = Vec::new();
+        let length = password.chars().count();
+
+        if length < self.policy.min_length {
+            errors.push(FieldError::new(
+                field,
+                &format!("must be at least {} characters", self.policy.min_length),
+            ));
+        }
+
+        if length > self.policy.max_length {
+            errors.push(FieldError::new(
+                field,
+                &format!("must be at most {} characters", self.policy.max_length),
+            ));
+        }
+
+        let lowercase = password.to_lowercase();
+        let contains =
+            |word: &String| word.len() >= MIN_BANNED_WORD_LEN && lowercase.contains(word);
+
+        if personal
+            .iter()
+            .map(|value| value.trim().to_lowercase())
+            .any(|value| contains(&value))
+        {
+            errors.push(FieldError::new(
+                field,
+                "must not contain your username or email",
+            ));
+        }
+
+        if self.policy.banned_words.iter().any(contains) {
+            errors.push(FieldError::new(field, "must not contain common words"));
+        }
+
+        if estimate_entropy_bits(password) < self.policy.min_entropy_bits {
+            errors.push(FieldError::new(field, "is too easy to guess"));
+        }
+
+        if self.is_breached(password).await {
+            errors.push(FieldError::new(field, "appears in a known data breach"));
+        }
+
+        if !errors.is_empty() {
+            return Err(CustomError::ValidationError(errors));
+        }
+
+        Ok(())
+    }
+
+    async fn is_breached(&self, password: &str) -> bool {
+        match &self.breached_ranges {
+            Some(ranges) => ranges.contains(password).await,
+            None => false,
+        }
+    }
+}
+
+/// Length of the SHA-1 hex prefix the range files are named after
+const RANGE_PREFIX_LEN: usize = 5;
+/// Length of the SHA-1 hex suffixes listed in a range file
+const RANGE_SUFFIX_LEN: usize = 35;
+
+/// Pwned Passwords in the k-anonymity range format, as written by the
+/// PwnedPasswordsDownloader: one `<PREFIX>.txt` file per 5 hex character SHA-1
+/// prefix, holding `<35 hex character suffix>:<count>` lines.
+/// Only the file of the password's prefix is read, nothing is kept in memory
+struct BreachedRanges {
+    dir: PathBuf,
+}
+
+impl BreachedRanges {
+    fn open(dir: &str) -> Self {
+        let dir = PathBuf::from(dir);
+
+        if !dir.is_dir() {
+            panic!(
+                "{} is not a directory of breached password ranges",
+                dir.display()
+            );
+        }
+
+        Self { dir }
+    }
+
+    /// The range file is read on the blocking pool, not on the async runtime
+    async fn contains(&self, password: &str) -> bool {
+        let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = hash.split_at(RANGE_PREFIX_LEN);
+
+        let path = self.dir.join(format!("{}.txt", prefix));
+        let suffix = suffix.to_owned();
+
+        let lookup = tokio::task::spawn_blocking(move || match File::open(&path) {
+            Ok(file) => range_contains(&path, BufReader::new(file), &suffix),
+            Err(err) => {
+                tracing::warn!(
+                    "Can't open breached password range {}: {}",
+                    path.display(),
+                    err
+                );
+                false
+            }
+        });
+
+        lookup.await.unwrap_or_else(|err| {
+            tracing::error!("Breached password lookup failed: {:?}", err);
+            false
+        })
+    }
+}
+
+/// Whether the range file at `path` lists `suffix`, warning about lines which
+/// aren't `<suffix>[:count]` instead of silently skipping them
+fn range_contains(path: &Path, reader: impl BufRead, suffix: &str) -> bool {
+    let mut rejected = 0;
+    let mut found = false;
+
+    for line in reader.lines().map_while(Result::ok) {
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        match parse_range_line(line) {
+            Some(listed) if listed.eq_ignore_ascii_case(suffix) => {
+                found = true;
+                break;
+            }
+            Some(_) => {}
+            None => rejected += 1,
+        }
+    }
+
+    if rejected > 0 {
+        tracing::warn!(
+            "Skipped {} malformed lines in breached password range {}",
+            rejected,
+            path.display()
+        );
+    }
+
+    found
+}
+
+fn parse_range_line(line: &str) -> Option<&str> {
+    let suffix = line.split(':').next()?.trim();
+
+    (suffix.len() == RANGE_SUFFIX_LEN && suffix.bytes().all(|b| b.is_ascii_hexdigit()))
+        .then_some(suffix)
+}
+
+/// Rough strength estimate: the size of the character classes used, to the
+/// power of the length. Repeated characters and runs like `abc` or `321`
+/// only count half
+fn estimate_entropy_bits(password: &str) -> f64 {
+    let chars: Vec<char> = password.chars().collect();
+
+    let pool: u32 = [
+        (chars.iter().any(|c| c.is_ascii_lowercase()), 26),
+        (chars.iter().any(|c| c.is_ascii_uppercase()), 26),
+        (chars.iter().any(|c| c.is_ascii_digit()), 10),
+        (
+            chars.iter().any(|c| c.is_ascii_punctuation() || *c == ' '),
+            33,
+        ),
+        (chars.iter().any(|c| !c.is_ascii()), 100),
+    ]
+    .iter()
+    .filter(|(used, _)| *used)
+    .map(|(_, size)| size)
+    .sum();
+
+    if pool == 0 {
+        return 0.0;
+    }
+
+    let effective_length: f64 = chars
+        .iter()
+        .enumerate()
+        .map(|(i, c)| match i.checked_sub(1).map(|prev| chars[prev]) {
+            Some(prev) if (*c as i64 - prev as i64).abs() <= 1 => 0.5,
+            _ => 1.0,
+        })
+        .sum();
+
+    effective_length * (pool as f64).log2()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn service(breached_ranges: Option<BreachedRanges>) -> PasswordPolicyService {
+        PasswordPolicyService {
+            policy: PasswordPolicy {
+                min_length: 8,
+                max_length: 128,
+                min_entropy_bits: 36.0,
+                banned_words: vec!["halalho".to_owned()],
+                breached_ranges_dir: None,
+            },
+            breached_ranges,
+        }
+    }
+
+    fn reasons(result: Result<(), CustomError>) -> Vec<String> {
+        match result {
+            Err(CustomError::ValidationError(errors)) => {
+                errors.into_iter().map(|e| e.reason).collect()
+            }
+            Err(err) => panic!("unexpected error {:?}", err),
+            Ok(()) => Vec::new(),
+        }
+    }
+
+    /// Range directory holding the given passwords plus a malformed line
+    fn ranges_with(passwords: &[&str]) -> BreachedRanges {
+        let dir = std::env::temp_dir().join(format!("bff-ranges-{}", bson::uuid::Uuid::new()));
+        fs::create_dir_all(&dir).unwrap();
+
+        for password in passwords {
+            let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+            let (prefix, suffix) = hash.split_at(RANGE_PREFIX_LEN);
+
+            let lines = format!(
+                "{}:3\nnot a hash\n\n0000000000000000000000000000000000A:1\n",
+                suffix
+            );
+            fs::write(dir.join(format!("{}.txt", prefix)), lines).unwrap();
+        }
+
+        BreachedRanges::open(dir.to_str().unwrap())
+    }
+
+    #[tokio::test]
+    async fn strong_password_passes() {
+        assert!(
+            service(None)
+                .check("password", "vN7#qLx!2mWp", &["alice"])
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn short_password_is_rejected() {
+        let reasons = reasons(service(None).check("password", "aB3$x", &[]).await);
+
+        assert!(reasons.contains(&"must be at least 8 characters".to_owned()));
+    }
+
+    #[tokio::test]
+    async fn personal_and_banned_words_are_rejected() {
+        let service = service(None);
+
+        assert!(
+            reasons(
+                service
+                    .check("password", "xAlice#2024zz", &["alice", "bob"])
+                    .await
+            )
+            .contains(&"must not contain your username or email".to_owned())
+        );
+        assert!(
+            reasons(service.check("password", "Q9#halalhoZ!", &[]).await)
+                .contains(&"must not contain common words".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn predictable_password_is_too_easy_to_guess() {
+        let reasons = reasons(service(None).check("password", "abcdefghij", &[]).await);
+
+        assert_eq!(reasons, vec!["is too easy to guess".to_owned()]);
+    }
+
+    #[tokio::test]
+    async fn breached_password_is_found_by_prefix() {
+        let service = service(Some(ranges_with(&["vN7#qLx!2mWp"])));
+
+        assert_eq!(
+            reasons(service.check("password", "vN7#qLx!2mWp", &[]).await),
+            vec!["appears in a known data breach".to_owned()]
+        );
+    }
+
+    #[tokio::test]
+    async fn password_missing_from_ranges_is_not_breached() {
+        let ranges = ranges_with(&["vN7#qLx!2mWp"]);
+
+        // Same directory, but either no file for the prefix or not listed in it
+        assert!(!ranges.contains("Zk4!rTq#8uYe").await);
+    }
+
+    #[test]
+    fn malformed_range_lines_are_skipped() {
+        let path = Path::new("range.txt");
+        let range = "not a hash\nABC:1\n0018A45C4D1DEF81644B54AB7F969B88D65:10\n";
+
+        assert!(range_contains(
+            path,
+            range.as_bytes(),
+            "0018a45c4d1def81644b54ab7f969b88d65"
+        ));
+        assert!(!range_contains(path, range.as_bytes(), "ABC"));
+    }
+}
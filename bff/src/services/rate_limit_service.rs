@@ -0,0 +1,109 @@
+use bson::doc;
+use chrono::{DateTime, TimeZone, Utc};
+use mongodb::{Database, options::ReturnDocument};
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::{
+    config::rate_limit::RateLimitBackend,
+    models::rate_limit::{RATE_LIMITS_COLL, RateLimitCounter},
+    types::error::CustomError,
+    utils::datetime::now_epoch,
+};
+
+/// Entries kept by the memory store before expired ones are swept
+const MEMORY_SWEEP_THRESHOLD: usize = 10_000;
+
+enum RateLimitStore {
+    Memory(Mutex<HashMap<String, (u64, usize)>>),
+    Mongo(Database),
+}
+
+pub struct RateLimitService {
+    store: RateLimitStore,
+}
+
+impl RateLimitService {
+    pub fn new(backend: RateLimitBackend, db: Database) -> Self {
+        let store = match backend {
+            RateLimitBackend::Memory => RateLimitStore::Memory(Mutex::new(HashMap::new())),
+            RateLimitBackend::Mongo => RateLimitStore::Mongo(db),
+        };
+
+        Self { store }
+    }
+
+    /// Count a request for `key` in the current window of `window_secs`.
+    ///
+    /// Returns the count so far in this window and the epoch second it resets at
+    pub async fn hit(&self, key: &str, window_secs: u64) -> Result<(u64, usize), CustomError> {
+        let window = window_secs as usize;
+        let reset_at = (now_epoch() / window + 1) * window;
+        let window_key = format!("{}:{}", key, reset_at);
+
+        match &self.store {
+            RateLimitStore::Memory(counters) => {
+                let mut counters = counters.lock().unwrap();
+
+                if counters.len() > MEMORY_SWEEP_THRESHOLD {
+                    let now = now_epoch();
+                    counters.retain(|_, (_, reset)| *reset > now);
+                }
+
+                let entry = counters.entry(window_key).or_insert((0, reset_at));
+                entry.0 += 1;
+
+                Ok((entry.0, reset_at))
+            }
+            RateLimitStore::Mongo(db) => {
+                let expires_at: DateTime<Utc> = Utc
+                    .timestamp_opt(reset_at as i64, 0)
+                    .single()
+                    .ok_or(CustomError::TokenCreation)?;
+
+                let counter = db
+                    .collection::<RateLimitCounter>(RATE_LIMITS_COLL)
+                    .find_one_and_update(
+                        doc! { "key": &window_key },
+                        doc! {
+                            "$inc": { "count": 1_i64 },
+                            "$setOnInsert": { "expiresAt": expires_at }
+                        },
+                    )
+                    .upsert(true)
+                    .return_document(ReturnDocument::After)
+                    .await
+                    .map_err(|err| {
+                        tracing::error!("Error counting request for {}: {:?}", window_key, err);
+                        CustomError::MongoError(err)
+                    })?;
+
+                Ok((counter.map(|c| c.count as u64).unwrap_or(1), reset_at))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::Client;
+
+    async fn memory_service() -> RateLimitService {
+        let client = Client::with_uri_str("mongodb://127.0.0.1:9").await.unwrap();
+
+        RateLimitService::new(RateLimitBackend::Memory, client.database("test"))
+    }
+
+    #[tokio::test]
+    async fn memory_store_counts_per_key_and_window() {
+        let service = memory_service().await;
+
+        let (first, reset_at) = service.hit("login:ip:1", 300).await.unwrap();
+        let (second, _) = service.hit("login:ip:1", 300).await.unwrap();
+        let (other, _) = service.hit("login:ip:2", 300).await.unwrap();
+
+        assert_eq!((first, second, other), (1, 2, 1));
+        assert_eq!(reset_at % 300, 0);
+        assert!(reset_at > now_epoch() && reset_at <= now_epoch() + 300);
+    }
+}
@@ -1,13 +1,19 @@
 use crate::{
-    models::refresh_token::{NewRefreshToken, REFRESH_TOKENS_COLL, RefreshToken}, types::error::CustomError
+    models::refresh_token::{NewRefreshToken, REFRESH_TOKENS_COLL, RefreshToken},
+    types::error::CustomError,
 };
-use chrono::Utc;
+use chrono::{DateTime, Duration, Utc};
+use futures::TryStreamExt;
 use mongodb::{
     Database,
-    bson::doc,
+    bson::{doc, oid::ObjectId},
     error::{ErrorKind, WriteFailure},
 };
 
+/// How long a rotated refresh token may still be presented, e.g. by two tabs
+/// refreshing at the same time, without being treated as reuse
+pub const ROTATION_GRACE_SECS: i64 = 90;
+
 pub struct RefreshTokenService {
     db: Database,
 }
@@ -17,6 +23,59 @@ impl RefreshTokenService {
         Self { db }
     }
 
+    /// Whether presenting an already revoked token is a benign race rather than reuse.
+    ///
+    /// Only tokens which were rotated (`usedAt` is set by `rotate_token` alone) within
+    /// the grace window qualify, only while their family still has a live token and
+    /// only if they weren't redeemed in the window already.
+    /// Logged out, revoked or reuse-killed families never do.
+    pub fn within_rotation_grace(
+        token: &RefreshToken,
+        family_active: bool,
+        now: DateTime<Utc>,
+    ) -> bool {
+        match token.usedAt {
+            Some(used_at) => {
+                token.isRevoked
+                    && token.graceUsedAt.is_none()
+                    && family_active
+                    && (now - used_at).num_seconds() < ROTATION_GRACE_SECS
+            }
+            None => false,
+        }
+    }
+
+    /// Use up the single redemption a rotated token gets inside the grace window.
+    ///
+    /// Returns false if the token was redeemed already or the window is over
+    pub async fn redeem_in_grace(&self, token: &str) -> Result<bool, CustomError> {
+        let now = Utc::now();
+
+        match self
+            .db
+            .collection::<RefreshToken>(REFRESH_TOKENS_COLL)
+            .update_one(
+                doc! {
+                    "token": token,
+                    "isRevoked": true,
+                    "usedAt": { "$gt": now - Duration::seconds(ROTATION_GRACE_SECS) },
+                    "graceUsedAt": { "$eq": null }
+                },
+                doc! { "$set": doc! { "graceUsedAt": now } },
+            )
+            .await
+        {
+            Ok(value) => {
+                tracing::debug!("{:#?} has been redeemed in grace", value.modified_count);
+                Ok(value.modified_count == 1)
+            }
+            Err(error) => {
+                tracing::debug!("Error redeeming token in grace: {}", error);
+                Err(CustomError::MongoError(error))
+            }
+        }
+    }
+
     pub async fn get_token_by_jti(&self, data: &str) -> Result<RefreshToken, CustomError> {
         match self
             .db
@@ -33,6 +92,83 @@ impl RefreshTokenService {
         }
     }
 
+    /// Get the refresh tokens of the user which are neither revoked nor expired, newest first
+    pub async fn get_active_tokens_by_user(
+        &self,
+        user_id: &str,
+    ) -> Result<Vec<RefreshToken>, CustomError> {
+        let user_obj_id = ObjectId::parse_str(user_id).map_err(|e| {
+            tracing::error!("Error while parsing {}: {:?}", user_id, e);
+            CustomError::InvalidIDError(user_id.to_owned())
+        })?;
+
+        let cursor = self
+            .db
+            .collection::<RefreshToken>(REFRESH_TOKENS_COLL)
+            .find(doc! {
+                "userId": user_obj_id,
+                "isRevoked": false,
+                "expiresAt": { "$gt": Utc::now() }
+            })
+            .sort(doc! { "createdAt": -1 })
+            .await
+            .map_err(|err| {
+                tracing::debug!("Error finding tokens of {}: {}", user_id, err);
+                CustomError::MongoError(err)
+            })?;
+
+        cursor.try_collect().await.map_err(|err| {
+            tracing::debug!("Error collecting tokens of {}: {}", user_id, err);
+            CustomError::MongoError(err)
+        })
+    }
+
+    /// Whether the session still has a refresh token which is neither revoked nor expired
+    pub async fn is_family_active(&self, family_id: &str) -> Result<bool, CustomError> {
+        match self
+            .db
+            .collection::<RefreshToken>(REFRESH_TOKENS_COLL)
+            .count_documents(doc! {
+                "familyId": family_id,
+                "isRevoked": false,
+                "expiresAt": { "$gt": Utc::now() }
+            })
+            .limit(1)
+            .await
+        {
+            Ok(count) => Ok(count > 0),
+            Err(err) => {
+                tracing::debug!("Error counting tokens of family {}: {}", family_id, err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    pub async fn get_token_by_id(
+        &self,
+        id: &str,
+        user_id: &str,
+    ) -> Result<RefreshToken, CustomError> {
+        let obj_id =
+            ObjectId::parse_str(id).map_err(|_| CustomError::InvalidIDError(id.to_owned()))?;
+        let user_obj_id = ObjectId::parse_str(user_id)
+            .map_err(|_| CustomError::InvalidIDError(user_id.to_owned()))?;
+
+        match self
+            .db
+            .collection::<RefreshToken>(REFRESH_TOKENS_COLL)
+            .find_one(doc! { "_id": obj_id, "userId": user_obj_id })
+            .await
+        {
+            Ok(Some(token)) => Ok(token),
+            Ok(None) => Err(CustomError::NotFoundError(id.to_owned())),
+            Err(err) => {
+                tracing::debug!("Error finding token: {}", err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
     pub async fn create_token(&self, data: &NewRefreshToken) -> Result<(), CustomError> {
         match self
             .db
@@ -57,7 +193,11 @@ impl RefreshTokenService {
         }
     }
 
-    pub async fn revoke_token(&self, token: &str) -> Result<(), CustomError> {
+    /// Mark the token as exchanged for a new pair of the same family.
+    ///
+    /// Returns false if the token was no longer active, i.e. a concurrent
+    /// request rotated or revoked it first
+    pub async fn rotate_token(&self, token: &str) -> Result<bool, CustomError> {
         match self
             .db
             .collection::<RefreshToken>(REFRESH_TOKENS_COLL)
@@ -66,6 +206,27 @@ impl RefreshTokenService {
                 doc! { "$set": doc! { "isRevoked": true, "usedAt": Utc::now() } },
             )
             .await
+        {
+            Ok(value) => {
+                tracing::debug!("{:#?} has been rotated", value.modified_count);
+                Ok(value.modified_count == 1)
+            }
+            Err(error) => {
+                tracing::debug!("Error rotating token: {}", error);
+                Err(CustomError::MongoError(error))
+            }
+        }
+    }
+
+    pub async fn revoke_token(&self, token: &str) -> Result<(), CustomError> {
+        match self
+            .db
+            .collection::<RefreshToken>(REFRESH_TOKENS_COLL)
+            .update_one(
+                doc! { "token": token, "isRevoked": false },
+                doc! { "$set": doc! { "isRevoked": true } },
+            )
+            .await
         {
             Ok(value) => {
                 tracing::debug!("{:#?} has been revoked", value.modified_count);
@@ -77,4 +238,275 @@ impl RefreshTokenService {
             }
         }
     }
+
+    /// Revoke every active refresh token rotated from the same login
+    pub async fn revoke_family(&self, family_id: &str) -> Result<u64, CustomError> {
+        match self
+            .db
+            .collection::<RefreshToken>(REFRESH_TOKENS_COLL)
+            .update_many(
+                doc! { "familyId": family_id, "isRevoked": false },
+                doc! { "$set": doc! { "isRevoked": true } },
+            )
+            .await
+        {
+            Ok(value) => {
+                tracing::debug!(
+                    "{} tokens of family {} have been revoked",
+                    value.modified_count,
+                    family_id
+                );
+                Ok(value.modified_count)
+            }
+            Err(error) => {
+                tracing::debug!("Error revoking token family {}: {}", family_id, error);
+                Err(CustomError::MongoError(error))
+            }
+        }
+    }
+
+    /// Revoke every active refresh token of the user, i.e. log out of all sessions
+    pub async fn revoke_all_user_tokens(&self, user_id: &str) -> Result<u64, CustomError> {
+        let user_obj_id = ObjectId::parse_str(user_id).map_err(|e| {
+            tracing::error!("Error while parsing {}: {:?}", user_id, e);
+            CustomError::InvalidIDError(user_id.to_owned())
+        })?;
+
+        match self
+            .db
+            .collection::<RefreshToken>(REFRESH_TOKENS_COLL)
+            .update_many(
+                doc! { "userId": user_obj_id, "isRevoked": false },
+                doc! { "$set": doc! { "isRevoked": true } },
+            )
+            .await
+        {
+            Ok(value) => {
+                tracing::debug!(
+                    "{} tokens of {} have been revoked",
+                    value.modified_count,
+                    user_id
+                );
+                Ok(value.modified_count)
+            }
+            Err(error) => {
+                tracing::debug!("Error revoking tokens of {}: {}", user_id, error);
+                Err(CustomError::MongoError(error))
+            }
+        }
+    }
+
+    /// Revoke every active refresh token of the user except the ones of `family_id`,
+    /// i.e. log out of all other sessions
+    pub async fn revoke_user_tokens_except_family(
+        &self,
+        user_id: &str,
+        family_id: &str,
+    ) -> Result<u64, CustomError> {
+        let user_obj_id = ObjectId::parse_str(user_id).map_err(|e| {
+            tracing::error!("Error while parsing {}: {:?}", user_id, e);
+            CustomError::InvalidIDError(user_id.to_owned())
+        })?;
+
+        match self
+            .db
+            .collection::<RefreshToken>(REFRESH_TOKENS_COLL)
+            .update_many(
+                doc! {
+                    "userId": user_obj_id,
+                    "familyId": { "$ne": family_id },
+                    "isRevoked": false
+                },
+                doc! { "$set": doc! { "isRevoked": true } },
+            )
+            .await
+        {
+            Ok(value) => {
+                tracing::debug!(
+                    "{} other tokens of {} have been revoked",
+                    value.modified_count,
+                    user_id
+                );
+                Ok(value.modified_count)
+            }
+            Err(error) => {
+                tracing::debug!("Error revoking other tokens of {}: {}", user_id, error);
+                Err(CustomError::MongoError(error))
+            }
+        }
+    }
+
+    /// Every refresh token of the user, revoked and expired ones included, newest first
+    pub async fn get_tokens_by_user(
+        &self,
+        user_id: &ObjectId,
+    ) -> Result<Vec<RefreshToken>, CustomError> {
+        let cursor = self
+            .db
+            .collection::<RefreshToken>(REFRESH_TOKENS_COLL)
+            .find(doc! { "userId": user_id })
+            .sort(doc! { "createdAt": -1 })
+            .await
+            .map_err(|err| {
+                tracing::debug!("Error finding tokens of {}: {}", user_id, err);
+                CustomError::MongoError(err)
+            })?;
+
+        cursor.try_collect().await.map_err(|err| {
+            tracing::debug!("Error collecting tokens of {}: {}", user_id, err);
+            CustomError::MongoError(err)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_db;
+
+    fn token(is_revoked: bool, used_at: Option<DateTime<Utc>>) -> RefreshToken {
+        let now = Utc::now();
+
+        RefreshToken {
+            id: ObjectId::new(),
+            userId: ObjectId::new(),
+            token: "jti".to_owned(),
+            familyId: "family".to_owned(),
+            isRevoked: is_revoked,
+            createdAt: now,
+            expiresAt: now + Duration::days(7),
+            usedAt: used_at,
+            graceUsedAt: None,
+            sessionStartedAt: None,
+            userAgent: None,
+            ip: None,
+        }
+    }
+
+    #[test]
+    fn recently_rotated_token_of_live_family_is_a_race() {
+        let now = Utc::now();
+        let rotated = token(true, Some(now - Duration::seconds(10)));
+
+        assert!(RefreshTokenService::within_rotation_grace(
+            &rotated, true, now
+        ));
+    }
+
+    #[test]
+    fn rotated_token_of_revoked_family_is_reuse_inside_grace_window() {
+        let now = Utc::now();
+        let rotated = token(true, Some(now - Duration::seconds(10)));
+
+        assert!(!RefreshTokenService::within_rotation_grace(
+            &rotated, false, now
+        ));
+    }
+
+    #[test]
+    fn rotated_token_redeemed_in_grace_is_reuse() {
+        let now = Utc::now();
+        let mut rotated = token(true, Some(now - Duration::seconds(10)));
+        rotated.graceUsedAt = Some(now - Duration::seconds(5));
+
+        assert!(!RefreshTokenService::within_rotation_grace(
+            &rotated, true, now
+        ));
+    }
+
+    #[test]
+    fn revoked_token_without_rotation_is_reuse() {
+        let revoked = token(true, None);
+
+        assert!(!RefreshTokenService::within_rotation_grace(
+            &revoked,
+            true,
+            Utc::now()
+        ));
+    }
+
+    #[test]
+    fn rotated_token_after_grace_window_is_reuse() {
+        let now = Utc::now();
+        let rotated = token(true, Some(now - Duration::seconds(ROTATION_GRACE_SECS)));
+
+        assert!(!RefreshTokenService::within_rotation_grace(
+            &rotated, true, now
+        ));
+    }
+
+    #[test]
+    fn active_token_is_not_a_race() {
+        let now = Utc::now();
+        let active = token(false, Some(now));
+
+        assert!(!RefreshTokenService::within_rotation_grace(
+            &active, true, now
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn token_is_rotated_once() {
+        let db = test_db().await;
+        let service = RefreshTokenService::new(db.clone());
+        let now = Utc::now();
+
+        service
+            .create_token(&NewRefreshToken {
+                userId: ObjectId::new(),
+                token: "jti".to_owned(),
+                familyId: "family".to_owned(),
+                isRevoked: false,
+                createdAt: now,
+                expiresAt: now + Duration::days(7),
+                usedAt: None,
+                sessionStartedAt: Some(now),
+                userAgent: None,
+                ip: None,
+            })
+            .await
+            .unwrap();
+
+        let (first, second) =
+            tokio::join!(service.rotate_token("jti"), service.rotate_token("jti"));
+
+        assert!(first.unwrap() ^ second.unwrap());
+        assert!(!service.is_family_active("family").await.unwrap());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn rotated_token_is_redeemed_in_grace_once() {
+        let db = test_db().await;
+        let service = RefreshTokenService::new(db.clone());
+        let now = Utc::now();
+
+        service
+            .create_token(&NewRefreshToken {
+                userId: ObjectId::new(),
+                token: "jti".to_owned(),
+                familyId: "family".to_owned(),
+                isRevoked: false,
+                createdAt: now,
+                expiresAt: now + Duration::days(7),
+                usedAt: None,
+                sessionStartedAt: Some(now),
+                userAgent: None,
+                ip: None,
+            })
+            .await
+            .unwrap();
+
+        // an active token has nothing to redeem yet
+        assert!(!service.redeem_in_grace("jti").await.unwrap());
+        assert!(service.rotate_token("jti").await.unwrap());
+
+        let (first, second) = tokio::join!(
+            service.redeem_in_grace("jti"),
+            service.redeem_in_grace("jti")
+        );
+
+        assert!(first.unwrap() ^ second.unwrap());
+    }
 }
@@ -0,0 +1,134 @@
+use bson::{doc, oid::ObjectId};
+use chrono::Utc;
+use mongodb::{
+    Database,
+    error::{ErrorKind, WriteFailure},
+};
+
+use crate::{
+    models::reset_pass_token::{NewResetPassToken, RESET_PASS_TOKENS_COLL, ResetPassToken},
+    types::error::CustomError,
+};
+
+pub struct ResetPassTokenService {
+    db: Database,
+}
+
+impl ResetPassTokenService {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    pub async fn create_token(&self, data: &NewResetPassToken) -> Result<ObjectId, CustomError> {
+        match self
+            .db
+            .collection::<NewResetPassToken>(RESET_PASS_TOKENS_COLL)
+            .insert_one(data)
+            .await
+        {
+            Ok(v) => {
+                tracing::info!("Created reset pass token with id: {}", v.inserted_id);
+                Ok(v.inserted_id.as_object_id().unwrap())
+            }
+            Err(error) => {
+                tracing::error!("Error creating reset pass token: {:?}", error);
+
+                match error.kind.as_ref() {
+                    ErrorKind::Write(WriteFailure::WriteError(w)) if w.code == 11000 => {
+                        Err(CustomError::DuplicateKey(data.tokenHash.to_owned()))
+                    }
+                    _ => Err(CustomError::MongoError(error)),
+                }
+            }
+        }
+    }
+
+    /// Mark every outstanding reset token of the user as used,
+    /// so only the most recently issued link stays valid
+    pub async fn invalidate_user_tokens(&self, user_id: &ObjectId) -> Result<(), CustomError> {
+        match self
+            .db
+            .collection::<ResetPassToken>(RESET_PASS_TOKENS_COLL)
+            .update_many(
+                doc! {
+                    "userId": user_id,
+                    "usedAt": { "$eq": null }
+                },
+                doc! {
+                    "$set": { "usedAt": Utc::now() }
+                },
+            )
+            .await
+        {
+            Ok(v) => {
+                tracing::debug!(
+                    "{} reset pass tokens have been invalidated",
+                    v.modified_count
+                );
+                Ok(())
+            }
+            Err(err) => {
+                tracing::error!(
+                    "Error invalidating reset pass tokens of {}: {:?}",
+                    user_id,
+                    err
+                );
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    /// Look up a reset token that is unused and not expired yet, without consuming it
+    pub async fn find_valid_token(&self, token_hash: &str) -> Result<ResetPassToken, CustomError> {
+        match self
+            .db
+            .collection::<ResetPassToken>(RESET_PASS_TOKENS_COLL)
+            .find_one(doc! {
+                "tokenHash": token_hash,
+                "usedAt": { "$eq": null },
+                "expiresAt": { "$gt": Utc::now() }
+            })
+            .await
+        {
+            Ok(Some(token)) => Ok(token),
+            Ok(None) => Err(CustomError::InvalidToken),
+            Err(err) => {
+                tracing::error!("Error finding reset pass token {}: {:?}", token_hash, err);
+                Err(CustomError::InvalidToken)
+            }
+        }
+    }
+
+    /// Atomically consume a reset token that is unused and not expired yet
+    pub async fn find_valid_token_then_update(
+        &self,
+        token_hash: &str,
+    ) -> Result<ResetPassToken, CustomError> {
+        match self
+            .db
+            .collection::<ResetPassToken>(RESET_PASS_TOKENS_COLL)
+            .find_one_and_update(
+                doc! {
+                    "tokenHash": token_hash,
+                    "usedAt": { "$eq": null },
+                    "expiresAt": { "$gt": Utc::now() }
+                },
+                doc! {
+                    "$set": { "usedAt": Utc::now() }
+                },
+            )
+            .await
+        {
+            Ok(Some(token)) => Ok(token),
+            Ok(None) => Err(CustomError::InvalidToken),
+            Err(err) => {
+                tracing::error!(
+                    "Error finding then updating reset pass token {}: {:?}",
+                    token_hash,
+                    err
+                );
+                Err(CustomError::InvalidToken)
+            }
+        }
+    }
+}
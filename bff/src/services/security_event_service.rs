@@ -0,0 +1,62 @@
+use bson::{doc, oid::ObjectId};
+use futures::TryStreamExt;
+use mongodb::Database;
+
+use crate::{
+    models::security_event::{NewSecurityEvent, SECURITY_EVENTS_COLL, SecurityEvent},
+    types::error::CustomError,
+};
+
+pub struct SecurityEventService {
+    db: Database,
+}
+
+impl SecurityEventService {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    pub async fn record_event(&self, data: &NewSecurityEvent) -> Result<ObjectId, CustomError> {
+        match self
+            .db
+            .collection::<NewSecurityEvent>(SECURITY_EVENTS_COLL)
+            .insert_one(data)
+            .await
+        {
+            Ok(v) => {
+                tracing::warn!(
+                    "Security event {:?} recorded for user {}: {}",
+                    data.kind,
+                    data.userId,
+                    data.detail
+                );
+                Ok(v.inserted_id.as_object_id().unwrap())
+            }
+            Err(err) => {
+                tracing::error!("Error recording security event: {:?}", err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    pub async fn get_user_events(
+        &self,
+        user_id: &ObjectId,
+    ) -> Result<Vec<SecurityEvent>, CustomError> {
+        let cursor = self
+            .db
+            .collection::<SecurityEvent>(SECURITY_EVENTS_COLL)
+            .find(doc! { "userId": user_id })
+            .sort(doc! { "createdAt": -1 })
+            .await
+            .map_err(|err| {
+                tracing::debug!("Error finding security events of {}: {}", user_id, err);
+                CustomError::MongoError(err)
+            })?;
+
+        cursor.try_collect().await.map_err(|err| {
+            tracing::debug!("Error collecting security events of {}: {}", user_id, err);
+            CustomError::MongoError(err)
+        })
+    }
+}
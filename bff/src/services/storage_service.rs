@@ -1,6 +1,12 @@
-use aws_sdk_s3::Client;
+use aws_sdk_s3::{
+    Client,
+    presigning::PresigningConfig,
+    primitives::ByteStream,
+    types::{Delete, ObjectIdentifier},
+};
+use std::time::Duration;
 
-use crate::config::r2::BUCKET;
+use crate::{config::r2::BUCKET, types::error::CustomError};
 
 pub struct StorageService {
     pub r2_client: Client,
@@ -11,7 +17,10 @@ impl StorageService {
         Self { r2_client: client }
     }
 
-    pub async fn get_object(&self, key: &str) -> Result<(Vec<u8>, Option<String>), Box<dyn std::error::Error>> {
+    pub async fn get_object(
+        &self,
+        key: &str,
+    ) -> Result<(Vec<u8>, Option<String>), Box<dyn std::error::Error>> {
         let resp = self
             .r2_client
             .get_object()
@@ -25,4 +34,188 @@ impl StorageService {
 
         Ok((data.to_vec(), content_type))
     }
+
+    pub async fn put_object(
+        &self,
+        key: &str,
+        bytes: Vec<u8>,
+        content_type: &str,
+    ) -> Result<(), CustomError> {
+        self.r2_client
+            .put_object()
+            .bucket(BUCKET.clone())
+            .key(key)
+            .content_type(content_type)
+            .body(ByteStream::from(bytes))
+            .send()
+            .await
+            .map_err(|e| {
+                tracing::error!("Error uploading object {}: {:?}", key, e);
+                CustomError::R2Error
+            })?;
+
+        Ok(())
+    }
+
+    pub async fn delete_object(&self, key: &str) -> Result<(), CustomError> {
+        self.r2_client
+            .delete_object()
+            .bucket(BUCKET.clone())
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| {
+                tracing::error!("Error deleting object {}: {:?}", key, e);
+                CustomError::R2Error
+            })?;
+
+        Ok(())
+    }
+
+    /// Signed URL to download the object without credentials until it expires
+    pub async fn presigned_get_url(
+        &self,
+        key: &str,
+        expires_in: Duration,
+    ) -> Result<String, CustomError> {
+        let config = PresigningConfig::expires_in(expires_in).map_err(|_| CustomError::R2Error)?;
+
+        let request = self
+            .r2_client
+            .get_object()
+            .bucket(BUCKET.clone())
+            .key(key)
+            .presigned(config)
+            .await
+            .map_err(|e| {
+                tracing::error!("Error presigning object {}: {:?}", key, e);
+                CustomError::R2Error
+            })?;
+
+        Ok(request.uri().to_owned())
+    }
+
+    /// Prefix under which every object belonging to the user is stored
+    pub fn user_prefix(&self, user_id: &str) -> String {
+        format!("users/{}/", user_id)
+    }
+
+    /// Delete every object whose key starts with `prefix`, returns how many were deleted
+    pub async fn delete_objects_with_prefix(&self, prefix: &str) -> Result<usize, CustomError> {
+        let mut deleted = 0;
+        let mut continuation_token = None;
+
+        loop {
+            let page = self
+                .r2_client
+                .list_objects_v2()
+                .bucket(BUCKET.clone())
+                .prefix(prefix)
+                .set_continuation_token(continuation_token)
+                .send()
+                .await
+                .map_err(|e| {
+                    tracing::error!("Error listing objects under {}: {:?}", prefix, e);
+                    CustomError::R2Error
+                })?;
+
+            let objects = page
+                .contents()
+                .iter()
+                .filter_map(|o| o.key())
+                .map(|key| ObjectIdentifier::builder().key(key).build())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| CustomError::R2Error)?;
+
+            if !objects.is_empty() {
+                let count = objects.len();
+
+                let delete = Delete::builder()
+                    .set_objects(Some(objects))
+                    .quiet(true)
+                    .build()
+                    .map_err(|_| CustomError::R2Error)?;
+
+                let output = self
+                    .r2_client
+                    .delete_objects()
+                    .bucket(BUCKET.clone())
+                    .delete(delete)
+                    .send()
+                    .await
+                    .map_err(|e| {
+                        tracing::error!("Error deleting objects under {}: {:?}", prefix, e);
+                        CustomError::R2Error
+                    })?;
+
+                // A quiet delete only lists the objects it failed to remove
+                if !output.errors().is_empty() {
+                    for err in output.errors() {
+                        tracing::error!(
+                            "Error deleting object {:?}: {:?} {:?}",
+                            err.key(),
+                            err.code(),
+                            err.message()
+                        );
+                    }
+                    return Err(CustomError::R2Error);
+                }
+
+                deleted += count;
+            }
+
+            match page.next_continuation_token() {
+                Some(token) if page.is_truncated() == Some(true) => {
+                    continuation_token = Some(token.to_owned())
+                }
+                _ => break,
+            }
+        }
+
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockR2;
+
+    #[tokio::test]
+    async fn prefix_delete_removes_only_the_prefix() {
+        let r2 = MockR2::start().await;
+        r2.put("users/u1/avatars/me.png");
+        r2.put("users/u1/exports/export.zip");
+        r2.put("users/u2/avatars/me.png");
+
+        let storage = StorageService::new(r2.client.clone());
+
+        assert_eq!(
+            storage
+                .delete_objects_with_prefix("users/u1/")
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(r2.keys(), vec!["users/u2/avatars/me.png"]);
+    }
+
+    #[tokio::test]
+    async fn prefix_delete_fails_when_an_object_stays() {
+        let r2 = MockR2::start().await;
+        r2.put("users/u1/avatars/me.png");
+        r2.put("users/u1/exports/export.zip");
+        r2.failing_keys
+            .lock()
+            .unwrap()
+            .insert("users/u1/exports/export.zip".to_owned());
+
+        let storage = StorageService::new(r2.client.clone());
+
+        assert!(matches!(
+            storage.delete_objects_with_prefix("users/u1/").await,
+            Err(CustomError::R2Error)
+        ));
+        assert_eq!(r2.keys(), vec!["users/u1/exports/export.zip"]);
+    }
 }
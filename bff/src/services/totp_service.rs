@@ -0,0 +1,260 @@
+use aes_gcm::{
+    Aes256Gcm, Nonce,
+    aead::{Aead, KeyInit, Payload},
+};
+use aws_lc_rs::constant_time::verify_slices_are_equal;
+use base64::{Engine, engine::general_purpose};
+use rand::TryRngCore;
+use std::env::var;
+use totp_rs::{Algorithm, TOTP};
+
+use crate::{types::error::CustomError, utils::datetime::now_epoch};
+
+const TOTP_SECRET_BYTES: usize = 20;
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP_SECS: u64 = 30;
+/// Steps before and after the current one which are accepted too
+const TOTP_SKEW: u8 = 1;
+const NONCE_BYTES: usize = 12;
+pub const RECOVERY_CODES_COUNT: usize = 10;
+
+pub struct TotpService {
+    cipher: Aes256Gcm,
+}
+
+impl TotpService {
+    pub fn new() -> Self {
+        let key_b64 = var("TOTP_ENCRYPTION_KEY").expect("TOTP_ENCRYPTION_KEY missing");
+        let key = general_purpose::STANDARD
+            .decode(&key_b64)
+            .expect("Invalid TOTP_ENCRYPTION_KEY base64");
+
+        Self {
+            cipher: Aes256Gcm::new_from_slice(&key).expect("TOTP_ENCRYPTION_KEY must be 32 bytes"),
+        }
+    }
+
+    pub fn generate_secret(&self) -> Result<Vec<u8>, CustomError> {
+        let mut secret = vec![0u8; TOTP_SECRET_BYTES];
+        rand::rngs::OsRng
+            .try_fill_bytes(&mut secret)
+            .map_err(|_| CustomError::TokenCreation)?;
+
+        Ok(secret)
+    }
+
+    fn totp(&self, secret: Vec<u8>, account: &str) -> Result<TOTP, CustomError> {
+        TOTP::new(
+            Algorithm::SHA1,
+            TOTP_DIGITS,
+            TOTP_SKEW,
+            TOTP_STEP_SECS,
+            secret,
+            Some(var("APP_NAME").expect("APP_NAME missing")),
+            account.to_owned(),
+        )
+        .map_err(|e| {
+            tracing::error!("Error creating TOTP: {:?}", e);
+            CustomError::TokenCreation
+        })
+    }
+
+    /// Returns the base32 secret and the otpauth URI to be shown as a QR code
+    pub fn enrollment(
+        &self,
+        secret: &[u8],
+        account: &str,
+    ) -> Result<(String, String), CustomError> {
+        let totp = self.totp(secret.to_vec(), account)?;
+
+        Ok((totp.get_secret_base32(), totp.get_url()))
+    }
+
+    /// Check `code` against the steps around now and return the step it matched.
+    /// Steps up to `last_step`, the one of the last accepted code, are refused
+    /// so a code can't be replayed while it is still current
+    pub fn verify_code(
+        &self,
+        secret: &[u8],
+        code: &str,
+        last_step: Option<u64>,
+    ) -> Result<Option<u64>, CustomError> {
+        let totp = self.totp(secret.to_vec(), "")?;
+        let now = now_epoch() as u64;
+
+        Ok(matching_step(&totp, code.trim(), now, last_step))
+    }
+
+    /// Encrypt the secret of `user_id` to be stored, as base64 of nonce followed
+    /// by ciphertext. The user id is bound as associated data, so the ciphertext
+    /// can't be copied onto another user
+    pub fn encrypt_secret(&self, secret: &[u8], user_id: &str) -> Result<String, CustomError> {
+        let mut nonce = [0u8; NONCE_BYTES];
+        rand::rngs::OsRng
+            .try_fill_bytes(&mut nonce)
+            .map_err(|_| CustomError::TokenCreation)?;
+
+        let payload = Payload {
+            msg: secret,
+            aad: user_id.as_bytes(),
+        };
+
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| CustomError::TokenCreation)?;
+
+        Ok(general_purpose::STANDARD.encode([nonce.as_slice(), &ciphertext].concat()))
+    }
+
+    pub fn decrypt_secret(&self, encrypted: &str, user_id: &str) -> Result<Vec<u8>, CustomError> {
+        let bytes = general_purpose::STANDARD
+            .decode(encrypted)
+            .map_err(|_| CustomError::InvalidToken)?;
+
+        if bytes.len() <= NONCE_BYTES {
+            return Err(CustomError::InvalidToken);
+        }
+
+        let (nonce, ciphertext) = bytes.split_at(NONCE_BYTES);
+
+        let payload = Payload {
+            msg: ciphertext,
+            aad: user_id.as_bytes(),
+        };
+
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| {
+                tracing::error!("Error decrypting TOTP secret of {}", user_id);
+                CustomError::InvalidToken
+            })
+    }
+
+    /// Generate one-time recovery codes formatted as `xxxxx-xxxxx`
+    pub fn generate_recovery_codes(&self) -> Result<Vec<String>, CustomError> {
+        (0..RECOVERY_CODES_COUNT)
+            .map(|_| {
+                let mut bytes = [0u8; 5];
+                rand::rngs::OsRng
+                    .try_fill_bytes(&mut bytes)
+                    .map_err(|_| CustomError::TokenCreation)?;
+
+                let code = hex::encode(bytes);
+
+                Ok(format!("{}-{}", &code[..5], &code[5..]))
+            })
+            .collect()
+    }
+
+    /// Normalize a recovery code typed by the user before hashing it
+    pub fn normalize_recovery_code(&self, code: &str) -> String {
+        let code: String = code
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_lowercase();
+
+        match code.len() {
+            10 => format!("{}-{}", &code[..5], &code[5..]),
+            _ => code,
+        }
+    }
+}
+
+/// The step within the allowed skew around `now` whose code is `code`,
+/// ignoring the ones up to `last_step`
+fn matching_step(totp: &TOTP, code: &str, now: u64, last_step: Option<u64>) -> Option<u64> {
+    let current = now / TOTP_STEP_SECS;
+    let skew = TOTP_SKEW as u64;
+
+    (current.saturating_sub(skew)..=current + skew)
+        .filter(|step| last_step.is_none_or(|last| *step > last))
+        .find(|step| {
+            let expected = totp.generate(step * TOTP_STEP_SECS);
+
+            verify_slices_are_equal(expected.as_bytes(), code.as_bytes()).is_ok()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::init_env;
+
+    const SECRET: &[u8] = b"12345678901234567890";
+    const NOW: u64 = 1_700_000_000;
+
+    fn service() -> TotpService {
+        init_env();
+        TotpService::new()
+    }
+
+    fn code_at(service: &TotpService, time: u64) -> String {
+        service.totp(SECRET.to_vec(), "").unwrap().generate(time)
+    }
+
+    #[test]
+    fn current_and_adjacent_codes_are_accepted() {
+        let service = service();
+        let totp = service.totp(SECRET.to_vec(), "").unwrap();
+        let step = NOW / TOTP_STEP_SECS;
+
+        let current = code_at(&service, NOW);
+        let previous = code_at(&service, NOW - TOTP_STEP_SECS);
+
+        assert_eq!(matching_step(&totp, &current, NOW, None), Some(step));
+        assert_eq!(matching_step(&totp, &previous, NOW, None), Some(step - 1));
+    }
+
+    #[test]
+    fn code_outside_skew_is_rejected() {
+        let service = service();
+        let totp = service.totp(SECRET.to_vec(), "").unwrap();
+
+        let old = code_at(&service, NOW - 3 * TOTP_STEP_SECS);
+
+        assert_eq!(matching_step(&totp, &old, NOW, None), None);
+    }
+
+    #[test]
+    fn accepted_code_is_not_accepted_again() {
+        let service = service();
+        let totp = service.totp(SECRET.to_vec(), "").unwrap();
+        let step = NOW / TOTP_STEP_SECS;
+
+        let current = code_at(&service, NOW);
+        let previous = code_at(&service, NOW - TOTP_STEP_SECS);
+
+        // Still inside the window 30 seconds later, but already used
+        assert_eq!(
+            matching_step(&totp, &current, NOW + TOTP_STEP_SECS, Some(step)),
+            None
+        );
+        assert_eq!(matching_step(&totp, &previous, NOW, Some(step)), None);
+    }
+
+    #[test]
+    fn secret_only_decrypts_for_its_user() {
+        let service = service();
+
+        let encrypted = service.encrypt_secret(SECRET, "alice").unwrap();
+
+        assert_eq!(service.decrypt_secret(&encrypted, "alice").unwrap(), SECRET);
+        assert!(service.decrypt_secret(&encrypted, "mallory").is_err());
+    }
+
+    #[test]
+    fn recovery_codes_are_normalized() {
+        let service = service();
+
+        assert_eq!(
+            service.normalize_recovery_code(" AB12C 3d4e5 "),
+            "ab12c-3d4e5"
+        );
+        assert_eq!(
+            service.generate_recovery_codes().unwrap().len(),
+            RECOVERY_CODES_COUNT
+        );
+    }
+}
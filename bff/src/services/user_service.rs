@@ -1,5 +1,6 @@
-use bson::{doc, oid::ObjectId};
-use chrono::Utc;
+use bson::{Document, doc, oid::ObjectId};
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
 use mongodb::{
     Database,
     error::{ErrorKind, WriteFailure},
@@ -7,7 +8,11 @@ use mongodb::{
 
 use crate::{
     models::user::{NewUser, USERS_COLL, User},
-    types::error::CustomError,
+    types::{
+        error::CustomError,
+        role::{Permission, Role},
+    },
+    utils::{db_util::username_collation, email::normalize_email},
 };
 
 pub struct UserService {
@@ -73,6 +78,468 @@ impl UserService {
         }
     }
 
+    pub async fn update_password(
+        &self,
+        user_id: &str,
+        password_hash: &str,
+    ) -> Result<(), CustomError> {
+        let user_obj_id = ObjectId::parse_str(user_id).map_err(|e| {
+            tracing::error!("Error while parsing {}: {:?}", user_id, e);
+            CustomError::InvalidIDError(user_id.to_owned())
+        })?;
+
+        match self
+            .db
+            .collection::<User>(USERS_COLL)
+            .update_one(
+                doc! {
+                    "_id": user_obj_id
+                },
+                doc! {
+                    "$set": {
+                        "password": password_hash,
+                        "updatedAt": Utc::now()
+                    }
+                },
+            )
+            .await
+        {
+            Ok(value) if value.matched_count == 0 => {
+                Err(CustomError::NotFoundError(user_id.to_owned()))
+            }
+            Ok(_) => Ok(()),
+            Err(err) => {
+                tracing::error!("Error updating password for {}: {:?}", user_id, err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    /// Switch the user to a new, already confirmed email address
+    pub async fn update_email(&self, user_id: &str, email: &str) -> Result<(), CustomError> {
+        let user_obj_id = ObjectId::parse_str(user_id).map_err(|e| {
+            tracing::error!("Error while parsing {}: {:?}", user_id, e);
+            CustomError::InvalidIDError(user_id.to_owned())
+        })?;
+
+        match self
+            .db
+            .collection::<User>(USERS_COLL)
+            .update_one(
+                doc! {
+                    "_id": user_obj_id
+                },
+                doc! {
+                    "$set": {
+                        "email": normalize_email(email),
+                        "isEmailVerified": true,
+                        "updatedAt": Utc::now()
+                    }
+                },
+            )
+            .await
+        {
+            Ok(value) if value.matched_count == 0 => {
+                Err(CustomError::NotFoundError(user_id.to_owned()))
+            }
+            Ok(_) => Ok(()),
+            Err(err) => {
+                tracing::error!("Error updating email for {}: {:?}", user_id, err);
+
+                match err.kind.as_ref() {
+                    ErrorKind::Write(WriteFailure::WriteError(w)) if w.code == 11000 => {
+                        Err(CustomError::DuplicateKey(email.to_owned()))
+                    }
+                    _ => Err(CustomError::MongoError(err)),
+                }
+            }
+        }
+    }
+
+    pub async fn update_username(&self, user_id: &str, username: &str) -> Result<(), CustomError> {
+        let user_obj_id = ObjectId::parse_str(user_id).map_err(|e| {
+            tracing::error!("Error while parsing {}: {:?}", user_id, e);
+            CustomError::InvalidIDError(user_id.to_owned())
+        })?;
+
+        match self
+            .db
+            .collection::<User>(USERS_COLL)
+            .update_one(
+                doc! { "_id": user_obj_id },
+                doc! {
+                    "$set": { "username": username, "updatedAt": Utc::now() }
+                },
+            )
+            .await
+        {
+            Ok(value) if value.matched_count == 0 => {
+                Err(CustomError::NotFoundError(user_id.to_owned()))
+            }
+            Ok(_) => Ok(()),
+            Err(err) => {
+                tracing::error!("Error updating username for {}: {:?}", user_id, err);
+
+                match err.kind.as_ref() {
+                    ErrorKind::Write(WriteFailure::WriteError(w)) if w.code == 11000 => {
+                        Err(CustomError::DuplicateKey(username.to_owned()))
+                    }
+                    _ => Err(CustomError::MongoError(err)),
+                }
+            }
+        }
+    }
+
+    pub async fn username_exists(&self, username: &str) -> Result<bool, CustomError> {
+        match self
+            .db
+            .collection::<User>(USERS_COLL)
+            .count_documents(doc! { "username": username })
+            .collation(username_collation())
+            .await
+        {
+            Ok(count) => Ok(count > 0),
+            Err(err) => {
+                tracing::error!("Error looking up username {}: {:?}", username, err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    pub async fn set_pending_totp_secret(
+        &self,
+        user_id: &str,
+        secret: &str,
+    ) -> Result<(), CustomError> {
+        self.update_user(
+            user_id,
+            doc! {
+                "$set": {
+                    "pendingTotpSecret": secret,
+                    "updatedAt": Utc::now()
+                }
+            },
+        )
+        .await
+    }
+
+    /// Turn on 2FA with the confirmed secret and the hashed recovery codes
+    pub async fn enable_totp(
+        &self,
+        user_id: &str,
+        secret: &str,
+        recovery_code_hashes: Vec<String>,
+        last_step: i64,
+    ) -> Result<(), CustomError> {
+        self.update_user(
+            user_id,
+            doc! {
+                "$set": {
+                    "isTotpEnabled": true,
+                    "totpSecret": secret,
+                    "totpLastStep": last_step,
+                    "totpRecoveryCodes": recovery_code_hashes,
+                    "updatedAt": Utc::now()
+                },
+                "$unset": { "pendingTotpSecret": "" }
+            },
+        )
+        .await
+    }
+
+    pub async fn disable_totp(&self, user_id: &str) -> Result<(), CustomError> {
+        self.update_user(
+            user_id,
+            doc! {
+                "$set": {
+                    "isTotpEnabled": false,
+                    "totpRecoveryCodes": [],
+                    "updatedAt": Utc::now()
+                },
+                "$unset": { "totpSecret": "", "totpLastStep": "", "pendingTotpSecret": "" }
+            },
+        )
+        .await
+    }
+
+    /// Atomically record the time step of an accepted TOTP code, returns false
+    /// if the same or a later step was accepted already, i.e. the code is replayed
+    pub async fn record_totp_step(&self, user_id: &str, step: i64) -> Result<bool, CustomError> {
+        let user_obj_id = ObjectId::parse_str(user_id).map_err(|e| {
+            tracing::error!("Error while parsing {}: {:?}", user_id, e);
+            CustomError::InvalidIDError(user_id.to_owned())
+        })?;
+
+        match self
+            .db
+            .collection::<User>(USERS_COLL)
+            .update_one(
+                doc! {
+                    "_id": user_obj_id,
+                    "$or": [
+                        { "totpLastStep": null },
+                        { "totpLastStep": { "$lt": step } }
+                    ]
+                },
+                doc! {
+                    "$set": { "totpLastStep": step, "updatedAt": Utc::now() }
+                },
+            )
+            .await
+        {
+            Ok(value) => Ok(value.modified_count == 1),
+            Err(err) => {
+                tracing::error!("Error recording TOTP step of {}: {:?}", user_id, err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    /// Replace the roles and directly granted permissions of the user
+    pub async fn update_roles(
+        &self,
+        user_id: &str,
+        roles: &[Role],
+        permissions: &[Permission],
+    ) -> Result<(), CustomError> {
+        let roles: Vec<&str> = roles.iter().map(Role::as_str).collect();
+        let permissions: Vec<&str> = permissions.iter().map(Permission::as_str).collect();
+
+        self.update_user(
+            user_id,
+            doc! {
+                "$set": {
+                    "roles": roles,
+                    "permissions": permissions,
+                    "updatedAt": Utc::now()
+                }
+            },
+        )
+        .await
+    }
+
+    /// Set the given profile fields, a `None` value removes the field
+    pub async fn update_profile(
+        &self,
+        user_id: &str,
+        changes: &[(&str, Option<&str>)],
+    ) -> Result<(), CustomError> {
+        let mut set = doc! { "updatedAt": Utc::now() };
+        let mut unset = Document::new();
+
+        for (field, value) in changes {
+            match value {
+                Some(value) => set.insert(*field, *value),
+                None => unset.insert(*field, ""),
+            };
+        }
+
+        let mut update = doc! { "$set": set };
+
+        if !unset.is_empty() {
+            update.insert("$unset", unset);
+        }
+
+        self.update_user(user_id, update).await
+    }
+
+    pub async fn set_disabled(&self, user_id: &str, disabled: bool) -> Result<(), CustomError> {
+        self.update_user(
+            user_id,
+            doc! {
+                "$set": { "isDisabled": disabled, "updatedAt": Utc::now() }
+            },
+        )
+        .await
+    }
+
+    /// Soft delete the user. The email and username are replaced so they can be
+    /// taken again, secrets, the profile and any privileges are dropped and the TTL
+    /// index purges the document at `purge_at`
+    pub async fn soft_delete(
+        &self,
+        user_id: &str,
+        purge_at: DateTime<Utc>,
+    ) -> Result<(), CustomError> {
+        self.update_user(
+            user_id,
+            doc! {
+                "$set": {
+                    "email": format!("deleted-{}@deleted.invalid", user_id),
+                    "username": format!("deleted-{}", user_id),
+                    "password": "",
+                    "isTotpEnabled": false,
+                    "totpRecoveryCodes": [],
+                    "roles": [],
+                    "permissions": [],
+                    "deletedAt": Utc::now(),
+                    "purgeAt": purge_at,
+                    "updatedAt": Utc::now()
+                },
+                "$unset": {
+                    "totpSecret": "",
+                    "pendingTotpSecret": "",
+                    "displayName": "",
+                    "bio": "",
+                    "avatarKey": "",
+                    "locale": "",
+                    "timezone": ""
+                }
+            },
+        )
+        .await
+    }
+
+    /// Whether the user was disabled or deleted, only the flags are read
+    pub async fn is_user_disabled(&self, user_id: &str) -> Result<bool, CustomError> {
+        let user_obj_id = ObjectId::parse_str(user_id)
+            .map_err(|_| CustomError::InvalidIDError(user_id.to_owned()))?;
+
+        match self
+            .db
+            .collection::<Document>(USERS_COLL)
+            .find_one(doc! { "_id": user_obj_id })
+            .projection(doc! { "isDisabled": 1, "deletedAt": 1 })
+            .await
+        {
+            Ok(Some(user)) => {
+                Ok(user.get_bool("isDisabled").unwrap_or(false) || user.contains_key("deletedAt"))
+            }
+            Ok(None) => Err(CustomError::NotFoundError(user_id.to_owned())),
+            Err(err) => {
+                tracing::error!("Error finding user {}: {:?}", user_id, err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    /// Whether the account was deleted. A user whose document is already gone counts
+    /// as deleted
+    pub async fn is_user_deleted(&self, user_id: &ObjectId) -> Result<bool, CustomError> {
+        match self
+            .db
+            .collection::<Document>(USERS_COLL)
+            .find_one(doc! { "_id": user_id })
+            .projection(doc! { "deletedAt": 1 })
+            .await
+        {
+            Ok(Some(user)) => Ok(user.contains_key("deletedAt")),
+            Ok(None) => Ok(true),
+            Err(err) => {
+                tracing::error!("Error finding user {}: {:?}", user_id, err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    /// Page through users whose email or username contains `query`, newest first.
+    /// Deleted users are left out unless `include_deleted`.
+    /// Returns the page and the total count of matching users
+    pub async fn search_users(
+        &self,
+        query: Option<&str>,
+        include_deleted: bool,
+        skip: u64,
+        limit: i64,
+    ) -> Result<(Vec<User>, u64), CustomError> {
+        let mut filter = match query.map(str::trim).filter(|q| !q.is_empty()) {
+            Some(q) => {
+                let pattern = escape_regex(q);
+
+                doc! {
+                    "$or": [
+                        { "email": { "$regex": &pattern, "$options": "i" } },
+                        { "username": { "$regex": &pattern, "$options": "i" } }
+                    ]
+                }
+            }
+            None => doc! {},
+        };
+
+        if !include_deleted {
+            filter.insert("deletedAt", doc! { "$exists": false });
+        }
+
+        let users = self.db.collection::<User>(USERS_COLL);
+
+        let total = users.count_documents(filter.clone()).await.map_err(|err| {
+            tracing::error!("Error counting users: {:?}", err);
+            CustomError::MongoError(err)
+        })?;
+
+        let cursor = users
+            .find(filter)
+            .sort(doc! { "createdAt": -1 })
+            .skip(skip)
+            .limit(limit)
+            .await
+            .map_err(|err| {
+                tracing::error!("Error searching users: {:?}", err);
+                CustomError::MongoError(err)
+            })?;
+
+        let page = cursor.try_collect().await.map_err(|err| {
+            tracing::error!("Error collecting users: {:?}", err);
+            CustomError::MongoError(err)
+        })?;
+
+        Ok((page, total))
+    }
+
+    /// Atomically remove a recovery code, returns false if it wasn't there
+    pub async fn consume_recovery_code(
+        &self,
+        user_id: &str,
+        code_hash: &str,
+    ) -> Result<bool, CustomError> {
+        let user_obj_id = ObjectId::parse_str(user_id).map_err(|e| {
+            tracing::error!("Error while parsing {}: {:?}", user_id, e);
+            CustomError::InvalidIDError(user_id.to_owned())
+        })?;
+
+        match self
+            .db
+            .collection::<User>(USERS_COLL)
+            .update_one(
+                doc! { "_id": user_obj_id, "totpRecoveryCodes": code_hash },
+                doc! {
+                    "$pull": { "totpRecoveryCodes": code_hash },
+                    "$set": { "updatedAt": Utc::now() }
+                },
+            )
+            .await
+        {
+            Ok(value) => Ok(value.modified_count == 1),
+            Err(err) => {
+                tracing::error!("Error consuming recovery code of {}: {:?}", user_id, err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    async fn update_user(&self, user_id: &str, update: Document) -> Result<(), CustomError> {
+        let user_obj_id = ObjectId::parse_str(user_id).map_err(|e| {
+            tracing::error!("Error while parsing {}: {:?}", user_id, e);
+            CustomError::InvalidIDError(user_id.to_owned())
+        })?;
+
+        match self
+            .db
+            .collection::<User>(USERS_COLL)
+            .update_one(doc! { "_id": user_obj_id }, update)
+            .await
+        {
+            Ok(value) if value.matched_count == 0 => {
+                Err(CustomError::NotFoundError(user_id.to_owned()))
+            }
+            Ok(_) => Ok(()),
+            Err(err) => {
+                tracing::error!("Error updating user {}: {:?}", user_id, err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
     pub async fn get_user_by_id(&self, id: &str) -> Result<User, CustomError> {
         let user_id =
             ObjectId::parse_str(id).map_err(|_| CustomError::InvalidIDError(id.to_owned()))?;
@@ -96,7 +563,7 @@ impl UserService {
         match self
             .db
             .collection::<User>(USERS_COLL)
-            .find_one(doc! { "email": email.to_lowercase() })
+            .find_one(doc! { "email": normalize_email(email) })
             .await
         {
             Ok(Some(user)) => Ok(user),
@@ -108,3 +575,17 @@ impl UserService {
         }
     }
 }
+
+/// Escape the regex metacharacters so user input is matched literally
+fn escape_regex(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
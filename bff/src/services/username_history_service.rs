@@ -0,0 +1,134 @@
+use bson::{doc, oid::ObjectId};
+use chrono::Utc;
+use mongodb::Database;
+
+use crate::{
+    models::username_history::{NewUsernameHistory, USERNAME_HISTORY_COLL, UsernameHistory},
+    services::user_service::UserService,
+    types::error::CustomError,
+};
+
+/// A user can change the username once per this many days
+pub const USERNAME_CHANGE_COOLDOWN_DAYS: i64 = 30;
+/// An old username is reserved for its previous owner for this many days
+pub const USERNAME_HOLD_DAYS: i64 = 90;
+
+pub struct UsernameHistoryService {
+    db: Database,
+}
+
+impl UsernameHistoryService {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    pub async fn create_entry(&self, data: &NewUsernameHistory) -> Result<ObjectId, CustomError> {
+        match self
+            .db
+            .collection::<NewUsernameHistory>(USERNAME_HISTORY_COLL)
+            .insert_one(data)
+            .await
+        {
+            Ok(v) => {
+                tracing::info!("Created username history entry with id: {}", v.inserted_id);
+                Ok(v.inserted_id.as_object_id().unwrap())
+            }
+            Err(err) => {
+                tracing::error!("Error creating username history entry: {:?}", err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    /// Why the normalized `username` can't be taken by `user_id` (or a new user),
+    /// `None` if it's free. Old usernames are only free for their previous owner
+    pub async fn username_taken_reason(
+        &self,
+        user_service: &UserService,
+        username: &str,
+        user_id: Option<&ObjectId>,
+    ) -> Result<Option<String>, CustomError> {
+        if user_service.username_exists(username).await? {
+            return Ok(Some("is taken".to_owned()));
+        }
+
+        let held = self.get_held_entry(username).await?;
+
+        Ok(Self::held_reason(held.as_ref(), user_id))
+    }
+
+    /// Why a username held by `held` can't be taken by `user_id`
+    fn held_reason(held: Option<&UsernameHistory>, user_id: Option<&ObjectId>) -> Option<String> {
+        match held {
+            Some(entry) if Some(&entry.userId) != user_id => {
+                Some("was recently used by another account".to_owned())
+            }
+            _ => None,
+        }
+    }
+
+    /// The latest entry which still holds `username` for its previous owner
+    pub async fn get_held_entry(
+        &self,
+        username: &str,
+    ) -> Result<Option<UsernameHistory>, CustomError> {
+        self.db
+            .collection::<UsernameHistory>(USERNAME_HISTORY_COLL)
+            .find_one(doc! { "username": username, "heldUntil": { "$gt": Utc::now() } })
+            .sort(doc! { "heldUntil": -1 })
+            .await
+            .map_err(|err| {
+                tracing::debug!("Error finding held username {}: {}", username, err);
+                CustomError::MongoError(err)
+            })
+    }
+
+    /// The last username change of the user
+    pub async fn get_latest_change(
+        &self,
+        user_id: &ObjectId,
+    ) -> Result<Option<UsernameHistory>, CustomError> {
+        self.db
+            .collection::<UsernameHistory>(USERNAME_HISTORY_COLL)
+            .find_one(doc! { "userId": user_id })
+            .sort(doc! { "changedAt": -1 })
+            .await
+            .map_err(|err| {
+                tracing::debug!("Error finding username changes of {}: {}", user_id, err);
+                CustomError::MongoError(err)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn held_by(user_id: ObjectId) -> UsernameHistory {
+        UsernameHistory {
+            id: ObjectId::new(),
+            userId: user_id,
+            username: "alice".to_owned(),
+            newUsername: "alice2".to_owned(),
+            changedAt: Utc::now(),
+            heldUntil: Utc::now() + Duration::days(USERNAME_HOLD_DAYS),
+        }
+    }
+
+    #[test]
+    fn held_username_is_free_for_its_previous_owner_only() {
+        let owner = ObjectId::new();
+        let entry = held_by(owner);
+
+        assert_eq!(
+            UsernameHistoryService::held_reason(Some(&entry), Some(&owner)),
+            None
+        );
+        assert!(
+            UsernameHistoryService::held_reason(Some(&entry), Some(&ObjectId::new())).is_some()
+        );
+        assert!(UsernameHistoryService::held_reason(Some(&entry), None).is_some());
+        assert_eq!(UsernameHistoryService::held_reason(None, None), None);
+    }
+}
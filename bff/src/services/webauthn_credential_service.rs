@@ -0,0 +1,173 @@
+use bson::{doc, oid::ObjectId};
+use chrono::Utc;
+use futures::TryStreamExt;
+use mongodb::{
+    Database,
+    error::{ErrorKind, WriteFailure},
+};
+
+use crate::{
+    models::webauthn_credential::{
+        NewWebauthnCredential, WEBAUTHN_CHALLENGES_COLL, WEBAUTHN_CREDENTIALS_COLL,
+        WebauthnCeremony, WebauthnChallenge, WebauthnCredential,
+    },
+    types::error::CustomError,
+};
+
+pub struct WebauthnCredentialService {
+    db: Database,
+}
+
+impl WebauthnCredentialService {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    pub async fn create_challenge(&self, data: &WebauthnChallenge) -> Result<(), CustomError> {
+        match self
+            .db
+            .collection::<WebauthnChallenge>(WEBAUTHN_CHALLENGES_COLL)
+            .insert_one(data)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                tracing::error!("Error creating passkey challenge: {:?}", err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    /// Atomically remove a pending challenge so it can only be answered once
+    pub async fn consume_challenge(
+        &self,
+        challenge: &str,
+        ceremony: WebauthnCeremony,
+    ) -> Result<WebauthnChallenge, CustomError> {
+        match self
+            .db
+            .collection::<WebauthnChallenge>(WEBAUTHN_CHALLENGES_COLL)
+            .find_one_and_delete(doc! {
+                "challenge": challenge,
+                "ceremony": ceremony.as_str(),
+                "expiresAt": { "$gt": Utc::now() }
+            })
+            .await
+        {
+            Ok(Some(challenge)) => Ok(challenge),
+            Ok(None) => Err(CustomError::InvalidToken),
+            Err(err) => {
+                tracing::error!("Error consuming passkey challenge: {:?}", err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    pub async fn create_credential(
+        &self,
+        data: &NewWebauthnCredential,
+    ) -> Result<ObjectId, CustomError> {
+        match self
+            .db
+            .collection::<NewWebauthnCredential>(WEBAUTHN_CREDENTIALS_COLL)
+            .insert_one(data)
+            .await
+        {
+            Ok(v) => {
+                tracing::info!("Created passkey with id: {}", v.inserted_id);
+                Ok(v.inserted_id.as_object_id().unwrap())
+            }
+            Err(error) => {
+                tracing::error!("Error creating passkey: {:?}", error);
+
+                match error.kind.as_ref() {
+                    ErrorKind::Write(WriteFailure::WriteError(w)) if w.code == 11000 => {
+                        Err(CustomError::DuplicateKey(data.credentialId.to_owned()))
+                    }
+                    _ => Err(CustomError::MongoError(error)),
+                }
+            }
+        }
+    }
+
+    pub async fn get_credentials_by_user(
+        &self,
+        user_id: &ObjectId,
+    ) -> Result<Vec<WebauthnCredential>, CustomError> {
+        let cursor = self
+            .db
+            .collection::<WebauthnCredential>(WEBAUTHN_CREDENTIALS_COLL)
+            .find(doc! { "userId": user_id })
+            .await
+            .map_err(|err| {
+                tracing::debug!("Error finding passkeys of {}: {}", user_id, err);
+                CustomError::MongoError(err)
+            })?;
+
+        cursor.try_collect().await.map_err(|err| {
+            tracing::debug!("Error collecting passkeys of {}: {}", user_id, err);
+            CustomError::MongoError(err)
+        })
+    }
+
+    pub async fn get_credential(
+        &self,
+        credential_id: &str,
+    ) -> Result<WebauthnCredential, CustomError> {
+        match self
+            .db
+            .collection::<WebauthnCredential>(WEBAUTHN_CREDENTIALS_COLL)
+            .find_one(doc! { "credentialId": credential_id })
+            .await
+        {
+            Ok(Some(credential)) => Ok(credential),
+            Ok(None) => Err(CustomError::NotFoundError(credential_id.to_owned())),
+            Err(err) => {
+                tracing::debug!("Error finding passkey: {}", err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    /// Store the new signature counter, only if nobody else moved it meanwhile
+    pub async fn update_sign_count(
+        &self,
+        id: &ObjectId,
+        previous: i64,
+        sign_count: i64,
+    ) -> Result<(), CustomError> {
+        match self
+            .db
+            .collection::<WebauthnCredential>(WEBAUTHN_CREDENTIALS_COLL)
+            .update_one(
+                doc! { "_id": id, "signCount": previous },
+                doc! {
+                    "$set": { "signCount": sign_count, "lastUsedAt": Utc::now() }
+                },
+            )
+            .await
+        {
+            Ok(v) if v.matched_count == 1 => Ok(()),
+            Ok(_) => Err(CustomError::WrongCredentials),
+            Err(err) => {
+                tracing::error!("Error updating passkey {}: {:?}", id, err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+
+    pub async fn delete_user_credentials(&self, user_id: &ObjectId) -> Result<u64, CustomError> {
+        match self
+            .db
+            .collection::<WebauthnCredential>(WEBAUTHN_CREDENTIALS_COLL)
+            .delete_many(doc! { "userId": user_id })
+            .await
+        {
+            Ok(v) => Ok(v.deleted_count),
+            Err(err) => {
+                tracing::error!("Error deleting passkeys of {}: {:?}", user_id, err);
+                Err(CustomError::MongoError(err))
+            }
+        }
+    }
+}
@@ -0,0 +1,768 @@
+use aws_lc_rs::signature::{
+    ECDSA_P256_SHA256_ASN1, ED25519, RSA_PKCS1_2048_8192_SHA256, RsaPublicKeyComponents,
+    UnparsedPublicKey,
+};
+use base64::{Engine, engine::general_purpose};
+use bson::oid::ObjectId;
+use ciborium::Value;
+use rand::TryRngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::env::var;
+
+use crate::{
+    dtos::passkey_dto::{
+        AuthenticatorSelection, CredentialDescriptor, CredentialParam, PasskeyAuthenticationDto,
+        PasskeyCreationOptionsDto, PasskeyRegistrationDto, PasskeyRequestOptionsDto, PasskeyUser,
+        RelyingParty,
+    },
+    types::error::CustomError,
+};
+
+pub const PASSKEY_CHALLENGE_EXP_SECS: i64 = 5 * 60;
+const CHALLENGE_BYTES: usize = 32;
+/// COSE algorithm ids of the credential keys we accept
+pub(crate) const COSE_ALG_ES256: i64 = -7;
+pub(crate) const COSE_ALG_EDDSA: i64 = -8;
+pub(crate) const COSE_ALG_RS256: i64 = -257;
+/// Offered to authenticators in this order of preference
+const SUPPORTED_COSE_ALGS: [i64; 3] = [COSE_ALG_ES256, COSE_ALG_EDDSA, COSE_ALG_RS256];
+pub(crate) const FLAG_USER_PRESENT: u8 = 0x01;
+pub(crate) const FLAG_USER_VERIFIED: u8 = 0x04;
+pub(crate) const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+/// Parsed fixed part of the authenticator data
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    flags: u8,
+    sign_count: u32,
+    /// Attested credential data and extensions, if any
+    rest: &'a [u8],
+}
+
+/// Public key of a credential with the algorithm it signs with
+enum CredentialPublicKey {
+    /// Uncompressed P-256 point
+    Es256(Vec<u8>),
+    Ed25519(Vec<u8>),
+    /// Big-endian modulus and exponent without leading zeros
+    Rs256 {
+        n: Vec<u8>,
+        e: Vec<u8>,
+    },
+}
+
+pub struct VerifiedRegistration {
+    pub challenge: String,
+    pub credential_id: String,
+    /// base64url COSE key of the credential
+    pub public_key: String,
+    pub public_key_alg: i64,
+    pub sign_count: u32,
+}
+
+pub struct VerifiedAssertion {
+    pub challenge: String,
+    pub sign_count: u32,
+}
+
+pub struct WebauthnService {
+    rp_id: String,
+    rp_name: String,
+    origin: String,
+}
+
+impl WebauthnService {
+    pub fn new() -> Self {
+        Self {
+            rp_id: var("WEBAUTHN_RP_ID").expect("WEBAUTHN_RP_ID missing"),
+            rp_name: var("APP_NAME").expect("APP_NAME missing"),
+            origin: var("WEBAUTHN_ORIGIN").expect("WEBAUTHN_ORIGIN missing"),
+        }
+    }
+
+    pub fn generate_challenge(&self) -> Result<String, CustomError> {
+        let mut challenge = [0u8; CHALLENGE_BYTES];
+        rand::rngs::OsRng
+            .try_fill_bytes(&mut challenge)
+            .map_err(|_| CustomError::TokenCreation)?;
+
+        Ok(general_purpose::URL_SAFE_NO_PAD.encode(challenge))
+    }
+
+    /// The user handle is the raw bytes of the user id
+    pub fn user_handle(&self, user_id: &ObjectId) -> String {
+        general_purpose::URL_SAFE_NO_PAD.encode(user_id.bytes())
+    }
+
+    pub fn creation_options(
+        &self,
+        challenge: String,
+        user_id: &ObjectId,
+        username: &str,
+        exclude_credential_ids: Vec<String>,
+    ) -> PasskeyCreationOptionsDto {
+        PasskeyCreationOptionsDto {
+            challenge,
+            rp: RelyingParty {
+                id: self.rp_id.clone(),
+                name: self.rp_name.clone(),
+            },
+            user: PasskeyUser {
+                id: self.user_handle(user_id),
+                name: username.to_owned(),
+                display_name: username.to_owned(),
+            },
+            pub_key_cred_params: SUPPORTED_COSE_ALGS
+                .iter()
+                .map(|alg| CredentialParam {
+                    kind: "public-key".to_owned(),
+                    alg: *alg,
+                })
+                .collect(),
+            timeout: PASSKEY_CHALLENGE_EXP_SECS as u64 * 1000,
+            attestation: "none".to_owned(),
+            exclude_credentials: exclude_credential_ids
+                .into_iter()
+                .map(|id| CredentialDescriptor {
+                    kind: "public-key".to_owned(),
+                    id,
+                })
+                .collect(),
+            authenticator_selection: AuthenticatorSelection {
+                resident_key: "required".to_owned(),
+                user_verification: "required".to_owned(),
+            },
+        }
+    }
+
+    /// Options for a discoverable credential login, no credentials are listed
+    pub fn request_options(&self, challenge: String) -> PasskeyRequestOptionsDto {
+        PasskeyRequestOptionsDto {
+            challenge,
+            rp_id: self.rp_id.clone(),
+            timeout: PASSKEY_CHALLENGE_EXP_SECS as u64 * 1000,
+            user_verification: "required".to_owned(),
+            allow_credentials: vec![],
+        }
+    }
+
+    /// Check the attestation of a new credential and extract its public key.
+    /// The attestation statement itself is not verified as we ask for `none`
+    pub fn verify_registration(
+        &self,
+        payload: &PasskeyRegistrationDto,
+    ) -> Result<VerifiedRegistration, CustomError> {
+        let client_data_json = decode_b64url(&payload.response.client_data_json)?;
+        let challenge = self.verify_client_data(&client_data_json, "webauthn.create")?;
+
+        let attestation_object = decode_b64url(&payload.response.attestation_object)?;
+        let attestation: Value = ciborium::from_reader(attestation_object.as_slice())
+            .map_err(|_| invalid("attestation object is not CBOR"))?;
+
+        let auth_data_bytes = attestation
+            .as_map()
+            .and_then(|map| text_field(map, "authData"))
+            .and_then(Value::as_bytes)
+            .ok_or_else(|| invalid("attestation object has no authData"))?;
+
+        let auth_data = self.verify_authenticator_data(auth_data_bytes)?;
+
+        if auth_data.flags & FLAG_ATTESTED_CREDENTIAL == 0 {
+            return Err(invalid("no attested credential data"));
+        }
+
+        // aaguid (16 bytes) then the credential id prefixed by its length
+        let rest = auth_data.rest;
+        if rest.len() < 18 {
+            return Err(invalid("attested credential data too short"));
+        }
+
+        let id_len = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+        let credential_id = rest
+            .get(18..18 + id_len)
+            .ok_or_else(|| invalid("credential id out of bounds"))?;
+
+        if general_purpose::URL_SAFE_NO_PAD.encode(credential_id)
+            != normalize_b64url(&payload.raw_id)
+        {
+            return Err(invalid("credential id does not match rawId"));
+        }
+
+        let cose_key: Value = ciborium::from_reader(&rest[18 + id_len..])
+            .map_err(|_| invalid("credential public key is not CBOR"))?;
+
+        let public_key_alg = CredentialPublicKey::from_cose(&cose_key)?.alg();
+
+        // Only the key itself is kept, without extensions which may follow it
+        let mut public_key = Vec::new();
+        ciborium::into_writer(&cose_key, &mut public_key)
+            .map_err(|_| invalid("credential public key can't be encoded"))?;
+
+        Ok(VerifiedRegistration {
+            challenge,
+            credential_id: general_purpose::URL_SAFE_NO_PAD.encode(credential_id),
+            public_key: general_purpose::URL_SAFE_NO_PAD.encode(public_key),
+            public_key_alg,
+            sign_count: auth_data.sign_count,
+        })
+    }
+
+    /// Check an assertion against the stored public key and signature counter.
+    /// Without `public_key_alg` the key is an uncompressed P-256 point, as stored
+    /// for credentials registered when ES256 was the only algorithm accepted
+    pub fn verify_assertion(
+        &self,
+        payload: &PasskeyAuthenticationDto,
+        public_key: &str,
+        public_key_alg: Option<i64>,
+        stored_sign_count: u32,
+    ) -> Result<VerifiedAssertion, CustomError> {
+        let client_data_json = decode_b64url(&payload.response.client_data_json)?;
+        let challenge = self.verify_client_data(&client_data_json, "webauthn.get")?;
+
+        let auth_data_bytes = decode_b64url(&payload.response.authenticator_data)?;
+        let auth_data = self.verify_authenticator_data(&auth_data_bytes)?;
+
+        let signature = decode_b64url(&payload.response.signature)?;
+        let public_key = decode_b64url(public_key)?;
+
+        let public_key = match public_key_alg {
+            None => CredentialPublicKey::Es256(public_key),
+            Some(alg) => {
+                let cose_key: Value = ciborium::from_reader(public_key.as_slice())
+                    .map_err(|_| invalid("stored public key is not CBOR"))?;
+                let key = CredentialPublicKey::from_cose(&cose_key)?;
+
+                if key.alg() != alg {
+                    return Err(invalid("stored public key has another algorithm"));
+                }
+
+                key
+            }
+        };
+
+        let mut signed = auth_data_bytes.clone();
+        signed.extend_from_slice(&Sha256::digest(&client_data_json));
+
+        public_key
+            .verify(&signed, &signature)
+            .map_err(|_| CustomError::WrongCredentials)?;
+
+        // authenticators without a counter always send 0, otherwise it must grow
+        if (auth_data.sign_count != 0 || stored_sign_count != 0)
+            && auth_data.sign_count <= stored_sign_count
+        {
+            tracing::warn!(
+                "Passkey signature counter went from {} to {}, the authenticator may be cloned",
+                stored_sign_count,
+                auth_data.sign_count
+            );
+            return Err(CustomError::WrongCredentials);
+        }
+
+        Ok(VerifiedAssertion {
+            challenge,
+            sign_count: auth_data.sign_count,
+        })
+    }
+
+    /// Returns the challenge of the client data once its type and origin are checked
+    fn verify_client_data(
+        &self,
+        client_data_json: &[u8],
+        kind: &str,
+    ) -> Result<String, CustomError> {
+        let client_data: ClientData = serde_json::from_slice(client_data_json)
+            .map_err(|_| invalid("client data is not JSON"))?;
+
+        if client_data.kind != kind {
+            return Err(invalid("unexpected client data type"));
+        }
+
+        if client_data.origin != self.origin {
+            return Err(invalid("unexpected origin"));
+        }
+
+        Ok(normalize_b64url(&client_data.challenge))
+    }
+
+    fn verify_authenticator_data<'a>(
+        &self,
+        auth_data: &'a [u8],
+    ) -> Result<AuthenticatorData<'a>, CustomError> {
+        if auth_data.len() < 37 {
+            return Err(invalid("authenticator data too short"));
+        }
+
+        let parsed = AuthenticatorData {
+            rp_id_hash: &auth_data[..32],
+            flags: auth_data[32],
+            sign_count: u32::from_be_bytes([
+                auth_data[33],
+                auth_data[34],
+                auth_data[35],
+                auth_data[36],
+            ]),
+            rest: &auth_data[37..],
+        };
+
+        if parsed.rp_id_hash != Sha256::digest(self.rp_id.as_bytes()).as_slice() {
+            return Err(invalid("unexpected RP ID hash"));
+        }
+
+        if parsed.flags & FLAG_USER_PRESENT == 0 {
+            return Err(invalid("user not present"));
+        }
+
+        // A passkey is the only factor, so possession alone isn't enough
+        if parsed.flags & FLAG_USER_VERIFIED == 0 {
+            return Err(invalid("user not verified"));
+        }
+
+        Ok(parsed)
+    }
+}
+
+pub fn normalize_b64url(value: &str) -> String {
+    value.trim_end_matches('=').to_owned()
+}
+
+fn decode_b64url(value: &str) -> Result<Vec<u8>, CustomError> {
+    general_purpose::URL_SAFE_NO_PAD
+        .decode(normalize_b64url(value))
+        .map_err(|_| invalid("invalid base64url"))
+}
+
+fn invalid(reason: &str) -> CustomError {
+    tracing::debug!("Passkey verification failed: {}", reason);
+    CustomError::InvalidToken
+}
+
+fn text_field<'a>(map: &'a [(Value, Value)], name: &str) -> Option<&'a Value> {
+    map.iter()
+        .find(|(k, _)| k.as_text() == Some(name))
+        .map(|(_, v)| v)
+}
+
+fn int_field(map: &[(Value, Value)], label: i64) -> Option<&Value> {
+    map.iter()
+        .find(|(k, _)| k.as_integer().map(i128::from) == Some(label as i128))
+        .map(|(_, v)| v)
+}
+
+impl CredentialPublicKey {
+    /// Read a COSE key of one of `SUPPORTED_COSE_ALGS`
+    fn from_cose(cose_key: &Value) -> Result<Self, CustomError> {
+        let map = cose_key
+            .as_map()
+            .ok_or_else(|| invalid("COSE key is not a map"))?;
+
+        let int = |label| {
+            int_field(map, label)
+                .and_then(Value::as_integer)
+                .map(i128::from)
+        };
+        let bytes = |label| {
+            int_field(map, label)
+                .and_then(Value::as_bytes)
+                .ok_or_else(|| invalid("missing COSE key parameter"))
+        };
+
+        // kty 2 = EC2 with crv 1 = P-256, kty 1 = OKP with crv 6 = Ed25519, kty 3 = RSA
+        match (int(1), int(3)) {
+            (Some(2), Some(alg)) if alg == COSE_ALG_ES256 as i128 && int(-1) == Some(1) => {
+                let (x, y) = (bytes(-2)?, bytes(-3)?);
+
+                if x.len() != 32 || y.len() != 32 {
+                    return Err(invalid("invalid EC2 coordinate"));
+                }
+
+                Ok(Self::Es256([&[0x04], x.as_slice(), y.as_slice()].concat()))
+            }
+            (Some(1), Some(alg)) if alg == COSE_ALG_EDDSA as i128 && int(-1) == Some(6) => {
+                let x = bytes(-2)?;
+
+                if x.len() != 32 {
+                    return Err(invalid("invalid Ed25519 key"));
+                }
+
+                Ok(Self::Ed25519(x.clone()))
+            }
+            (Some(3), Some(alg)) if alg == COSE_ALG_RS256 as i128 => Ok(Self::Rs256 {
+                n: without_leading_zeros(bytes(-1)?),
+                e: without_leading_zeros(bytes(-2)?),
+            }),
+            _ => Err(invalid(
+                "unsupported COSE key, only ES256, EdDSA and RS256 are accepted",
+            )),
+        }
+    }
+
+    fn alg(&self) -> i64 {
+        match self {
+            Self::Es256(_) => COSE_ALG_ES256,
+            Self::Ed25519(_) => COSE_ALG_EDDSA,
+            Self::Rs256 { .. } => COSE_ALG_RS256,
+        }
+    }
+
+    fn verify(
+        &self,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), aws_lc_rs::error::Unspecified> {
+        match self {
+            Self::Es256(point) => {
+                UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, point).verify(message, signature)
+            }
+            Self::Ed25519(key) => UnparsedPublicKey::new(&ED25519, key).verify(message, signature),
+            Self::Rs256 { n, e } => RsaPublicKeyComponents { n, e }.verify(
+                &RSA_PKCS1_2048_8192_SHA256,
+                message,
+                signature,
+            ),
+        }
+    }
+}
+
+fn without_leading_zeros(bytes: &[u8]) -> Vec<u8> {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+
+    bytes[start..].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::SoftwareAuthenticator;
+
+    const RP_ID: &str = "example.com";
+    const ORIGIN: &str = "https://example.com";
+
+    fn service() -> WebauthnService {
+        WebauthnService {
+            rp_id: RP_ID.to_owned(),
+            rp_name: "Test".to_owned(),
+            origin: ORIGIN.to_owned(),
+        }
+    }
+
+    #[test]
+    fn options_require_user_verification() {
+        let service = service();
+        let options = service.creation_options("c".to_owned(), &ObjectId::new(), "alice", vec![]);
+
+        assert_eq!(
+            options.authenticator_selection.user_verification,
+            "required"
+        );
+        assert_eq!(
+            service.request_options("c".to_owned()).user_verification,
+            "required"
+        );
+    }
+
+    #[test]
+    fn registration_and_assertion_round_trip() {
+        let service = service();
+        let mut authenticator = SoftwareAuthenticator::new(RP_ID, ORIGIN);
+
+        let registration = service
+            .verify_registration(&authenticator.register("register-challenge"))
+            .unwrap();
+
+        assert_eq!(registration.challenge, "register-challenge");
+        assert_eq!(registration.credential_id, authenticator.credential_id());
+
+        let assertion = service
+            .verify_assertion(
+                &authenticator.assert("login-challenge"),
+                &registration.public_key,
+                Some(registration.public_key_alg),
+                registration.sign_count,
+            )
+            .unwrap();
+
+        assert_eq!(assertion.challenge, "login-challenge");
+        assert_eq!(assertion.sign_count, 1);
+    }
+
+    #[test]
+    fn bad_signature_is_rejected() {
+        let service = service();
+        let mut authenticator = SoftwareAuthenticator::new(RP_ID, ORIGIN);
+        let registration = service
+            .verify_registration(&authenticator.register("c"))
+            .unwrap();
+
+        let mut assertion = authenticator.assert("c");
+        // Signed by another authenticator
+        assertion.response.signature = SoftwareAuthenticator::new(RP_ID, ORIGIN)
+            .assert("c")
+            .response
+            .signature;
+
+        assert!(matches!(
+            service.verify_assertion(
+                &assertion,
+                &registration.public_key,
+                Some(registration.public_key_alg),
+                0
+            ),
+            Err(CustomError::WrongCredentials)
+        ));
+    }
+
+    #[test]
+    fn wrong_origin_is_rejected() {
+        let service = service();
+        let mut authenticator = SoftwareAuthenticator::new(RP_ID, ORIGIN);
+        let registration = service
+            .verify_registration(&authenticator.register("c"))
+            .unwrap();
+
+        authenticator.origin = "https://evil.example".to_owned();
+
+        assert!(
+            service
+                .verify_registration(&authenticator.register("c"))
+                .is_err()
+        );
+        assert!(
+            service
+                .verify_assertion(
+                    &authenticator.assert("c"),
+                    &registration.public_key,
+                    Some(registration.public_key_alg),
+                    0
+                )
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn counter_regression_is_rejected() {
+        let service = service();
+        let mut authenticator = SoftwareAuthenticator::new(RP_ID, ORIGIN);
+        let registration = service
+            .verify_registration(&authenticator.register("c"))
+            .unwrap();
+
+        let assertion = authenticator.assert("c");
+
+        // A clone of the authenticator which already signed more often
+        assert!(matches!(
+            service.verify_assertion(
+                &assertion,
+                &registration.public_key,
+                Some(registration.public_key_alg),
+                5
+            ),
+            Err(CustomError::WrongCredentials)
+        ));
+    }
+
+    #[test]
+    fn missing_user_verification_is_rejected() {
+        let service = service();
+        let mut authenticator = SoftwareAuthenticator::new(RP_ID, ORIGIN);
+        let registration = service
+            .verify_registration(&authenticator.register("c"))
+            .unwrap();
+
+        authenticator.flags = FLAG_USER_PRESENT;
+
+        assert!(
+            service
+                .verify_registration(&authenticator.register("c"))
+                .is_err()
+        );
+        assert!(matches!(
+            service.verify_assertion(
+                &authenticator.assert("c"),
+                &registration.public_key,
+                Some(registration.public_key_alg),
+                0
+            ),
+            Err(CustomError::InvalidToken)
+        ));
+    }
+
+    #[test]
+    fn eddsa_and_rs256_round_trip() {
+        let service = service();
+
+        for alg in [COSE_ALG_EDDSA, COSE_ALG_RS256] {
+            let mut authenticator = SoftwareAuthenticator::with_alg(RP_ID, ORIGIN, alg);
+            let registration = service
+                .verify_registration(&authenticator.register("c"))
+                .unwrap();
+
+            assert_eq!(registration.public_key_alg, alg);
+
+            let assertion = service
+                .verify_assertion(
+                    &authenticator.assert("c"),
+                    &registration.public_key,
+                    Some(alg),
+                    registration.sign_count,
+                )
+                .unwrap();
+
+            assert_eq!(assertion.sign_count, 1);
+
+            // The stored key can't be passed off as one of another algorithm
+            assert!(
+                service
+                    .verify_assertion(
+                        &authenticator.assert("c"),
+                        &registration.public_key,
+                        Some(COSE_ALG_ES256),
+                        0,
+                    )
+                    .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn stored_p256_point_still_verifies() {
+        let service = service();
+        let mut authenticator = SoftwareAuthenticator::new(RP_ID, ORIGIN);
+        let registration = service
+            .verify_registration(&authenticator.register("c"))
+            .unwrap();
+
+        let cose_key: Value =
+            ciborium::from_reader(decode_b64url(&registration.public_key).unwrap().as_slice())
+                .unwrap();
+        let Ok(CredentialPublicKey::Es256(point)) = CredentialPublicKey::from_cose(&cose_key)
+        else {
+            panic!("expected an ES256 key");
+        };
+
+        assert!(
+            service
+                .verify_assertion(
+                    &authenticator.assert("c"),
+                    &general_purpose::URL_SAFE_NO_PAD.encode(point),
+                    None,
+                    0,
+                )
+                .is_ok()
+        );
+    }
+
+    /// Deterministic xorshift so failures can be replayed
+    struct Fuzzer(u64);
+
+    impl Fuzzer {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            (self.next() % bound.max(1) as u64) as usize
+        }
+
+        fn bytes(&mut self, len: usize) -> Vec<u8> {
+            (0..len).map(|_| self.next() as u8).collect()
+        }
+
+        /// Flip a bit of, drop a byte of or truncate a valid input, so it always changes
+        fn mutate(&mut self, input: &[u8]) -> Vec<u8> {
+            let mut output = input.to_vec();
+
+            match self.below(3) {
+                0 => {
+                    let i = self.below(output.len());
+                    if let Some(byte) = output.get_mut(i) {
+                        *byte ^= 1 << self.below(8);
+                    }
+                }
+                1 => {
+                    let i = self.below(output.len());
+                    if i < output.len() {
+                        output.remove(i);
+                    }
+                }
+                _ => output.truncate(self.below(output.len())),
+            }
+
+            output
+        }
+    }
+
+    fn reencode(value: &str, fuzzer: &mut Fuzzer) -> String {
+        general_purpose::URL_SAFE_NO_PAD.encode(fuzzer.mutate(&decode_b64url(value).unwrap()))
+    }
+
+    #[test]
+    fn fuzzed_payloads_are_rejected_without_panicking() {
+        let service = service();
+        let mut fuzzer = Fuzzer(0x9E37_79B9_7F4A_7C15);
+
+        for alg in SUPPORTED_COSE_ALGS {
+            let mut authenticator = SoftwareAuthenticator::with_alg(RP_ID, ORIGIN, alg);
+            let registration = authenticator.register("c");
+            let public_key = service
+                .verify_registration(&registration)
+                .unwrap()
+                .public_key;
+
+            for _ in 0..500 {
+                let mut payload = registration.clone();
+                payload.response.attestation_object =
+                    reencode(&registration.response.attestation_object, &mut fuzzer);
+                if fuzzer.below(4) == 0 {
+                    payload.response.client_data_json =
+                        reencode(&registration.response.client_data_json, &mut fuzzer);
+                }
+                let _ = service.verify_registration(&payload);
+
+                let assertion = authenticator.assert("c");
+                let mut payload = assertion.clone();
+                match fuzzer.below(3) {
+                    0 => {
+                        payload.response.authenticator_data =
+                            reencode(&assertion.response.authenticator_data, &mut fuzzer)
+                    }
+                    1 => {
+                        payload.response.signature =
+                            reencode(&assertion.response.signature, &mut fuzzer)
+                    }
+                    _ => {
+                        payload.response.client_data_json =
+                            reencode(&assertion.response.client_data_json, &mut fuzzer)
+                    }
+                }
+                assert!(
+                    service
+                        .verify_assertion(&payload, &public_key, Some(alg), 0)
+                        .is_err()
+                );
+
+                let fuzzed_key = reencode(&public_key, &mut fuzzer);
+                let _ = service.verify_assertion(&assertion, &fuzzed_key, Some(alg), 0);
+                let _ = service.verify_assertion(&assertion, &fuzzed_key, None, 0);
+
+                let len = fuzzer.below(96);
+                let noise = fuzzer.bytes(len);
+                let _ = ciborium::from_reader::<Value, _>(noise.as_slice())
+                    .map(|value| CredentialPublicKey::from_cose(&value));
+            }
+        }
+    }
+}
@@ -1,7 +1,15 @@
 use crate::services::{
-    auth_service::AuthService, email_service::EmailService,
-    refresh_token_service::RefreshTokenService, storage_service::StorageService,
-    user_service::UserService, email_verif_token_service::VerifEmailTokenService,
+    access_denylist_service::AccessDenylistService, account_purge_service::AccountPurgeService,
+    auth_service::AuthService, data_export_service::DataExportService, email_service::EmailService,
+    email_verif_token_service::VerifEmailTokenService,
+    linked_identity_service::LinkedIdentityService, login_attempt_service::LoginAttemptService,
+    mfa_token_service::MfaTokenService, oidc_service::OidcService,
+    password_policy_service::PasswordPolicyService, rate_limit_service::RateLimitService,
+    refresh_token_service::RefreshTokenService, reset_pass_token_service::ResetPassTokenService,
+    security_event_service::SecurityEventService, storage_service::StorageService,
+    totp_service::TotpService, user_service::UserService,
+    username_history_service::UsernameHistoryService,
+    webauthn_credential_service::WebauthnCredentialService, webauthn_service::WebauthnService,
 };
 
 pub struct AppState {
@@ -11,4 +19,19 @@ pub struct AppState {
     pub storage_service: StorageService,
     pub email_service: EmailService,
     pub verif_email_token_service: VerifEmailTokenService,
+    pub reset_pass_token_service: ResetPassTokenService,
+    pub security_event_service: SecurityEventService,
+    pub login_attempt_service: LoginAttemptService,
+    pub rate_limit_service: RateLimitService,
+    pub totp_service: TotpService,
+    pub webauthn_credential_service: WebauthnCredentialService,
+    pub webauthn_service: WebauthnService,
+    pub linked_identity_service: LinkedIdentityService,
+    pub oidc_service: OidcService,
+    pub data_export_service: DataExportService,
+    pub username_history_service: UsernameHistoryService,
+    pub access_denylist_service: AccessDenylistService,
+    pub password_policy_service: PasswordPolicyService,
+    pub account_purge_service: AccountPurgeService,
+    pub mfa_token_service: MfaTokenService,
 }
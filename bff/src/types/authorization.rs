@@ -0,0 +1,163 @@
+use crate::{
+    AppState,
+    types::{
+        claims::Claims,
+        error::CustomError,
+        role::{Permission, Role},
+    },
+};
+use axum::{extract::FromRequestParts, http::request::Parts};
+use std::{marker::PhantomData, sync::Arc};
+
+/// Marker of a role required by [`RequireRole`]
+pub trait RoleMarker {
+    const ROLE: Role;
+}
+
+/// Marker of a permission required by [`RequirePermission`]
+pub trait PermissionMarker {
+    const PERMISSION: Permission;
+}
+
+pub struct Admin;
+
+impl RoleMarker for Admin {
+    const ROLE: Role = Role::Admin;
+}
+
+pub struct ReadUsers;
+
+impl PermissionMarker for ReadUsers {
+    const PERMISSION: Permission = Permission::ReadUsers;
+}
+
+pub struct ManageUsers;
+
+impl PermissionMarker for ManageUsers {
+    const PERMISSION: Permission = Permission::ManageUsers;
+}
+
+/// Claims of a caller holding the role `R`, anyone else gets a 403
+pub struct RequireRole<R: RoleMarker> {
+    pub claims: Claims,
+    _role: PhantomData<R>,
+}
+
+impl<R: RoleMarker> FromRequestParts<Arc<AppState>> for RequireRole<R> {
+    type Rejection = CustomError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let claims = Claims::from_request_parts(parts, state).await?;
+
+        check_role(&claims, R::ROLE)?;
+
+        Ok(Self {
+            claims,
+            _role: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+impl<R: RoleMarker> RequireRole<R> {
+    /// Same check as the extractor on claims which didn't come from a request
+    pub fn checked(claims: Claims) -> Result<Self, CustomError> {
+        check_role(&claims, R::ROLE)?;
+
+        Ok(Self {
+            claims,
+            _role: PhantomData,
+        })
+    }
+}
+
+/// Claims of a caller holding the permission `P`, anyone else gets a 403
+pub struct RequirePermission<P: PermissionMarker> {
+    pub claims: Claims,
+    _permission: PhantomData<P>,
+}
+
+impl<P: PermissionMarker> FromRequestParts<Arc<AppState>> for RequirePermission<P> {
+    type Rejection = CustomError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let claims = Claims::from_request_parts(parts, state).await?;
+
+        check_permission(&claims, P::PERMISSION)?;
+
+        Ok(Self {
+            claims,
+            _permission: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+impl<P: PermissionMarker> RequirePermission<P> {
+    /// Same check as the extractor on claims which didn't come from a request
+    pub fn checked(claims: Claims) -> Result<Self, CustomError> {
+        check_permission(&claims, P::PERMISSION)?;
+
+        Ok(Self {
+            claims,
+            _permission: PhantomData,
+        })
+    }
+}
+
+fn check_role(claims: &Claims, role: Role) -> Result<(), CustomError> {
+    if !claims.roles.contains(&role) {
+        tracing::warn!("User {} lacks role {:?}", claims.sub, role);
+        return Err(CustomError::Forbidden);
+    }
+
+    Ok(())
+}
+
+fn check_permission(claims: &Claims, permission: Permission) -> Result<(), CustomError> {
+    if !claims.perms.contains(&permission) {
+        tracing::warn!("User {} lacks permission {:?}", claims.sub, permission);
+        return Err(CustomError::Forbidden);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::claims_of;
+
+    #[test]
+    fn role_is_required() {
+        let mut claims = claims_of("user-1", "family-a");
+        claims.roles = vec![Role::Moderator];
+
+        assert!(matches!(
+            check_role(&claims, Admin::ROLE),
+            Err(CustomError::Forbidden)
+        ));
+
+        claims.roles.push(Role::Admin);
+
+        assert!(check_role(&claims, Admin::ROLE).is_ok());
+    }
+
+    #[test]
+    fn permission_is_required() {
+        let mut claims = claims_of("user-1", "family-a");
+        claims.perms = vec![Permission::ReadUsers];
+
+        assert!(check_permission(&claims, ReadUsers::PERMISSION).is_ok());
+        assert!(matches!(
+            check_permission(&claims, ManageUsers::PERMISSION),
+            Err(CustomError::Forbidden)
+        ));
+    }
+}
@@ -0,0 +1,111 @@
+use crate::{
+    config::email::{EMAIL_CONFIG, whole_minutes},
+    services::auth_service::EMAIL_VERIFICATION_EXP_SECS,
+};
+
+const EXPIRY_MINUTES: u64 = whole_minutes(EMAIL_VERIFICATION_EXP_SECS as u64);
+
+/// Values for the email sent to the new address to confirm the change
+pub struct ChangeEmail {
+    app_name: String,
+    username: String,
+    new_email: String,
+    confirmation_url: String,
+    expiry_minutes: String,
+    support_email: String,
+    company_address: String,
+}
+
+impl ChangeEmail {
+    pub fn new(username: &str, user_id: &str, new_email: &str, token: &str) -> Self {
+        Self {
+            app_name: EMAIL_CONFIG.app_name.clone(),
+            username: username.to_owned(),
+            new_email: new_email.to_owned(),
+            confirmation_url: format!(
+                "{}/auth/confirm_email_change?token={}&user_id={}",
+                EMAIL_CONFIG.domain, token, user_id
+            ),
+            expiry_minutes: EXPIRY_MINUTES.to_string(),
+            support_email: EMAIL_CONFIG.support_email.clone(),
+            company_address: EMAIL_CONFIG.company_address.clone(),
+        }
+    }
+    pub fn as_array(&self) -> [(&str, &str); 7] {
+        [
+            ("app_name", &self.app_name),
+            ("username", &self.username),
+            ("new_email", &self.new_email),
+            ("confirmation_url", &self.confirmation_url),
+            ("expiry_minutes", &self.expiry_minutes),
+            ("support_email", &self.support_email),
+            ("company_address", &self.company_address),
+        ]
+    }
+}
+
+/// Values for the notice sent to the old address when a change is requested
+pub struct EmailChangeNotice {
+    app_name: String,
+    username: String,
+    new_email: String,
+    support_email: String,
+    company_address: String,
+}
+
+impl EmailChangeNotice {
+    pub fn new(username: &str, new_email: &str) -> Self {
+        Self {
+            app_name: EMAIL_CONFIG.app_name.clone(),
+            username: username.to_owned(),
+            new_email: new_email.to_owned(),
+            support_email: EMAIL_CONFIG.support_email.clone(),
+            company_address: EMAIL_CONFIG.company_address.clone(),
+        }
+    }
+    pub fn as_array(&self) -> [(&str, &str); 5] {
+        [
+            ("app_name", &self.app_name),
+            ("username", &self.username),
+            ("new_email", &self.new_email),
+            ("support_email", &self.support_email),
+            ("company_address", &self.company_address),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::init_env;
+
+    #[test]
+    fn confirmation_link_goes_to_the_new_address_owner() {
+        init_env();
+
+        let values = ChangeEmail::new("alice", "user-1", "new@example.com", "raw-token");
+
+        assert!(values.as_array().contains(&(
+            "confirmation_url",
+            "https://api.test/auth/confirm_email_change?token=raw-token&user_id=user-1"
+        )));
+        assert!(
+            values
+                .as_array()
+                .contains(&("new_email", "new@example.com"))
+        );
+    }
+
+    #[test]
+    fn notice_to_the_old_address_names_the_new_one() {
+        init_env();
+
+        let values = EmailChangeNotice::new("alice", "new@example.com");
+
+        assert!(
+            values
+                .as_array()
+                .contains(&("new_email", "new@example.com"))
+        );
+    }
+}
@@ -1,18 +1,28 @@
+use crate::AppState;
 use crate::types::error::CustomError;
-use crate::types::keys::KEYS;
+use crate::types::role::{Permission, Role};
 use axum::{RequestPartsExt, extract::FromRequestParts, http::request::Parts};
 use axum_extra::{
     TypedHeader,
     headers::{Authorization, authorization::Bearer},
 };
-use jsonwebtoken::{Algorithm, Validation, decode, errors::ErrorKind};
 use serde::{Deserialize, Serialize};
-use std::{env::var, fmt::Display};
+use std::{fmt::Display, sync::Arc};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
     pub exp: usize,
+    /// Session (refresh token family) the access token was issued for
+    pub sid: String,
+    /// Id of the token itself, so it can be revoked before it expires
+    pub jti: String,
+    pub iat: usize,
+    #[serde(default)]
+    pub roles: Vec<Role>,
+    /// Effective permissions, from the roles and granted directly
+    #[serde(default)]
+    pub perms: Vec<Permission>,
     pub aud: String,
     pub iss: String,
 }
@@ -23,32 +33,39 @@ impl Display for Claims {
     }
 }
 
-impl<S> FromRequestParts<S> for Claims
-where
-    S: Send + Sync,
-{
+impl Claims {
+    /// Check that the decoded access token wasn't revoked, by itself, with its session
+    /// or with all tokens of its user. Ending a session or disabling a user always adds
+    /// such an entry, so this needs no lookup of the user or the session
+    pub async fn ensure_not_revoked(&self, state: &AppState) -> Result<(), CustomError> {
+        if state.access_denylist_service.is_revoked(self).await? {
+            tracing::debug!("Revoked access token of {} was used", self.sub);
+            return Err(CustomError::InvalidToken);
+        }
+
+        Ok(())
+    }
+}
+
+impl FromRequestParts<Arc<AppState>> for Claims {
     type Rejection = CustomError;
 
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
         // Extract the token from the authorization header
         let TypedHeader(Authorization(bearer)) = parts
             .extract::<TypedHeader<Authorization<Bearer>>>()
             .await
             .map_err(|_| CustomError::InvalidToken)?;
         // Decode the user data
-        let mut validation = Validation::new(Algorithm::EdDSA);
-        validation.set_audience(&[var("JWT_AUDIENCE").expect("JWT_AUDIENCE missing")]);
-        validation.set_issuer(&[var("JWT_ISSUER").expect("JWT_ISSUER missing")]);
-
-        match decode::<Claims>(bearer.token(), &KEYS.decoding, &validation) {
-            Ok(value) => {
-                tracing::info!("Req from {} has just arrived", value.claims.sub);
-                Ok(value.claims)
-            }
-            Err(err) => match err.kind() {
-                ErrorKind::ExpiredSignature => Err(CustomError::TokenExpired),
-                _ => Err(CustomError::InvalidToken),
-            },
-        }
+        let claims = state.auth_service.decode_access_token(bearer.token())?;
+
+        tracing::info!("Req from {} has just arrived", claims.sub);
+
+        claims.ensure_not_revoked(state).await?;
+
+        Ok(claims)
     }
 }
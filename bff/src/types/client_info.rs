@@ -0,0 +1,170 @@
+use crate::{config::trusted_proxies::TRUSTED_PROXIES, types::error::CustomError};
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts},
+};
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+
+/// Information about the client device sending the request
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = CustomError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_owned());
+
+        Ok(Self {
+            user_agent,
+            ip: client_ip(parts),
+        })
+    }
+}
+
+/// Get the client IP. Forwarding headers are only believed when the TCP peer
+/// is one of `TRUSTED_PROXIES`, otherwise any client could pick its own IP
+pub fn client_ip(parts: &Parts) -> Option<String> {
+    let peer = parts
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+
+    let forwarded_for: Vec<&str> = parts
+        .headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect();
+
+    let real_ip = parts.headers.get("x-real-ip").and_then(|v| v.to_str().ok());
+
+    resolve_client_ip(peer, &forwarded_for, real_ip, &TRUSTED_PROXIES).map(|ip| ip.to_string())
+}
+
+/// Walk `X-Forwarded-For` from the right, where each trusted proxy appended the
+/// address it received the request from, and take the first untrusted hop.
+/// Entries left of it were sent by the client and are ignored
+fn resolve_client_ip(
+    peer: Option<IpAddr>,
+    forwarded_for: &[&str],
+    real_ip: Option<&str>,
+    trusted: &[IpNet],
+) -> Option<IpAddr> {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+
+    let peer = peer?;
+
+    if !is_trusted(&peer) {
+        return Some(peer);
+    }
+
+    if forwarded_for.iter().all(|hop| hop.trim().is_empty()) {
+        return real_ip.and_then(|ip| ip.trim().parse().ok()).or(Some(peer));
+    }
+
+    let mut client = peer;
+
+    for hop in forwarded_for.iter().rev().map(|hop| hop.trim()) {
+        if hop.is_empty() {
+            continue;
+        }
+
+        // Garbage can only come from the client, so the last trusted hop is kept
+        let Ok(ip) = hop.parse::<IpAddr>() else {
+            break;
+        };
+
+        client = ip;
+
+        if !is_trusted(&ip) {
+            break;
+        }
+    }
+
+    Some(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn proxies() -> Vec<IpNet> {
+        vec!["10.0.0.0/8".parse().unwrap()]
+    }
+
+    #[test]
+    fn headers_are_ignored_without_trusted_proxies() {
+        let client = resolve_client_ip(Some(ip("203.0.113.7")), &["1.2.3.4"], Some("5.6.7.8"), &[]);
+
+        assert_eq!(client, Some(ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn headers_from_untrusted_peer_are_ignored() {
+        let client = resolve_client_ip(
+            Some(ip("203.0.113.7")),
+            &["1.2.3.4"],
+            Some("5.6.7.8"),
+            &proxies(),
+        );
+
+        assert_eq!(client, Some(ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn rightmost_untrusted_hop_is_the_client() {
+        // The client sent `1.2.3.4` itself, the proxies appended the rest
+        let client = resolve_client_ip(
+            Some(ip("10.0.0.2")),
+            &["1.2.3.4", " 198.51.100.9", " 10.0.0.1"],
+            None,
+            &proxies(),
+        );
+
+        assert_eq!(client, Some(ip("198.51.100.9")));
+    }
+
+    #[test]
+    fn real_ip_is_used_without_forwarded_for() {
+        let client = resolve_client_ip(Some(ip("10.0.0.2")), &[], Some("198.51.100.9"), &proxies());
+
+        assert_eq!(client, Some(ip("198.51.100.9")));
+    }
+
+    #[test]
+    fn garbage_hop_keeps_the_last_trusted_one() {
+        let client = resolve_client_ip(
+            Some(ip("10.0.0.2")),
+            &["not-an-ip", "10.0.0.1"],
+            None,
+            &proxies(),
+        );
+
+        assert_eq!(client, Some(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn no_peer_means_no_ip() {
+        assert_eq!(
+            resolve_client_ip(None, &["1.2.3.4"], None, &proxies()),
+            None
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use crate::{
+    config::email::{EMAIL_CONFIG, whole_hours},
+    services::data_export_service::DATA_EXPORT_LINK_EXP_SECS,
+};
+
+const EXPIRY_HOURS: u64 = whole_hours(DATA_EXPORT_LINK_EXP_SECS);
+
+pub struct DataExportReady {
+    app_name: String,
+    username: String,
+    download_url: String,
+    expiry_hours: String,
+    support_email: String,
+    company_address: String,
+}
+
+impl DataExportReady {
+    pub fn new(username: &str, download_url: &str) -> Self {
+        Self {
+            app_name: EMAIL_CONFIG.app_name.clone(),
+            username: username.to_owned(),
+            download_url: download_url.to_owned(),
+            expiry_hours: EXPIRY_HOURS.to_string(),
+            support_email: EMAIL_CONFIG.support_email.clone(),
+            company_address: EMAIL_CONFIG.company_address.clone(),
+        }
+    }
+    pub fn as_array(&self) -> [(&str, &str); 6] {
+        [
+            ("app_name", &self.app_name),
+            ("username", &self.username),
+            ("download_url", &self.download_url),
+            ("expiry_hours", &self.expiry_hours),
+            ("support_email", &self.support_email),
+            ("company_address", &self.company_address),
+        ]
+    }
+}
+
+/// Values for the email sent when an export could not be completed
+pub struct DataExportFailed {
+    app_name: String,
+    username: String,
+    support_email: String,
+    company_address: String,
+}
+
+impl DataExportFailed {
+    pub fn new(username: &str) -> Self {
+        Self {
+            app_name: EMAIL_CONFIG.app_name.clone(),
+            username: username.to_owned(),
+            support_email: EMAIL_CONFIG.support_email.clone(),
+            company_address: EMAIL_CONFIG.company_address.clone(),
+        }
+    }
+    pub fn as_array(&self) -> [(&str, &str); 4] {
+        [
+            ("app_name", &self.app_name),
+            ("username", &self.username),
+            ("support_email", &self.support_email),
+            ("company_address", &self.company_address),
+        ]
+    }
+}
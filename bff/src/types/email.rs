@@ -1,4 +1,4 @@
-use std::env::var;
+use crate::config::email::EMAIL_CONFIG;
 
 #[allow(non_snake_case)]
 #[derive(Debug, serde::Serialize)]
@@ -16,15 +16,10 @@ struct Person {
 }
 
 impl Email {
-
     /// Create email object to be sent
-    /// 
+    ///
     /// recipients are vector of tuples (recipient_name, recipient_email)
-    pub fn new(
-        recipients: Vec<(&str, &str)>,
-        html: String,
-        subject: &str,
-    ) -> Self {
+    pub fn new(recipients: Vec<(&str, &str)>, html: String, subject: &str) -> Self {
         let mut recipients_vec: Vec<Person> = Vec::new();
 
         for rec in recipients.iter() {
@@ -36,8 +31,8 @@ impl Email {
 
         Self {
             sender: Person {
-                name: var("BREVO_SENDER_NAME").expect("BREVO_SENDER_NAME missing"),
-                email: var("BREVO_SENDER_EMAIL").expect("BREVO_SENDER_EMAIL missing"),
+                name: EMAIL_CONFIG.sender_name.clone(),
+                email: EMAIL_CONFIG.sender_email.clone(),
             },
             to: recipients_vec,
             htmlContent: html,
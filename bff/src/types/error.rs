@@ -1,10 +1,12 @@
 use axum::{
     Json,
-    http::StatusCode,
+    http::{StatusCode, header::RETRY_AFTER},
     response::{IntoResponse, Response},
 };
 use serde_json::json;
 
+use crate::types::validation::FieldError;
+
 #[derive(thiserror::Error, Debug)]
 pub enum CustomError {
     #[error("MongoDB error")]
@@ -34,7 +36,21 @@ pub enum CustomError {
     #[error("Reqwest error")]
     ReqwestError(#[from] reqwest::Error),
     #[error("Error sending email")]
-    SendEmailError
+    SendEmailError,
+    #[error("Too many requests, retry after {0} seconds")]
+    TooManyRequests(u64),
+    #[error("Account locked, retry after {0} seconds")]
+    AccountLocked(u64),
+    #[error("Forbidden")]
+    Forbidden,
+    #[error("Account disabled")]
+    AccountDisabled,
+    #[error("Identity provider error")]
+    OidcProviderError,
+    #[error("Archive error")]
+    ArchiveError,
+    #[error("Validation failed: {0:?}")]
+    ValidationError(Vec<FieldError>),
 }
 
 impl IntoResponse for CustomError {
@@ -79,12 +95,54 @@ impl IntoResponse for CustomError {
             ),
             CustomError::ReqwestError(_) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "Error sending request".to_owned()
+                "Error sending request".to_owned(),
             ),
             CustomError::SendEmailError => (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "Error sending email".to_owned()
-            )
+                "Error sending email".to_owned(),
+            ),
+            CustomError::Forbidden => {
+                (StatusCode::FORBIDDEN, "Insufficient permissions".to_owned())
+            }
+            CustomError::AccountDisabled => {
+                (StatusCode::FORBIDDEN, "Account is disabled".to_owned())
+            }
+            CustomError::OidcProviderError => (
+                StatusCode::BAD_GATEWAY,
+                "Identity provider error".to_owned(),
+            ),
+            CustomError::ArchiveError => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error building archive".to_owned(),
+            ),
+            CustomError::TooManyRequests(secs) => {
+                let body = Json(json!({
+                    "error": format!("Too many requests, retry after {} seconds", secs)
+                }));
+
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(RETRY_AFTER, secs.to_string())],
+                    body,
+                )
+                    .into_response();
+            }
+            CustomError::AccountLocked(secs) => {
+                let body = Json(json!({
+                    "error": format!("Account is temporarily locked, retry after {} seconds", secs)
+                }));
+
+                return (StatusCode::LOCKED, [(RETRY_AFTER, secs.to_string())], body)
+                    .into_response();
+            }
+            CustomError::ValidationError(fields) => {
+                let body = Json(json!({
+                    "error": "Validation failed",
+                    "fields": fields
+                }));
+
+                return (StatusCode::UNPROCESSABLE_ENTITY, body).into_response();
+            }
         };
 
         let body = Json(json!({
@@ -0,0 +1,60 @@
+use crate::{
+    config::email::{EMAIL_CONFIG, whole_minutes},
+    services::auth_service::MAGIC_LINK_EXP_SECS,
+};
+
+const EXPIRY_MINUTES: u64 = whole_minutes(MAGIC_LINK_EXP_SECS as u64);
+
+pub struct MagicLink {
+    app_name: String,
+    username: String,
+    login_url: String,
+    expiry_minutes: String,
+    support_email: String,
+    company_address: String,
+}
+
+impl MagicLink {
+    pub fn new(username: &str, user_id: &str, token: &str) -> Self {
+        Self {
+            app_name: EMAIL_CONFIG.app_name.clone(),
+            username: username.to_owned(),
+            login_url: format!(
+                "{}/magic_login?token={}&user_id={}",
+                EMAIL_CONFIG.frontend_url, token, user_id
+            ),
+            expiry_minutes: EXPIRY_MINUTES.to_string(),
+            support_email: EMAIL_CONFIG.support_email.clone(),
+            company_address: EMAIL_CONFIG.company_address.clone(),
+        }
+    }
+    pub fn as_array(&self) -> [(&str, &str); 6] {
+        [
+            ("app_name", &self.app_name),
+            ("username", &self.username),
+            ("login_url", &self.login_url),
+            ("expiry_minutes", &self.expiry_minutes),
+            ("support_email", &self.support_email),
+            ("company_address", &self.company_address),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::init_env;
+
+    #[test]
+    fn login_link_points_to_the_frontend() {
+        init_env();
+
+        let values = MagicLink::new("alice", "user-1", "raw-token");
+
+        assert!(values.as_array().contains(&(
+            "login_url",
+            "https://app.test/magic_login?token=raw-token&user_id=user-1"
+        )));
+        assert!(values.as_array().contains(&("expiry_minutes", "15")));
+    }
+}
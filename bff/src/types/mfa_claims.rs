@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+/// Claims of the short-lived token returned by login while the second factor is pending
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaClaims {
+    pub sub: String,
+    pub exp: usize,
+    /// Id of the token, which is consumed once the second factor is verified
+    pub jti: String,
+    pub aud: String,
+    pub iss: String,
+}
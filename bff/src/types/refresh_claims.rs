@@ -1,10 +1,13 @@
-use serde::{Serialize, Deserialize};
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RefreshClaims {
     pub sub: String,
     pub exp: usize,
     pub jti: String,
+    /// Id of the rotation chain this token belongs to
+    #[serde(default)]
+    pub fam: String,
     pub aud: String,
-    pub iss: String
-}
\ No newline at end of file
+    pub iss: String,
+}
@@ -0,0 +1,59 @@
+use crate::{
+    config::email::{EMAIL_CONFIG, whole_minutes},
+    services::auth_service::RESET_PASS_EXP_SECS,
+};
+
+const EXPIRY_MINUTES: u64 = whole_minutes(RESET_PASS_EXP_SECS as u64);
+
+pub struct ResetPassword {
+    app_name: String,
+    username: String,
+    reset_url: String,
+    expiry_minutes: String,
+    support_email: String,
+    company_address: String,
+}
+
+impl ResetPassword {
+    pub fn new(username: &str, token: &str) -> Self {
+        Self {
+            app_name: EMAIL_CONFIG.app_name.clone(),
+            username: username.to_owned(),
+            reset_url: format!(
+                "{}/reset_password?token={}",
+                EMAIL_CONFIG.frontend_url, token
+            ),
+            expiry_minutes: EXPIRY_MINUTES.to_string(),
+            support_email: EMAIL_CONFIG.support_email.clone(),
+            company_address: EMAIL_CONFIG.company_address.clone(),
+        }
+    }
+    pub fn as_array(&self) -> [(&str, &str); 6] {
+        [
+            ("app_name", &self.app_name),
+            ("username", &self.username),
+            ("reset_url", &self.reset_url),
+            ("expiry_minutes", &self.expiry_minutes),
+            ("support_email", &self.support_email),
+            ("company_address", &self.company_address),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::init_env;
+
+    #[test]
+    fn reset_link_points_to_the_frontend_with_the_token() {
+        init_env();
+
+        let values = ResetPassword::new("alice", "raw-token");
+
+        assert!(values.as_array().contains(&(
+            "reset_url",
+            "https://app.test/reset_password?token=raw-token"
+        )));
+    }
+}
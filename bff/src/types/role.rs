@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Moderator,
+    Admin,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    ReadUsers,
+    ManageUsers,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+
+    /// Position in the hierarchy, a higher role outranks the lower ones
+    pub fn rank(&self) -> u8 {
+        match self {
+            Role::Moderator => 1,
+            Role::Admin => 2,
+        }
+    }
+
+    /// Permissions every holder of the role gets
+    pub fn permissions(&self) -> &'static [Permission] {
+        match self {
+            Role::Moderator => &[Permission::ReadUsers],
+            Role::Admin => &[Permission::ReadUsers, Permission::ManageUsers],
+        }
+    }
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::ReadUsers => "read_users",
+            Permission::ManageUsers => "manage_users",
+        }
+    }
+}
+
+/// Rank of the highest of `roles`, users without a role rank lowest
+pub fn highest_rank(roles: &[Role]) -> u8 {
+    roles.iter().map(Role::rank).max().unwrap_or(0)
+}
+
+/// Permissions granted by the roles plus the ones granted directly, without duplicates
+pub fn effective_permissions(roles: &[Role], granted: &[Permission]) -> Vec<Permission> {
+    let mut permissions: Vec<Permission> = Vec::new();
+
+    for permission in roles
+        .iter()
+        .flat_map(|r| r.permissions())
+        .chain(granted.iter())
+    {
+        if !permissions.contains(permission) {
+            permissions.push(*permission);
+        }
+    }
+
+    permissions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_and_grants_are_merged_once() {
+        let permissions =
+            effective_permissions(&[Role::Moderator, Role::Admin], &[Permission::ReadUsers]);
+
+        assert_eq!(
+            permissions,
+            vec![Permission::ReadUsers, Permission::ManageUsers]
+        );
+    }
+
+    #[test]
+    fn highest_role_decides_the_rank() {
+        assert_eq!(highest_rank(&[]), 0);
+        assert!(highest_rank(&[Role::Moderator]) > highest_rank(&[]));
+        assert_eq!(
+            highest_rank(&[Role::Moderator, Role::Admin]),
+            highest_rank(&[Role::Admin])
+        );
+    }
+
+    #[test]
+    fn grants_apply_without_roles() {
+        assert_eq!(
+            effective_permissions(&[], &[Permission::ManageUsers]),
+            vec![Permission::ManageUsers]
+        );
+        assert!(effective_permissions(&[], &[]).is_empty());
+    }
+}
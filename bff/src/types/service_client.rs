@@ -0,0 +1,96 @@
+use crate::{config::service_clients::SERVICE_CLIENTS, types::error::CustomError};
+use aws_lc_rs::constant_time::verify_slices_are_equal;
+use axum::{RequestPartsExt, extract::FromRequestParts, http::request::Parts};
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Basic},
+};
+use sha2::{Digest, Sha256};
+
+/// An internal service authenticated with its client id and secret (HTTP Basic)
+#[derive(Debug, Clone)]
+pub struct ServiceClient {
+    pub client_id: String,
+}
+
+impl<S> FromRequestParts<S> for ServiceClient
+where
+    S: Send + Sync,
+{
+    type Rejection = CustomError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let TypedHeader(Authorization(basic)) = parts
+            .extract::<TypedHeader<Authorization<Basic>>>()
+            .await
+            .map_err(|_| CustomError::MissingCredentials)?;
+
+        let expected_hash = SERVICE_CLIENTS
+            .get(basic.username())
+            .ok_or(CustomError::WrongCredentials)?;
+
+        let secret_hash = hex::encode(Sha256::digest(basic.password().as_bytes()));
+
+        if verify_slices_are_equal(secret_hash.as_bytes(), expected_hash.as_bytes()).is_err() {
+            tracing::warn!("Wrong secret for service client {}", basic.username());
+            return Err(CustomError::WrongCredentials);
+        }
+
+        Ok(Self {
+            client_id: basic.username().to_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::init_env;
+    use axum::http::{Request, header::AUTHORIZATION};
+    use base64::{Engine, engine::general_purpose};
+
+    async fn client_with(authorization: Option<String>) -> Result<ServiceClient, CustomError> {
+        init_env();
+
+        let mut request = Request::builder();
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+        let (mut parts, _) = request.body(()).unwrap().into_parts();
+
+        ServiceClient::from_request_parts(&mut parts, &()).await
+    }
+
+    fn basic(client_id: &str, secret: &str) -> Option<String> {
+        let credentials = general_purpose::STANDARD.encode(format!("{}:{}", client_id, secret));
+
+        Some(format!("Basic {}", credentials))
+    }
+
+    #[tokio::test]
+    async fn configured_client_is_authenticated() {
+        let client = client_with(basic("post_service", "post-secret"))
+            .await
+            .unwrap();
+
+        assert_eq!(client.client_id, "post_service");
+    }
+
+    #[tokio::test]
+    async fn wrong_secret_or_unknown_client_is_rejected() {
+        for authorization in [
+            basic("post_service", "guess"),
+            basic("chat_service", "post-secret"),
+        ] {
+            assert!(matches!(
+                client_with(authorization).await,
+                Err(CustomError::WrongCredentials)
+            ));
+        }
+
+        assert!(matches!(
+            client_with(None).await,
+            Err(CustomError::MissingCredentials)
+        ));
+    }
+}
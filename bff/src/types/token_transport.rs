@@ -0,0 +1,291 @@
+use crate::{
+    dtos::auth_dto::AuthResDto, services::auth_service::REFRESH_EXP_SECS, types::error::CustomError,
+};
+use aws_lc_rs::constant_time::verify_slices_are_equal;
+use axum::{
+    extract::FromRequestParts,
+    http::{HeaderName, request::Parts},
+};
+use axum_extra::extract::{
+    CookieJar,
+    cookie::{Cookie, SameSite},
+};
+use rand::TryRngCore;
+use std::{env::var, sync::LazyLock};
+
+pub const REFRESH_COOKIE: &str = "refresh_token";
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: HeaderName = HeaderName::from_static("x-csrf-token");
+/// Clients opt into cookies with `X-Token-Transport: cookie`
+pub const TRANSPORT_HEADER: HeaderName = HeaderName::from_static("x-token-transport");
+
+/// `REFRESH_COOKIE_SAME_SITE=strict|lax|none`, strict by default.
+/// `none` is needed when the frontend is served from another site
+static COOKIE_SAME_SITE: LazyLock<SameSite> =
+    LazyLock::new(|| match var("REFRESH_COOKIE_SAME_SITE").as_deref() {
+        Ok("lax") => SameSite::Lax,
+        Ok("none") => SameSite::None,
+        Ok("strict") | Err(_) => SameSite::Strict,
+        Ok(other) => panic!(
+            "REFRESH_COOKIE_SAME_SITE must be strict, lax or none, got {}",
+            other
+        ),
+    });
+
+/// How the refresh token travels between us and the client.
+///
+/// Browsers get it in an HttpOnly cookie scoped to `/auth`, next to a CSRF token
+/// which has to be echoed in the `X-CSRF-Token` header (double submit).
+/// Other clients, e.g. mobile apps, keep sending it in the JSON body
+pub struct TokenTransport {
+    cookie_mode: bool,
+    jar: CookieJar,
+    csrf_header: Option<String>,
+}
+
+impl<S> FromRequestParts<S> for TokenTransport
+where
+    S: Send + Sync,
+{
+    type Rejection = CustomError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let header = |name: &HeaderName| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_owned())
+        };
+
+        Ok(Self {
+            cookie_mode: header(&TRANSPORT_HEADER)
+                .is_some_and(|v| v.eq_ignore_ascii_case("cookie")),
+            jar: CookieJar::from_headers(&parts.headers),
+            csrf_header: header(&CSRF_HEADER),
+        })
+    }
+}
+
+impl TokenTransport {
+    /// In cookie mode move the refresh token out of `tokens` into a cookie and
+    /// hand out a fresh CSRF token, otherwise return `tokens` as they are
+    pub fn deliver(self, mut tokens: AuthResDto) -> Result<(CookieJar, AuthResDto), CustomError> {
+        if !self.cookie_mode {
+            return Ok((self.jar, tokens));
+        }
+
+        let refresh_token = tokens.refresh_token.take().unwrap_or_default();
+        let csrf_token = new_csrf_token()?;
+
+        let jar = self
+            .jar
+            .add(build_cookie(REFRESH_COOKIE, refresh_token, "/auth", true))
+            .add(build_cookie(CSRF_COOKIE, csrf_token.clone(), "/", false));
+
+        tokens.csrf_token = Some(csrf_token);
+
+        Ok((jar, tokens))
+    }
+
+    /// The refresh token of the request. In cookie mode it's read from the cookie
+    /// and only accepted if the CSRF header matches the CSRF cookie
+    pub fn refresh_token(&self, body_token: Option<&str>) -> Result<String, CustomError> {
+        if !self.cookie_mode {
+            return body_token
+                .filter(|t| !t.is_empty())
+                .map(|t| t.to_owned())
+                .ok_or(CustomError::MissingCredentials);
+        }
+
+        let csrf_cookie = self
+            .jar
+            .get(CSRF_COOKIE)
+            .map(|c| c.value())
+            .ok_or(CustomError::Forbidden)?;
+        let csrf_header = self.csrf_header.as_deref().ok_or(CustomError::Forbidden)?;
+
+        if csrf_cookie.is_empty()
+            || verify_slices_are_equal(csrf_cookie.as_bytes(), csrf_header.as_bytes()).is_err()
+        {
+            tracing::warn!("CSRF token mismatch on a cookie authenticated request");
+            return Err(CustomError::Forbidden);
+        }
+
+        self.jar
+            .get(REFRESH_COOKIE)
+            .map(|c| c.value().to_owned())
+            .filter(|t| !t.is_empty())
+            .ok_or(CustomError::MissingCredentials)
+    }
+
+    /// Remove the cookies set by `deliver`, a no-op in body mode
+    pub fn clear(self) -> CookieJar {
+        if !self.cookie_mode {
+            return self.jar;
+        }
+
+        self.jar
+            .remove(build_cookie(REFRESH_COOKIE, String::new(), "/auth", true))
+            .remove(build_cookie(CSRF_COOKIE, String::new(), "/", false))
+    }
+}
+
+fn build_cookie(
+    name: &'static str,
+    value: String,
+    path: &'static str,
+    http_only: bool,
+) -> Cookie<'static> {
+    let max_age = time::Duration::seconds(REFRESH_EXP_SECS as i64);
+
+    secure_cookie(name, value, path, http_only, max_age)
+}
+
+/// Secure cookie with the configured SameSite policy and domain
+pub fn secure_cookie(
+    name: &'static str,
+    value: String,
+    path: &'static str,
+    http_only: bool,
+    max_age: time::Duration,
+) -> Cookie<'static> {
+    let mut cookie = Cookie::build((name, value))
+        .path(path)
+        .http_only(http_only)
+        .secure(true)
+        .same_site(*COOKIE_SAME_SITE)
+        .max_age(max_age);
+
+    if let Ok(domain) = var("COOKIE_DOMAIN") {
+        cookie = cookie.domain(domain);
+    }
+
+    cookie.build()
+}
+
+fn new_csrf_token() -> Result<String, CustomError> {
+    let mut bytes = [0u8; 32];
+    rand::rngs::OsRng
+        .try_fill_bytes(&mut bytes)
+        .map_err(|_| CustomError::TokenCreation)?;
+
+    Ok(hex::encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::transport_with;
+    use axum::http::header::COOKIE;
+
+    fn tokens() -> AuthResDto {
+        AuthResDto::new("access".to_owned(), "refresh".to_owned())
+    }
+
+    fn cookie_transport(
+        cookies: &[(&'static str, &str)],
+        csrf_header: Option<&str>,
+    ) -> TokenTransport {
+        let mut jar = CookieJar::new();
+        for (name, value) in cookies {
+            jar = jar.add(Cookie::new(*name, value.to_string()));
+        }
+
+        TokenTransport {
+            cookie_mode: true,
+            jar,
+            csrf_header: csrf_header.map(str::to_owned),
+        }
+    }
+
+    #[tokio::test]
+    async fn cookie_mode_is_opted_into_by_header() {
+        assert!(!transport_with(&[]).await.cookie_mode);
+        assert!(
+            transport_with(&[(TRANSPORT_HEADER, "Cookie")])
+                .await
+                .cookie_mode
+        );
+
+        let transport = transport_with(&[
+            (TRANSPORT_HEADER, "cookie"),
+            (COOKIE, "csrf_token=abc; refresh_token=refresh"),
+            (CSRF_HEADER, "abc"),
+        ])
+        .await;
+
+        assert_eq!(transport.refresh_token(None).unwrap(), "refresh");
+    }
+
+    #[tokio::test]
+    async fn body_mode_keeps_tokens_in_the_body() {
+        let transport = transport_with(&[]).await;
+
+        assert_eq!(transport.refresh_token(Some("refresh")).unwrap(), "refresh");
+        assert!(matches!(
+            transport.refresh_token(Some("")),
+            Err(CustomError::MissingCredentials)
+        ));
+
+        let (jar, tokens) = transport.deliver(tokens()).unwrap();
+
+        assert_eq!(tokens.refresh_token.as_deref(), Some("refresh"));
+        assert!(tokens.csrf_token.is_none());
+        assert_eq!(jar.iter().count(), 0);
+    }
+
+    #[test]
+    fn cookie_mode_moves_the_refresh_token_into_a_cookie() {
+        let (jar, tokens) = cookie_transport(&[], None).deliver(tokens()).unwrap();
+
+        let refresh = jar.get(REFRESH_COOKIE).unwrap();
+        let csrf = jar.get(CSRF_COOKIE).unwrap();
+
+        assert!(tokens.refresh_token.is_none());
+        assert_eq!(tokens.csrf_token.as_deref(), Some(csrf.value()));
+        assert_eq!(refresh.value(), "refresh");
+        assert_eq!(refresh.path(), Some("/auth"));
+        assert_eq!(refresh.http_only(), Some(true));
+        assert_eq!(refresh.secure(), Some(true));
+        assert_eq!(csrf.http_only(), Some(false));
+    }
+
+    #[test]
+    fn cookie_mode_needs_the_matching_csrf_header() {
+        let cookies = [(CSRF_COOKIE, "abc"), (REFRESH_COOKIE, "refresh")];
+
+        assert_eq!(
+            cookie_transport(&cookies, Some("abc"))
+                .refresh_token(Some("body"))
+                .unwrap(),
+            "refresh"
+        );
+
+        for transport in [
+            cookie_transport(&cookies, None),
+            cookie_transport(&cookies, Some("abd")),
+            cookie_transport(&[(REFRESH_COOKIE, "refresh")], Some("abc")),
+            cookie_transport(&[(CSRF_COOKIE, ""), (REFRESH_COOKIE, "refresh")], Some("")),
+        ] {
+            assert!(matches!(
+                transport.refresh_token(Some("body")),
+                Err(CustomError::Forbidden)
+            ));
+        }
+
+        assert!(matches!(
+            cookie_transport(&[(CSRF_COOKIE, "abc")], Some("abc")).refresh_token(Some("body")),
+            Err(CustomError::MissingCredentials)
+        ));
+    }
+
+    #[test]
+    fn clear_expires_the_cookies() {
+        let jar =
+            cookie_transport(&[(CSRF_COOKIE, "abc"), (REFRESH_COOKIE, "refresh")], None).clear();
+
+        assert!(jar.get(REFRESH_COOKIE).is_none());
+        assert!(jar.get(CSRF_COOKIE).is_none());
+    }
+}
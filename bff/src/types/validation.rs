@@ -0,0 +1,17 @@
+use serde::Serialize;
+
+/// Why the value of a single request field was rejected
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub reason: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, reason: &str) -> Self {
+        Self {
+            field: field.to_owned(),
+            reason: reason.to_owned(),
+        }
+    }
+}
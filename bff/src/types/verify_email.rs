@@ -1,6 +1,9 @@
-use std::env::var;
+use crate::{
+    config::email::{EMAIL_CONFIG, whole_minutes},
+    services::auth_service::EMAIL_VERIFICATION_EXP_SECS,
+};
 
-use crate::services::auth_service::EMAIL_VERIFICATION_EXP_MINUTES;
+const EXPIRY_MINUTES: u64 = whole_minutes(EMAIL_VERIFICATION_EXP_SECS as u64);
 
 pub struct VerifyEmail {
     app_name: String,
@@ -15,18 +18,16 @@ pub struct VerifyEmail {
 impl VerifyEmail {
     pub fn new(username: &str, user_id: &str, token: &str) -> Self {
         Self {
-            app_name: var("APP_NAME").expect("APP_NAME missing"),
+            app_name: EMAIL_CONFIG.app_name.clone(),
             username: username.to_owned(),
             verification_url: format!(
                 "{}/auth/verify_email?token={}&user_id={}",
-                var("DOMAIN").expect("DOMAIN missing"),
-                token,
-                user_id
+                EMAIL_CONFIG.domain, token, user_id
             ),
-            expiry_minutes: (EMAIL_VERIFICATION_EXP_MINUTES / 60).to_string(),
-            support_email: var("SUPPORT_EMAIL").expect("SUPPORT_EMAIL missing"),
-            company_address: var("COMPANY_ADDRESS").expect("COMPANY_ADDRESS missing"),
-            unsubscribe_url: format!("{}/unsubscribe", var("DOMAIN").expect("DOMAIN missing")),
+            expiry_minutes: EXPIRY_MINUTES.to_string(),
+            support_email: EMAIL_CONFIG.support_email.clone(),
+            company_address: EMAIL_CONFIG.company_address.clone(),
+            unsubscribe_url: format!("{}/unsubscribe", EMAIL_CONFIG.domain),
         }
     }
     pub fn as_array(&self) -> [(&str, &str); 7] {
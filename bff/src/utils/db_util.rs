@@ -1,27 +1,107 @@
-use bson::doc;
-use mongodb::{Database, IndexModel, error::Error, options::IndexOptions};
+use bson::{Document, doc, oid::ObjectId};
+use chrono::{Duration as ChronoDuration, Utc};
+use futures::TryStreamExt;
+use mongodb::{
+    Database, IndexModel,
+    error::{Error, ErrorKind, WriteFailure},
+    options::{Collation, CollationStrength, IndexOptions},
+};
 use std::time::Duration;
 
 use crate::models::{
+    account_purge::{ACCOUNT_PURGES_COLL, AccountPurge},
+    data_export::{DATA_EXPORTS_COLL, DataExport},
+    email_verif_token::{EMAIL_VERIF_TOKENS_COLL, EmailVerifToken},
+    linked_identity::{
+        LINKED_IDENTITIES_COLL, LinkedIdentity, OIDC_AUTH_REQUESTS_COLL, OidcAuthRequest,
+    },
+    login_attempt::{LOGIN_ATTEMPTS_COLL, LoginAttempt},
+    migration::{MIGRATIONS_COLL, Migration},
+    rate_limit::{RATE_LIMITS_COLL, RateLimitCounter},
     refresh_token::{REFRESH_TOKENS_COLL, RefreshToken},
+    reset_pass_token::{RESET_PASS_TOKENS_COLL, ResetPassToken},
+    revoked_access::{REVOKED_ACCESS_COLL, RevokedAccess},
+    security_event::{NewSecurityEvent, SECURITY_EVENTS_COLL, SecurityEvent, SecurityEventKind},
+    used_mfa_token::{USED_MFA_TOKENS_COLL, UsedMfaToken},
     user::{USERS_COLL, User},
-    email_verif_token::{EMAIL_VERIF_TOKENS_COLL, EmailVerifToken},
+    username_history::{NewUsernameHistory, USERNAME_HISTORY_COLL, UsernameHistory},
+    webauthn_credential::{
+        WEBAUTHN_CHALLENGES_COLL, WEBAUTHN_CREDENTIALS_COLL, WebauthnChallenge, WebauthnCredential,
+    },
 };
+use crate::services::username_history_service::USERNAME_HOLD_DAYS;
+use crate::utils::email::normalize_email;
 
 const DATA_REMOVAL_AFTER_SECS: u64 = 30 * 24 * 3600;
+const LOGIN_ATTEMPT_REMOVAL_AFTER_SECS: u64 = 24 * 3600;
+const NAMESPACE_NOT_FOUND: i32 = 26;
+const INDEX_NOT_FOUND: i32 = 27;
+const DUPLICATE_KEY: i32 = 11000;
+
+/// Collation of the username index, queries on usernames need it to use the index
+pub fn username_collation() -> Collation {
+    Collation::builder()
+        .locale("en")
+        .strength(CollationStrength::Secondary)
+        .build()
+}
 
 pub async fn ensure_indexes(db: &Database) -> Result<(), Error> {
+    // each migration runs once, by whichever instance records it first
+    if claim_migration(db, "lowercase_usernames").await? {
+        let result = lowercase_usernames(db).await;
+        finish_migration(db, "lowercase_usernames", result).await?;
+    }
+
+    if claim_migration(db, "lowercase_emails").await? {
+        let result = lowercase_emails(db).await;
+        finish_migration(db, "lowercase_emails", result).await?;
+    }
+
     let users = db.collection::<User>(USERS_COLL);
 
+    // the plain unique username index is covered by the case-insensitive one
+    if claim_migration(db, "drop_username_1").await? {
+        let result = match users.drop_index("username_1").await {
+            Err(err) => match err.kind.as_ref() {
+                ErrorKind::Command(c)
+                    if [INDEX_NOT_FOUND, NAMESPACE_NOT_FOUND].contains(&c.code) =>
+                {
+                    Ok(())
+                }
+                _ => Err(err),
+            },
+            Ok(_) => Ok(()),
+        };
+        finish_migration(db, "drop_username_1", result).await?;
+    }
+
     let user_indexes = vec![
+        // usernames differing only in case are the same username
         IndexModel::builder()
             .keys(doc! { "username": 1})
-            .options(IndexOptions::builder().unique(true).build())
+            .options(
+                IndexOptions::builder()
+                    .unique(true)
+                    .name("username_case_insensitive".to_owned())
+                    .collation(username_collation())
+                    .build(),
+            )
             .build(),
         IndexModel::builder()
             .keys(doc! { "email": 1})
             .options(IndexOptions::builder().unique(true).build())
             .build(),
+        // deleted accounts are purged once their grace period is over, the purge
+        // job removes the rest of their documents
+        IndexModel::builder()
+            .keys(doc! { "purgeAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(Some(Duration::from_secs(0)))
+                    .build(),
+            )
+            .build(),
     ];
 
     users.create_indexes(user_indexes).await?;
@@ -42,6 +122,8 @@ pub async fn ensure_indexes(db: &Database) -> Result<(), Error> {
                     .build(),
             )
             .build(),
+        IndexModel::builder().keys(doc! { "familyId": 1 }).build(),
+        IndexModel::builder().keys(doc! { "userId": 1 }).build(),
     ];
     // insert the indexes for refresh tokens
     refresh_tokens.create_indexes(refresh_token_indexes).await?;
@@ -68,11 +150,605 @@ pub async fn ensure_indexes(db: &Database) -> Result<(), Error> {
                 "userId": 1,
             })
             .build(),
+        IndexModel::builder()
+            .keys(doc! {
+                "userId": 1,
+                "createdAt": -1,
+            })
+            .build(),
     ];
 
     email_verfication_tokens
         .create_indexes(email_verif_indexes)
         .await?;
 
+    // and for reset password tokens
+    let reset_pass_tokens = db.collection::<ResetPassToken>(RESET_PASS_TOKENS_COLL);
+
+    let reset_pass_indexes = vec![
+        IndexModel::builder()
+            .keys(doc! { "tokenHash": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder()
+            .keys(doc! { "createdAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(Some(Duration::from_secs(DATA_REMOVAL_AFTER_SECS)))
+                    .build(),
+            )
+            .build(),
+        IndexModel::builder().keys(doc! { "userId": 1 }).build(),
+    ];
+
+    reset_pass_tokens.create_indexes(reset_pass_indexes).await?;
+
+    let security_events = db.collection::<SecurityEvent>(SECURITY_EVENTS_COLL);
+
+    let security_event_indexes = vec![
+        IndexModel::builder()
+            .keys(doc! { "userId": 1, "createdAt": -1 })
+            .build(),
+    ];
+
+    security_events
+        .create_indexes(security_event_indexes)
+        .await?;
+
+    // failed logins are forgotten a day after the last one
+    let login_attempts = db.collection::<LoginAttempt>(LOGIN_ATTEMPTS_COLL);
+
+    let login_attempt_indexes = vec![
+        IndexModel::builder()
+            .keys(doc! { "key": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder()
+            .keys(doc! { "updatedAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(Some(Duration::from_secs(LOGIN_ATTEMPT_REMOVAL_AFTER_SECS)))
+                    .build(),
+            )
+            .build(),
+    ];
+
+    login_attempts.create_indexes(login_attempt_indexes).await?;
+
+    // rate limit counters are removed as soon as their window is over
+    let rate_limits = db.collection::<RateLimitCounter>(RATE_LIMITS_COLL);
+
+    let rate_limit_indexes = vec![
+        IndexModel::builder()
+            .keys(doc! { "key": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder()
+            .keys(doc! { "expiresAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(Some(Duration::from_secs(0)))
+                    .build(),
+            )
+            .build(),
+    ];
+
+    rate_limits.create_indexes(rate_limit_indexes).await?;
+
+    let webauthn_credentials = db.collection::<WebauthnCredential>(WEBAUTHN_CREDENTIALS_COLL);
+
+    let webauthn_credential_indexes = vec![
+        IndexModel::builder()
+            .keys(doc! { "credentialId": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder().keys(doc! { "userId": 1 }).build(),
+    ];
+
+    webauthn_credentials
+        .create_indexes(webauthn_credential_indexes)
+        .await?;
+
+    // pending passkey challenges are removed once expired
+    let webauthn_challenges = db.collection::<WebauthnChallenge>(WEBAUTHN_CHALLENGES_COLL);
+
+    let webauthn_challenge_indexes = vec![
+        IndexModel::builder()
+            .keys(doc! { "challenge": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder()
+            .keys(doc! { "expiresAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(Some(Duration::from_secs(0)))
+                    .build(),
+            )
+            .build(),
+    ];
+
+    webauthn_challenges
+        .create_indexes(webauthn_challenge_indexes)
+        .await?;
+
+    let linked_identities = db.collection::<LinkedIdentity>(LINKED_IDENTITIES_COLL);
+
+    let linked_identity_indexes = vec![
+        IndexModel::builder()
+            .keys(doc! { "provider": 1, "subject": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder().keys(doc! { "userId": 1 }).build(),
+    ];
+
+    linked_identities
+        .create_indexes(linked_identity_indexes)
+        .await?;
+
+    // pending OIDC logins are removed once expired
+    let oidc_auth_requests = db.collection::<OidcAuthRequest>(OIDC_AUTH_REQUESTS_COLL);
+
+    let oidc_auth_request_indexes = vec![
+        IndexModel::builder()
+            .keys(doc! { "state": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder()
+            .keys(doc! { "expiresAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(Some(Duration::from_secs(0)))
+                    .build(),
+            )
+            .build(),
+    ];
+
+    oidc_auth_requests
+        .create_indexes(oidc_auth_request_indexes)
+        .await?;
+
+    let data_exports = db.collection::<DataExport>(DATA_EXPORTS_COLL);
+
+    let data_export_indexes = vec![
+        IndexModel::builder()
+            .keys(doc! { "userId": 1, "createdAt": -1 })
+            .build(),
+        // archives are deleted once the download link ran out
+        IndexModel::builder()
+            .keys(doc! { "status": 1, "completedAt": 1 })
+            .build(),
+    ];
+
+    data_exports.create_indexes(data_export_indexes).await?;
+
+    let username_history = db.collection::<UsernameHistory>(USERNAME_HISTORY_COLL);
+
+    let username_history_indexes = vec![
+        IndexModel::builder()
+            .keys(doc! { "username": 1, "heldUntil": -1 })
+            .build(),
+        IndexModel::builder()
+            .keys(doc! { "userId": 1, "changedAt": -1 })
+            .build(),
+    ];
+
+    username_history
+        .create_indexes(username_history_indexes)
+        .await?;
+
+    let revoked_access = db.collection::<RevokedAccess>(REVOKED_ACCESS_COLL);
+
+    // entries are dropped once the tokens they match have expired
+    let revoked_access_indexes = vec![
+        IndexModel::builder()
+            .keys(doc! { "key": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder()
+            .keys(doc! { "expiresAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(Some(Duration::from_secs(0)))
+                    .build(),
+            )
+            .build(),
+    ];
+
+    revoked_access
+        .create_indexes(revoked_access_indexes)
+        .await?;
+
+    let account_purges = db.collection::<AccountPurge>(ACCOUNT_PURGES_COLL);
+
+    let account_purge_indexes = vec![
+        IndexModel::builder()
+            .keys(doc! { "userId": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder()
+            .keys(doc! { "nextAttemptAt": 1 })
+            .build(),
+    ];
+
+    account_purges.create_indexes(account_purge_indexes).await?;
+
+    let used_mfa_tokens = db.collection::<UsedMfaToken>(USED_MFA_TOKENS_COLL);
+
+    // a used token only has to be remembered until it expires
+    let used_mfa_token_indexes = vec![
+        IndexModel::builder()
+            .keys(doc! { "jti": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder()
+            .keys(doc! { "expiresAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(Some(Duration::from_secs(0)))
+                    .build(),
+            )
+            .build(),
+    ];
+
+    used_mfa_tokens
+        .create_indexes(used_mfa_token_indexes)
+        .await?;
+
     Ok(())
 }
+
+/// Record that the migration `id` is starting, returns false if it already completed.
+/// A migration which was started but never completed is either running on another
+/// instance or was interrupted, which needs a look before starting again
+async fn claim_migration(db: &Database, id: &str) -> Result<bool, Error> {
+    let migrations = db.collection::<Migration>(MIGRATIONS_COLL);
+
+    let claimed = migrations
+        .insert_one(Migration {
+            id: id.to_owned(),
+            startedAt: Utc::now(),
+            completedAt: None,
+        })
+        .await;
+
+    let err = match claimed {
+        Ok(_) => {
+            tracing::info!("Running migration {}", id);
+            return Ok(true);
+        }
+        Err(err) => err,
+    };
+
+    match err.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(w)) if w.code == DUPLICATE_KEY => {}
+        _ => return Err(err),
+    }
+
+    match migrations.find_one(doc! { "_id": id }).await? {
+        Some(Migration {
+            completedAt: Some(_),
+            ..
+        }) => Ok(false),
+        Some(Migration { startedAt, .. }) => {
+            let message = format!(
+                "Migration {} was started at {} and hasn't completed. Another instance may be \
+                 running it, otherwise it was interrupted: check the data, then delete its \
+                 record from the {} collection to run it again",
+                id, startedAt, MIGRATIONS_COLL
+            );
+            tracing::error!("{}", message);
+            Err(Error::custom(message))
+        }
+        // released by a failed run in the meantime
+        None => Box::pin(claim_migration(db, id)).await,
+    }
+}
+
+/// Mark the claimed migration `id` as completed, or release it when it failed
+/// so the next start runs it again
+async fn finish_migration(db: &Database, id: &str, result: Result<(), Error>) -> Result<(), Error> {
+    let migrations = db.collection::<Migration>(MIGRATIONS_COLL);
+
+    if let Err(err) = result {
+        tracing::error!("Migration {} failed: {:?}", id, err);
+        migrations.delete_one(doc! { "_id": id }).await?;
+        return Err(err);
+    }
+
+    migrations
+        .update_one(
+            doc! { "_id": id },
+            doc! { "$set": { "completedAt": Utc::now() } },
+        )
+        .await?;
+
+    tracing::info!("Migration {} has completed", id);
+
+    Ok(())
+}
+
+/// Usernames are stored lowercase since they are normalized, older accounts may
+/// still have capitals. They are lowercased, or renamed by `fallback_username`
+/// when the lowercase name belongs to someone else already. Each rename is kept in
+/// the username history and the security events of the user, like a change by the user
+async fn lowercase_usernames(db: &Database) -> Result<(), Error> {
+    let users = db.collection::<Document>(USERS_COLL);
+    let username_history = db.collection::<NewUsernameHistory>(USERNAME_HISTORY_COLL);
+    let security_events = db.collection::<NewSecurityEvent>(SECURITY_EVENTS_COLL);
+
+    let mixed_case: Vec<Document> = users
+        .find(doc! { "username": { "$regex": "[A-Z]" } })
+        .projection(doc! { "username": 1 })
+        .await?
+        .try_collect()
+        .await?;
+
+    for user in mixed_case {
+        let (Ok(id), Ok(username)) = (user.get_object_id("_id"), user.get_str("username")) else {
+            continue;
+        };
+
+        let lowercase = username.to_lowercase();
+
+        let renamed = match users
+            .update_one(
+                doc! { "_id": id },
+                doc! { "$set": { "username": &lowercase } },
+            )
+            .await
+        {
+            Ok(_) => lowercase,
+            Err(err) => match err.kind.as_ref() {
+                ErrorKind::Write(WriteFailure::WriteError(w)) if w.code == DUPLICATE_KEY => {
+                    let fallback = fallback_username(&id);
+
+                    users
+                        .update_one(
+                            doc! { "_id": id },
+                            doc! { "$set": { "username": &fallback } },
+                        )
+                        .await?;
+
+                    fallback
+                }
+                _ => return Err(err),
+            },
+        };
+
+        let now = Utc::now();
+
+        username_history
+            .insert_one(&NewUsernameHistory {
+                userId: id,
+                username: username.to_lowercase(),
+                newUsername: renamed.clone(),
+                changedAt: now,
+                heldUntil: now + ChronoDuration::days(USERNAME_HOLD_DAYS),
+            })
+            .await?;
+
+        security_events
+            .insert_one(&NewSecurityEvent {
+                userId: id,
+                kind: SecurityEventKind::UsernameChanged,
+                detail: format!(
+                    "Username {} was renamed to {} by the migration",
+                    username, renamed
+                ),
+                createdAt: now,
+            })
+            .await?;
+
+        tracing::warn!("Renamed username {} of {} to {}", username, id, renamed);
+    }
+
+    // old usernames are looked up by their normalized form
+    db.collection::<Document>(USERNAME_HISTORY_COLL)
+        .update_many(
+            doc! { "username": { "$regex": "[A-Z]" } },
+            vec![doc! { "$set": { "username": { "$toLower": "$username" } } }],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Emails are looked up lowercase, older accounts may have been stored with
+/// capitals and couldn't be found by their email. An address which is already
+/// taken in lowercase by another account is left as it is
+async fn lowercase_emails(db: &Database) -> Result<(), Error> {
+    let users = db.collection::<Document>(USERS_COLL);
+
+    let mixed_case: Vec<Document> = users
+        .find(doc! { "email": { "$regex": "[A-Z]" } })
+        .projection(doc! { "email": 1 })
+        .await?
+        .try_collect()
+        .await?;
+
+    for user in mixed_case {
+        let (Ok(id), Ok(email)) = (user.get_object_id("_id"), user.get_str("email")) else {
+            continue;
+        };
+
+        match users
+            .update_one(
+                doc! { "_id": id },
+                doc! { "$set": { "email": normalize_email(email) } },
+            )
+            .await
+        {
+            Ok(_) => {}
+            Err(err) => match err.kind.as_ref() {
+                ErrorKind::Write(WriteFailure::WriteError(w)) if w.code == DUPLICATE_KEY => {
+                    tracing::warn!("Email {} of {} is taken in lowercase, kept it", email, id);
+                }
+                _ => return Err(err),
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Username for an account whose lowercased username is already taken
+fn fallback_username(user_id: &ObjectId) -> String {
+    format!("user-{}", user_id.to_hex())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_support::test_db, utils::username::normalize_username};
+
+    #[test]
+    fn fallback_username_is_a_valid_username() {
+        let fallback = fallback_username(&ObjectId::new());
+
+        assert_eq!(
+            normalize_username(&fallback).as_deref(),
+            Ok(fallback.as_str())
+        );
+    }
+
+    /// `test_db` already ran the migrations on the empty database
+    async fn forget_migrations(db: &Database) {
+        db.collection::<Migration>(MIGRATIONS_COLL)
+            .delete_many(doc! {})
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn migrations_run_once() {
+        let db = test_db().await;
+        let users = db.collection::<Document>(USERS_COLL);
+        users
+            .insert_one(doc! { "username": "alice", "email": "Alice@Example.com" })
+            .await
+            .unwrap();
+
+        // recorded as completed by `test_db`, so the email isn't touched again
+        ensure_indexes(&db).await.unwrap();
+
+        let user = users.find_one(doc! {}).await.unwrap().unwrap();
+        assert_eq!(user.get_str("email").unwrap(), "Alice@Example.com");
+        let completed = db
+            .collection::<Migration>(MIGRATIONS_COLL)
+            .count_documents(doc! { "completedAt": { "$exists": true } })
+            .await
+            .unwrap();
+        assert_eq!(completed, 3);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn migration_started_elsewhere_stops_the_startup() {
+        let db = test_db().await;
+        db.collection::<Migration>(MIGRATIONS_COLL)
+            .update_one(
+                doc! { "_id": "lowercase_emails" },
+                doc! { "$unset": { "completedAt": "" } },
+            )
+            .await
+            .unwrap();
+
+        let err = ensure_indexes(&db).await.unwrap_err();
+
+        assert!(err.to_string().contains("lowercase_emails"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn mixed_case_usernames_are_renamed_on_the_record() {
+        // a database from before usernames were normalized
+        let db = test_db().await;
+        forget_migrations(&db).await;
+        let users = db.collection::<Document>(USERS_COLL);
+        users.drop_index("username_case_insensitive").await.unwrap();
+        users
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! { "username": 1 })
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+            )
+            .await
+            .unwrap();
+        let bob = ObjectId::new();
+        let taken = ObjectId::new();
+        users
+            .insert_many([
+                doc! { "_id": bob, "username": "Bob", "email": "bob@example.com" },
+                doc! { "_id": taken, "username": "Alice", "email": "alice@example.com" },
+                doc! { "username": "alice", "email": "alice@example.org" },
+            ])
+            .await
+            .unwrap();
+
+        ensure_indexes(&db).await.unwrap();
+
+        let username_of = |id: ObjectId| {
+            let users = users.clone();
+            async move {
+                let user = users.find_one(doc! { "_id": id }).await.unwrap().unwrap();
+                user.get_str("username").unwrap().to_owned()
+            }
+        };
+        assert_eq!(username_of(bob).await, "bob");
+        assert_eq!(username_of(taken).await, fallback_username(&taken));
+
+        for (id, new_username) in [(bob, "bob".to_owned()), (taken, fallback_username(&taken))] {
+            let history = db
+                .collection::<UsernameHistory>(USERNAME_HISTORY_COLL)
+                .find_one(doc! { "userId": id })
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(history.newUsername, new_username);
+
+            let event = db
+                .collection::<SecurityEvent>(SECURITY_EVENTS_COLL)
+                .find_one(doc! { "userId": id })
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(event.kind, SecurityEventKind::UsernameChanged);
+        }
+
+        let indexes = users.list_index_names().await.unwrap();
+        assert!(!indexes.contains(&"username_1".to_owned()));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at TEST_MONGODB_URI"]
+    async fn mixed_case_emails_are_lowercased() {
+        // a database from before emails were normalized
+        let db = test_db().await;
+        forget_migrations(&db).await;
+        let users = db.collection::<Document>(USERS_COLL);
+        let alice = ObjectId::new();
+        let duplicate = ObjectId::new();
+        users
+            .insert_many([
+                doc! { "_id": alice, "username": "alice", "email": "Alice@Example.com" },
+                doc! { "_id": duplicate, "username": "bob", "email": "Bob@Example.com" },
+                doc! { "username": "bobby", "email": "bob@example.com" },
+            ])
+            .await
+            .unwrap();
+
+        ensure_indexes(&db).await.unwrap();
+
+        let email_of = |id: ObjectId| {
+            let users = users.clone();
+            async move {
+                let user = users.find_one(doc! { "_id": id }).await.unwrap().unwrap();
+                user.get_str("email").unwrap().to_owned()
+            }
+        };
+        assert_eq!(email_of(alice).await, "alice@example.com");
+        assert_eq!(email_of(duplicate).await, "Bob@Example.com");
+    }
+}
@@ -0,0 +1,5 @@
+/// Emails are stored and looked up trimmed and lowercase, whichever way they
+/// reached us: typed by the user or claimed by an OIDC provider
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
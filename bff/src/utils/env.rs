@@ -0,0 +1,13 @@
+use std::{env::var, str::FromStr};
+
+/// Parse the environment variable `name`, falling back to `default` when unset.
+/// Panics on a value that doesn't parse, so misconfiguration fails at startup
+pub fn parse_var<T: FromStr>(name: &str, default: T) -> T {
+    match var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .unwrap_or_else(|_| panic!("{} is not a valid number", name)),
+        Err(_) => default,
+    }
+}
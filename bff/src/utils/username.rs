@@ -0,0 +1,61 @@
+pub const USERNAME_MIN_LEN: usize = 5;
+pub const USERNAME_MAX_LEN: usize = 30;
+
+/// Names which could be mistaken for the service itself or clash with routes
+const RESERVED_USERNAMES: [&str; 24] = [
+    "admin",
+    "administrator",
+    "root",
+    "system",
+    "support",
+    "help",
+    "security",
+    "staff",
+    "moderator",
+    "official",
+    "api",
+    "auth",
+    "login",
+    "logout",
+    "register",
+    "settings",
+    "account",
+    "profile",
+    "users",
+    "sessions",
+    "null",
+    "undefined",
+    "noreply",
+    "postmaster",
+];
+
+/// Trim and lowercase `username`, then check the length, the characters
+/// (`a-z`, `0-9`, `_`, `.`, `-`) and the reserved names.
+/// Returns the normalized username or the reason it's rejected
+pub fn normalize_username(username: &str) -> Result<String, String> {
+    let username = username.trim().to_lowercase();
+
+    if !(USERNAME_MIN_LEN..=USERNAME_MAX_LEN).contains(&username.len()) {
+        return Err(format!(
+            "must be between {} and {} characters",
+            USERNAME_MIN_LEN, USERNAME_MAX_LEN
+        ));
+    }
+
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '.' | '-'))
+    {
+        return Err("may only contain letters, digits, _, . and -".to_owned());
+    }
+
+    if !username.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return Err("must start with a letter or digit".to_owned());
+    }
+
+    if RESERVED_USERNAMES.contains(&username.as_str()) || username.starts_with("deleted-") {
+        return Err("is reserved".to_owned());
+    }
+
+    Ok(username)
+}